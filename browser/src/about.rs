@@ -0,0 +1,110 @@
+//! The built-in `about:` pages, and the pages shown when loading fails, which are
+//! generated as HTML and rendered like any other page.
+
+use octo_http::cache::Cache;
+use octo_http::request::Timeout;
+use octo_url::{Url, WebUrl};
+
+use crate::engine::Settings;
+use crate::lex::escape_html;
+
+fn page(title: &str, lines: &[String]) -> String {
+    format!("<big><b>{title}</b></big><br><br>{}", lines.join("<br>"))
+}
+
+pub(crate) fn history(history: &[Url]) -> String {
+    let lines = history
+        .iter()
+        .enumerate()
+        .map(|(i, url)| format!("{}. {}", i + 1, escape_html(&url.to_display_string())))
+        .collect::<Vec<_>>();
+    if lines.is_empty() {
+        return page("History", &["No pages visited yet.".to_string()]);
+    }
+    page("History", &lines)
+}
+
+pub(crate) fn cache(cache: &Cache) -> String {
+    let stats = cache.stats();
+    let summary = format!(
+        "{} responses, {} of {} KiB; {} hits, {} misses, {} evictions",
+        stats.entries,
+        stats.size / 1024,
+        stats.max_size / 1024,
+        stats.hits,
+        stats.misses,
+        stats.evictions
+    );
+    let mut entries = cache.into_iter().collect::<Vec<_>>();
+    if entries.is_empty() {
+        return page("Cache", &[summary, "The cache is empty.".to_string()]);
+    }
+    entries.sort_by_key(|entry| {
+        (
+            entry.site.to_string(),
+            entry.url.to_string(),
+            entry.method.to_string(),
+        )
+    });
+
+    let lines = std::iter::once(summary)
+        .chain(entries.iter().map(|entry| {
+            format!(
+                "<b>{} {}</b><br>for {}, status {}, age {}s, max-age {}s ({})",
+                entry.method,
+                escape_html(&entry.url.to_string()),
+                escape_html(&entry.site.to_string()),
+                entry.response.status_code(),
+                entry.age.num_seconds(),
+                entry.max_age.num_seconds(),
+                if entry.is_fresh() { "fresh" } else { "stale" }
+            )
+        }))
+        .collect::<Vec<_>>();
+    page("Cache", &lines)
+}
+
+pub(crate) fn version() -> String {
+    let lines = [
+        format!("octo-browser {}", env!("CARGO_PKG_VERSION")),
+        format!("octo-http {}", octo_http::VERSION),
+        format!("octo-url {}", octo_url::VERSION),
+        format!("TLS root certificates: {}", octo_http::tls_root_count()),
+    ];
+    page("Version", &lines)
+}
+
+pub(crate) fn config(settings: &Settings) -> String {
+    let lines = [
+        format!("max_redirects: {}", settings.max_redirects),
+        format!("connect_timeout: {}s", settings.connect_timeout.as_secs()),
+        format!("read_timeout: {}s", settings.read_timeout.as_secs()),
+        format!("timeout: {}s", settings.timeout.as_secs()),
+        format!("keep_alive: {}", settings.keep_alive),
+        format!("compression: {}", settings.compression),
+        format!("cache: {}", settings.cache),
+        format!("cache_size: {} KiB", settings.cache_size / 1024),
+        format!(
+            "cache_directory: {}",
+            settings
+                .cache_directory
+                .as_ref()
+                .map_or("none".to_string(), |directory| escape_html(
+                    &directory.display().to_string()
+                ))
+        ),
+    ];
+    page("Config", &lines)
+}
+
+/// The page shown instead of `url` when its server took too long to respond.
+pub(crate) fn timed_out(url: &WebUrl, timeout: &Timeout) -> String {
+    let lines = [
+        format!(
+            "{} took too long to respond.",
+            escape_html(&url.to_string())
+        ),
+        format!("Details: {}.", escape_html(&timeout.to_string())),
+    ];
+    page("Server took too long", &lines)
+}
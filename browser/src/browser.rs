@@ -3,13 +3,15 @@ use thiserror::Error;
 use eframe::egui::{Context, Visuals};
 use eframe::{egui, Frame};
 
-use crate::engine::{Engine, EngineError};
-use crate::layout::{Layout, ProcessedToken, TokenProcessor, PADDING};
+use crate::engine::{Engine, EngineError, Settings};
+use crate::layout::{DisplayListItem, Layout, ProcessedToken, TokenProcessor, PADDING};
 use crate::lex::lex;
+use octo_url::Url;
 
 const EMPTY_BODY_TEXT: &str = "The response body was empty.";
 const SCROLL_STEP: f32 = 100.;
 
+#[allow(dead_code)]
 #[derive(Error, Debug)]
 pub enum BrowserError {
     #[error("Engine error: {0}")]
@@ -33,21 +35,7 @@ impl eframe::App for Browser {
 
             let response = ui.add(egui::TextEdit::singleline(&mut self.url));
             if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
-                self.scroll = 0.;
-                match self.engine.load(&self.url) {
-                    Ok(Some(tokens)) => {
-                        self.processed_tokens =
-                            TokenProcessor::from_tokens(tokens).processed_tokens;
-                    }
-                    Ok(None) => {
-                        self.processed_tokens =
-                            TokenProcessor::from_tokens(lex(EMPTY_BODY_TEXT, true))
-                                .processed_tokens;
-                    }
-                    Err(error) => {
-                        ui.label(error.to_string());
-                    }
-                }
+                self.load(ui);
             }
 
             let display_list = Layout::display_list(&self.processed_tokens, ui);
@@ -65,7 +53,7 @@ impl eframe::App for Browser {
             // is larger than the max_y we get this way.
             let max_scroll = display_list
                 .iter()
-                .map(|item| item.pos.y + item.galley.rect.height() - ui.min_rect().height())
+                .map(|item| item.pos.y + item.content.size().y - ui.min_rect().height())
                 .reduce(f32::max)
                 .map(|max_y| f32::max(max_y + top_margin, 0.))
                 .unwrap_or(ui.min_rect().bottom());
@@ -85,23 +73,95 @@ impl eframe::App for Browser {
                 self.scroll = (self.scroll - i.smooth_scroll_delta.y).clamp(0., max_scroll)
             });
 
-            // Account for the address bar;
-            for item in display_list {
-                let pos = egui::Pos2::new(item.pos.x, item.pos.y - self.scroll + top_margin);
-                if pos.y < top_margin || pos.y > ui.min_rect().bottom() {
-                    continue;
-                }
-                ui.painter().galley(pos, item.galley, Default::default());
+            if let Some(link) = self.paint(ui, &display_list, top_margin) {
+                self.follow_link(&link, ui);
             }
         });
     }
 }
 
+impl Browser {
+    /// Loads the URL in the address bar and shows the page (or the error).
+    fn load(&mut self, ui: &mut egui::Ui) {
+        self.scroll = 0.;
+        match self.engine.load(&self.url) {
+            Ok(Some(tokens)) => {
+                self.processed_tokens =
+                    TokenProcessor::from_tokens(tokens, ui.ctx()).processed_tokens;
+            }
+            Ok(None) => {
+                self.processed_tokens =
+                    TokenProcessor::from_tokens(lex(EMPTY_BODY_TEXT, true), ui.ctx())
+                        .processed_tokens;
+            }
+            Err(error) => {
+                ui.label(error.to_string());
+            }
+        }
+
+        // Show the canonical form of the URL, with internationalized domain names
+        // in their readable form, even if they were typed (or pasted) as Punycode.
+        if let Ok(url) = self.url.parse::<Url>() {
+            self.url = url.to_display_string();
+        }
+    }
+
+    /// Loads the page `href` points to, resolving it against the current page's URL.
+    fn follow_link(&mut self, href: &str, ui: &mut egui::Ui) {
+        let url = match self.engine.current_url() {
+            Some(base) => base.join(href),
+            None => href.parse::<Url>(),
+        };
+        match url {
+            Ok(url) => {
+                self.url = url.to_string();
+                self.load(ui);
+            }
+            Err(error) => {
+                ui.label(error.to_string());
+            }
+        }
+    }
+
+    /// Paints the visible part of the page, and returns the link that was clicked, if any.
+    fn paint(
+        &self,
+        ui: &egui::Ui,
+        display_list: &[DisplayListItem],
+        top_margin: f32,
+    ) -> Option<String> {
+        let (pointer, clicked) = ui.input(|i| (i.pointer.hover_pos(), i.pointer.primary_clicked()));
+        let mut clicked_link = None;
+
+        for item in display_list {
+            // Account for the address bar.
+            let pos = egui::Pos2::new(item.pos.x, item.pos.y - self.scroll + top_margin);
+            if pos.y < top_margin || pos.y > ui.min_rect().bottom() {
+                continue;
+            }
+            item.paint(ui.painter(), pos);
+
+            let rect = egui::Rect::from_min_size(pos, item.content.size());
+            if let Some(link) = item
+                .link
+                .as_ref()
+                .filter(|_| pointer.is_some_and(|p| rect.contains(p)))
+            {
+                ui.ctx().set_cursor_icon(egui::CursorIcon::PointingHand);
+                if clicked {
+                    clicked_link = Some(link.clone());
+                }
+            }
+        }
+        clicked_link
+    }
+}
+
 impl Default for Browser {
     fn default() -> Self {
         Self {
             url: "about:blank".to_string(),
-            engine: Default::default(),
+            engine: Engine::new(Settings::from_environment()),
             processed_tokens: vec![],
             scroll: 0.,
         }
@@ -0,0 +1,166 @@
+//! Working out what kind of content a resource has, and turning it into tokens to display.
+
+use std::fmt::{Debug, Formatter};
+use std::path::Path;
+use std::sync::Arc;
+
+use octo_url::MimeType;
+
+use crate::lex::{self, Token};
+
+/// A decoded image, with its pixels in RGBA order.
+#[derive(Clone, PartialEq)]
+pub struct Image {
+    pub(crate) size: [usize; 2],
+    pub(crate) rgba: Arc<Vec<u8>>,
+}
+
+impl Image {
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let image = image::load_from_memory(bytes)
+            .inspect_err(|e| eprintln!("Couldn't decode image: {e}"))
+            .ok()?
+            .to_rgba8();
+        Some(Self {
+            size: [image.width() as usize, image.height() as usize],
+            rgba: Arc::new(image.into_raw()),
+        })
+    }
+}
+
+impl Debug for Image {
+    // The pixels would only drown out everything else.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Image").field("size", &self.size).finish()
+    }
+}
+
+/// Guesses the MIME type of a file from its extension.
+fn mime_type_from_extension(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    let mime_type = match extension.as_str() {
+        "html" | "htm" | "xhtml" => "text/html",
+        "txt" | "text" | "md" | "rs" | "toml" | "json" | "log" => "text/plain",
+        "css" => "text/css",
+        "js" | "mjs" => "text/javascript",
+        "csv" => "text/csv",
+        "xml" => "text/xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "bmp" => "image/bmp",
+        "ico" => "image/x-icon",
+        "webp" => "image/webp",
+        _ => return None,
+    };
+    Some(mime_type)
+}
+
+/// Guesses the MIME type of `bytes` from their first few bytes, loosely following
+/// https://mimesniff.spec.whatwg.org/#identifying-a-resource-with-an-unknown-mime-type.
+fn sniff_mime_type(bytes: &[u8]) -> &'static str {
+    const HTML_PREFIXES: [&[u8]; 5] = [b"<!doctype html", b"<html", b"<head", b"<body", b"<!--"];
+
+    let start = bytes
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(bytes.len());
+    let start = &bytes[start..bytes.len().min(start + 16)];
+    if HTML_PREFIXES.iter().any(|prefix| {
+        start.len() >= prefix.len() && start[..prefix.len()].eq_ignore_ascii_case(prefix)
+    }) {
+        return "text/html";
+    }
+
+    if let Ok(format) = image::guess_format(bytes) {
+        return format.to_mime_type();
+    }
+
+    // Control characters (other than whitespace) mean it's most likely binary.
+    let is_binary = bytes
+        .iter()
+        .any(|&b| b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\x0c' | b'\r' | b'\x1b'));
+    if is_binary {
+        "application/octet-stream"
+    } else {
+        "text/plain"
+    }
+}
+
+/// Works out the MIME type of a local file, from its extension if it has a known one,
+/// or else from its contents.
+pub(crate) fn file_mime_type(path: &Path, contents: &[u8]) -> MimeType {
+    let mime_type = mime_type_from_extension(path).unwrap_or_else(|| sniff_mime_type(contents));
+    // All of the MIME types above are valid.
+    #[allow(clippy::unwrap_used)]
+    mime_type.parse::<MimeType>().unwrap()
+}
+
+/// Renders `body` according to its MIME type: HTML is lexed, other text is shown as it is
+/// (both decoded from their charset), and images are decoded.
+pub(crate) fn tokens(mime_type: &MimeType, body: &[u8]) -> Vec<Token> {
+    let text = || octo_http::charset::decode(body, Some(mime_type));
+    if mime_type.is_html() {
+        lex::lex(&text(), true)
+    } else if mime_type.is_text() {
+        vec![Token::Text(text())]
+    } else if let Some(image) = mime_type.is_image().then(|| Image::decode(body)).flatten() {
+        vec![Token::Image(image)]
+    } else {
+        vec![Token::Text(format!(
+            "Can't display {} ({} bytes).",
+            mime_type.essence(),
+            body.len()
+        ))]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::io::Cursor;
+
+    fn png() -> Result<Vec<u8>> {
+        let mut bytes = vec![];
+        image::RgbaImage::from_pixel(2, 1, image::Rgba([255, 0, 0, 255]))
+            .write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)?;
+        Ok(bytes)
+    }
+
+    #[test]
+    fn mime_types() -> Result<()> {
+        let essence =
+            |path: &str, contents: &[u8]| file_mime_type(Path::new(path), contents).essence();
+        assert_eq!(essence("index.HTML", b"hi"), "text/html");
+        assert_eq!(essence("notes.txt", b"<html>"), "text/plain");
+        assert_eq!(essence("index", b"  <!DOCTYPE html><p>hi"), "text/html");
+        assert_eq!(essence("README", b"Hello!\n"), "text/plain");
+        assert_eq!(essence("image", &png()?), "image/png");
+        assert_eq!(
+            essence("a.out", b"\x7fELF\x02\x01\x01\x00"),
+            "application/octet-stream"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn image_tokens() -> Result<()> {
+        let mime_type = "image/png".parse::<MimeType>()?;
+        match tokens(&mime_type, &png()?).as_slice() {
+            [Token::Image(image)] => {
+                assert_eq!(image.size, [2, 1]);
+                assert_eq!(image.rgba[..4], [255, 0, 0, 255]);
+            }
+            tokens => panic!("Expected an image, got {tokens:?}"),
+        }
+
+        assert_eq!(
+            tokens(&mime_type, b"not a png"),
+            vec![Token::Text(
+                "Can't display image/png (9 bytes).".to_string()
+            )]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+//! Listings of local directories, generated as HTML pages with a link to each entry.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+use chrono::{DateTime, Local};
+use octo_url::FileUrl;
+
+use crate::lex::escape_html;
+
+struct Entry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+/// Percent-encodes the characters of a file name that would otherwise end up meaning
+/// something else in a URL path or in the `href` attribute.
+fn encode_file_name(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for c in name.chars() {
+        match c {
+            '%' | '#' | '?' | '"' | '<' | '>' | '\\' | '&' => {
+                encoded.push_str(&format!("%{:02X}", c as u32))
+            }
+            _ => encoded.push(c),
+        }
+    }
+    encoded
+}
+
+/// Formats a size in bytes like `1.5 KiB`.
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if size < 1024 {
+        return format!("{size} B");
+    }
+    let mut size = size as f64 / 1024.;
+    let mut unit = 0;
+    while size >= 1024. && unit < UNITS.len() - 1 {
+        size /= 1024.;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+fn read_entries(path: &Path) -> io::Result<Vec<Entry>> {
+    let mut entries = fs::read_dir(path)?
+        .filter_map(Result::ok)
+        .map(|entry| {
+            // Follows symlinks, so links to directories are listed as directories.
+            let metadata = fs::metadata(entry.path()).ok();
+            Entry {
+                name: entry.file_name().to_string_lossy().to_string(),
+                is_dir: metadata.as_ref().is_some_and(|m| m.is_dir()),
+                size: metadata.as_ref().map_or(0, |m| m.len()),
+                modified: metadata.and_then(|m| m.modified().ok()),
+            }
+        })
+        .collect::<Vec<_>>();
+    // Directories first, then files, each sorted by name.
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+    Ok(entries)
+}
+
+fn entry_line(directory_path: &str, entry: &Entry) -> String {
+    let slash = if entry.is_dir { "/" } else { "" };
+    let size = if entry.is_dir {
+        "-".to_string()
+    } else {
+        format_size(entry.size)
+    };
+    let modified = entry.modified.map_or_else(
+        || "-".to_string(),
+        |modified| {
+            DateTime::<Local>::from(modified)
+                .format("%Y-%m-%d %H:%M")
+                .to_string()
+        },
+    );
+    format!(
+        "<a href=\"{directory_path}{}{slash}\">{}{slash}</a> <small>{size} {modified}</small>",
+        encode_file_name(&entry.name),
+        escape_html(&entry.name),
+    )
+}
+
+/// Generates an HTML index of the directory at `path`, which `url` points to.
+/// The links are absolute paths, so they work whether or not `url` ends with a `/`.
+pub(crate) fn listing(url: &FileUrl, path: &Path) -> io::Result<String> {
+    let entries = read_entries(path)?;
+    let directory_path = if url.path.ends_with('/') {
+        url.path.clone()
+    } else {
+        format!("{}/", url.path)
+    };
+    let title = escape_html(&path.to_string_lossy());
+
+    let mut lines = vec![format!("<big><b>Index of {title}</b></big><br>")];
+    if path.parent().is_some() {
+        lines.push(format!("<a href=\"{directory_path}../\">../</a>"));
+    }
+    lines.extend(
+        entries
+            .iter()
+            .map(|entry| entry_line(&directory_path, entry)),
+    );
+    Ok(lines.join("<br>"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(5 * 1024 * 1024 * 1024), "5.0 GiB");
+    }
+
+    #[test]
+    fn file_names_are_encoded() {
+        assert_eq!(encode_file_name("100% #1?.txt"), "100%25 %231%3F.txt");
+        assert_eq!(encode_file_name("<b>.html"), "%3Cb%3E.html");
+        // Entities in links are decoded, so `&` can't be left as it is.
+        assert_eq!(encode_file_name("&lt;.txt"), "%26lt;.txt");
+    }
+}
@@ -1,25 +1,85 @@
+use crate::about;
+use crate::content;
+use crate::directory;
 use crate::lex;
 use crate::lex::Token;
-use anyhow::{anyhow, Context};
-use octo_http::cache::Cache;
-use octo_http::request::{Request, RequestMethod, Response};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use octo_http::cache::{self, Cache, Lookup};
+use octo_http::client::Client;
+use octo_http::cookies::CookieJar;
+use octo_http::request::{RedirectPolicy, Request, RequestMethod, Response};
+use octo_http::HttpError;
 use octo_url::url::AboutValue;
-use octo_url::{Url, UrlError, WebUrl};
-use std::fs;
+use octo_url::{FileUrl, Site, Url, UrlError, WebUrl};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use std::{env, fs};
 use thiserror::Error;
 
-// TODO: Check what real browsers set this to.
-const MAX_REDIRECTS: u8 = 5;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+const TIMEOUT: Duration = Duration::from_secs(60);
+const CACHE_DIRECTORY_VARIABLE: &str = "OCTO_CACHE_DIR";
+
+/// The settings that change how the engine loads pages, shown on `about:config`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Settings {
+    pub(crate) max_redirects: u8,
+    /// How long connecting to a server can take.
+    pub(crate) connect_timeout: Duration,
+    /// How long a server can go without sending anything.
+    pub(crate) read_timeout: Duration,
+    /// How long loading a page can take altogether.
+    pub(crate) timeout: Duration,
+    pub(crate) keep_alive: bool,
+    pub(crate) compression: bool,
+    /// Whether responses are cached (and served from the cache).
+    pub(crate) cache: bool,
+    /// How many bytes of responses the cache keeps.
+    pub(crate) cache_size: usize,
+    /// Where the cache is kept between runs. Without one, it's only kept in memory.
+    pub(crate) cache_directory: Option<PathBuf>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            max_redirects: RedirectPolicy::DEFAULT_LIMIT,
+            connect_timeout: CONNECT_TIMEOUT,
+            read_timeout: READ_TIMEOUT,
+            timeout: TIMEOUT,
+            keep_alive: true,
+            compression: true,
+            cache: true,
+            cache_size: cache::DEFAULT_MAX_SIZE,
+            cache_directory: None,
+        }
+    }
+}
+
+impl Settings {
+    /// The default settings, with the cache kept in `OCTO_CACHE_DIR` if it's set.
+    pub(crate) fn from_environment() -> Self {
+        Self {
+            cache_directory: env::var_os(CACHE_DIRECTORY_VARIABLE).map(PathBuf::from),
+            ..Self::default()
+        }
+    }
+}
 
 macro_rules! lex_optional_body {
-    ($maybe_body:expr, $render:expr) => {
-        $maybe_body.as_deref().map(|s| lex::lex(s, $render))
+    ($response:expr, $render:expr) => {
+        $response
+            .body()
+            .map(|_| lex::lex($response.text(), $render))
     };
 }
 
 macro_rules! render_optional_body {
-    ($maybe_body:expr) => {
-        lex_optional_body!($maybe_body, true)
+    ($response:expr) => {
+        lex_optional_body!($response, true)
     };
 }
 
@@ -28,98 +88,164 @@ pub(crate) enum EngineError {
     #[error("Error loading page: {0}")]
     Load(#[from] octo_http::HttpError),
 
-    #[error("Redirect error: {0}")]
-    Redirect(String),
-
     #[error("Error parsing URL: {0}")]
     ParseUrl(#[from] UrlError),
-
-    #[error("Not a web URL: {0:?}")]
-    NotWebUrl(Url),
 }
 
-/// Returns the body of a WebUrl, handling potential redirects.
-fn load_web_url(url: &WebUrl) -> anyhow::Result<Response> {
-    let mut request = Request::new(RequestMethod::Get, &url.host, true, true);
-    let mut response = request.make(url, None)?;
-    let mut status_code = response.status_code();
-    let mut num_redirects = 0;
-
-    while (300..400).contains(&status_code) && num_redirects < MAX_REDIRECTS {
-        let new_url = response
-            .headers
-            .get("location")
-            .ok_or_else(|| {
-                EngineError::Redirect(format!(
-                    "Missing Location header in response: {:?}",
-                    response.headers
-                ))
-            })?
-            .first()
-            .ok_or_else(|| {
-                EngineError::Redirect(format!(
-                    "Missing Location value in response headers: {:?}",
-                    response.headers
-                ))
-            })?;
-
-        let new_url = if new_url.starts_with('/') {
-            Url::Web(url.with_path(new_url))
-        } else {
-            new_url.parse::<Url>()?
-        };
-
-        let new_url = new_url
-            .as_web_url()
-            .ok_or_else(|| EngineError::NotWebUrl(new_url.clone()))
-            .context(anyhow!("{response:?}"))?;
-
-        response = request.make(new_url, None)?;
-        status_code = response.status_code();
-        num_redirects += 1;
+/// A page saying that the server for `url` took too long to respond, instead of
+/// the error, if that's what `error` is.
+fn timeout_page(url: &WebUrl, error: anyhow::Error) -> anyhow::Result<Option<Vec<Token>>> {
+    match error.downcast_ref::<HttpError>() {
+        Some(HttpError::Timeout(timeout)) => {
+            Ok(Some(lex::lex(&about::timed_out(url, timeout), true)))
+        }
+        _ => Err(error),
     }
+}
 
-    if (300..400).contains(&status_code) {
-        // If we still have a redirect status code and exhausted MAX_REDIRECTS.
-        return Err(EngineError::Redirect("Too many redirects.".to_string()).into());
+/// Renders a local file according to its type, or lists the entries of a directory.
+fn file_url_tokens(url: &FileUrl) -> anyhow::Result<Vec<Token>> {
+    let path = url.file_path();
+    if path.is_dir() {
+        let listing = directory::listing(url, &path).context(url.path.clone())?;
+        return Ok(lex::lex(&listing, true));
     }
 
-    Ok(response)
+    let contents = fs::read(&path).context(url.path.clone())?;
+    let mime_type = content::file_mime_type(&path, &contents);
+    Ok(content::tokens(&mime_type, &contents))
 }
 
 #[derive(Debug)]
 enum LoadedResponse {
-    Fresh(Response),
+    /// A response from the server, to a request sent at the given time.
+    Fresh(Response, DateTime<Utc>),
     Cached(Response),
 }
 
-#[derive(Debug, Default)]
-#[cfg_attr(test, derive(PartialEq))]
+#[derive(Debug)]
 pub(crate) struct Engine {
+    /// Keeps the cookies set by every page, and sends them back
+    /// (including along redirects).
+    client: Client,
     cache: Cache,
+    /// The URLs loaded so far, oldest first.
+    history: Vec<Url>,
+    settings: Settings,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new(Settings::default())
+    }
 }
 
 impl Engine {
-    fn maybe_cache_response(&mut self, url: WebUrl, response: Response) -> bool {
+    pub(crate) fn new(settings: Settings) -> Self {
+        let cache = Cache::new().with_max_size(settings.cache_size);
+        let cache = match &settings.cache_directory {
+            Some(directory) => cache.with_directory(directory).unwrap_or_else(|e| {
+                eprintln!("Couldn't open the cache in {}: {e}", directory.display());
+                Cache::new().with_max_size(settings.cache_size)
+            }),
+            None => cache,
+        };
+        Self {
+            client: Client::new().with_cookie_jar(Arc::new(CookieJar::new())),
+            cache,
+            history: vec![],
+            settings,
+        }
+    }
+
+    /// A GET request for `url`, following redirects as the settings say,
+    /// and with any extra headers (like the ones that make it conditional).
+    fn request(&self, url: &WebUrl, headers: &[(&str, String)]) -> Request {
+        let mut request = Request::builder(RequestMethod::Get, url);
+        for (key, value) in headers {
+            request = request.header(key, value);
+        }
+        request
+            .keep_alive(self.settings.keep_alive)
+            .compression(self.settings.compression)
+            .redirect_policy(RedirectPolicy::Limit(self.settings.max_redirects))
+            .connect_timeout(self.settings.connect_timeout)
+            .read_timeout(self.settings.read_timeout)
+            .timeout(self.settings.timeout)
+            .build()
+    }
+
+    fn maybe_cache_response(
+        &mut self,
+        top_level_site: &Site,
+        request: &Request,
+        response: Response,
+        request_time: DateTime<Utc>,
+    ) -> bool {
+        if !self.settings.cache {
+            return false;
+        }
         self.cache
-            .insert(url, response)
+            .insert(top_level_site, request, response, request_time)
             .inspect_err(|e| eprintln!("Couldn't cache the response: {e}"))
             .is_ok()
     }
 
-    fn load_or_get_cached(&self, url: &WebUrl) -> anyhow::Result<LoadedResponse> {
-        if let Some(response) = self.cache.get(url).maybe_clone() {
-            Ok(LoadedResponse::Cached(response))
-        } else {
-            load_web_url(url).map(LoadedResponse::Fresh)
+    /// Uses the response to `request` cached for pages on `top_level_site` if it's fresh,
+    /// or else sends it, as a conditional request if there's a stale response to revalidate.
+    fn load_or_get_cached(
+        &mut self,
+        top_level_site: &Site,
+        request: &Request,
+    ) -> anyhow::Result<LoadedResponse> {
+        let request_time = Utc::now();
+        if !self.settings.cache {
+            return Ok(LoadedResponse::Fresh(
+                self.client.execute(request)?,
+                request_time,
+            ));
+        }
+        let conditional_headers = match self.cache.lookup(top_level_site, request) {
+            Lookup::Fresh(response) => return Ok(LoadedResponse::Cached(response)),
+            Lookup::Stale(conditional_headers) => conditional_headers,
+            Lookup::Miss => vec![],
+        };
+
+        let conditional_request = self.request(request.url(), &conditional_headers);
+        match self.client.execute(&conditional_request) {
+            Ok(response) if response.status_code() == 304 && !conditional_headers.is_empty() => Ok(
+                match self
+                    .cache
+                    .update(top_level_site, request, &response, request_time)
+                {
+                    Some(response) => LoadedResponse::Cached(response),
+                    None => LoadedResponse::Fresh(response, request_time),
+                },
+            ),
+            Ok(response) => Ok(LoadedResponse::Fresh(response, request_time)),
+            // A stale page is better than none when the server can't be reached,
+            // unless it says otherwise.
+            Err(error) => self
+                .cache
+                .get_stale(top_level_site, request)
+                .map(LoadedResponse::Cached)
+                .ok_or(error.into()),
         }
     }
 
     fn load_or_maybe_cache(&mut self, url: WebUrl) -> anyhow::Result<Response> {
-        let response = self.load_or_get_cached(&url)?;
+        // Pages are loaded as top-level documents, so they're cached for their own site.
+        let top_level_site = url.origin().site();
+        let request = self.request(&url, &[]);
+        let response = self.load_or_get_cached(&top_level_site, &request)?;
         Ok(match response {
-            LoadedResponse::Fresh(response) => {
-                self.maybe_cache_response(url, response.clone());
+            LoadedResponse::Fresh(response, request_time) => {
+                self.maybe_cache_response(
+                    &top_level_site,
+                    &request,
+                    response.clone(),
+                    request_time,
+                );
                 response
             }
             LoadedResponse::Cached(response) => response,
@@ -127,8 +253,15 @@ impl Engine {
     }
 
     fn load_and_parse_body(&mut self, url: WebUrl) -> anyhow::Result<Option<Vec<Token>>> {
-        let response = self.load_or_maybe_cache(url)?;
-        Ok(render_optional_body!(response.body))
+        match self.load_or_maybe_cache(url.clone()) {
+            Ok(response) => Ok(render_optional_body!(response)),
+            Err(error) => timeout_page(&url, error),
+        }
+    }
+
+    /// The URL of the page loaded last, which links on it are relative to.
+    pub(crate) fn current_url(&self) -> Option<&Url> {
+        self.history.last()
     }
 
     pub(crate) fn load(&mut self, url: &str) -> anyhow::Result<Option<Vec<Token>>> {
@@ -137,28 +270,32 @@ impl Engine {
             .inspect_err(|e| eprintln!("{e}"))
             .unwrap_or(Url::About(AboutValue::Blank));
 
+        let tokens = self.load_url(url.clone())?;
+        self.history.push(url);
+        Ok(tokens)
+    }
+
+    fn load_url(&mut self, url: Url) -> anyhow::Result<Option<Vec<Token>>> {
         match url {
             Url::Web(url) => self.load_and_parse_body(url),
-            Url::File(url) => {
-                let contents = fs::read(&url.path).context(url.path)?;
-                let contents = String::from_utf8_lossy(&contents);
-                let tokens = vec![Token::Text(contents.to_string())];
-                Ok(Some(tokens))
-            }
-            Url::Data(url) => {
-                let tokens = render_optional_body!(Some(url.data));
-                Ok(tokens)
-            }
+            Url::File(url) => file_url_tokens(&url).map(Some),
+            Url::Data(url) => Ok(Some(content::tokens(&url.mime_type, &url.body))),
             Url::ViewSource(url) => {
-                let response = Request::get(&url)?;
-                Ok(lex_optional_body!(response.body, false))
+                let request = self.request(&url, &[]);
+                match self.client.execute(&request) {
+                    Ok(response) => Ok(lex_optional_body!(response, false)),
+                    Err(error) => timeout_page(&url, error.into()),
+                }
             }
             Url::About(about_value) => {
                 let body = match about_value {
                     AboutValue::Blank => "".to_string(),
+                    AboutValue::History => about::history(&self.history),
+                    AboutValue::Cache => about::cache(&self.cache),
+                    AboutValue::Version => about::version(),
+                    AboutValue::Config => about::config(&self.settings),
                 };
-                let tokens = vec![Token::Text(body)];
-                Ok(Some(tokens))
+                Ok(Some(lex::lex(&body, true)))
             }
         }
     }
@@ -168,17 +305,48 @@ impl Engine {
 mod tests {
     use super::*;
     use anyhow::Result;
+    use octo_http::transport::ScriptedTransport;
     use std::env;
 
+    /// An engine whose requests are answered by `transport`, instead of over the network.
+    fn scripted_engine(transport: &ScriptedTransport) -> Engine {
+        Engine {
+            client: Client::new()
+                .with_cookie_jar(Arc::new(CookieJar::new()))
+                .with_scripted_transport(transport.clone()),
+            ..Engine::default()
+        }
+    }
+
+    /// A response with an HTML `body`, and the extra `headers` (each ending in `\r\n`).
+    fn page(body: &str, headers: &str) -> Vec<u8> {
+        format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: text/html\r\n{headers}content-length: {}\r\n\r\n{body}",
+            body.len()
+        )
+        .into_bytes()
+    }
+
     #[test]
     fn load_url() -> Result<()> {
-        Engine::default().load("http://example.org")?;
+        let transport = ScriptedTransport::new();
+        transport.add_response("example.org", 80, page("<b>hi</b>", ""));
+        let tokens = scripted_engine(&transport).load("http://example.org")?;
+        assert!(tokens
+            .unwrap_or_default()
+            .contains(&Token::Text("hi".to_string())));
+        assert!(transport.requests()[0].starts_with("GET / HTTP/1.1\r\n"));
         Ok(())
     }
 
     #[test]
     fn load_url_https() -> Result<()> {
-        Engine::default().load("https://example.org")?;
+        let transport = ScriptedTransport::new();
+        transport.add_response("example.org", 443, page("<b>hi</b>", ""));
+        let tokens = scripted_engine(&transport).load("https://example.org")?;
+        assert!(tokens
+            .unwrap_or_default()
+            .contains(&Token::Text("hi".to_string())));
         Ok(())
     }
 
@@ -191,17 +359,218 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn load_directory() -> Result<()> {
+        let current_dir = env::current_dir().expect("Can't get current directory.");
+        let project_root = current_dir.parent().expect("Can't get parent directory");
+        let url = format!("file://{}", project_root.to_string_lossy());
+        let tokens = Engine::default().load(&url)?.unwrap_or_default();
+
+        let license_link = format!("a href=\"{}/LICENSE\"", project_root.to_string_lossy());
+        assert!(tokens.contains(&Token::Tag(license_link)));
+        let browser_link = format!("a href=\"{}/browser/\"", project_root.to_string_lossy());
+        assert!(tokens.contains(&Token::Tag(browser_link)));
+        Ok(())
+    }
+
+    #[test]
+    fn load_html_file() -> Result<()> {
+        let dir = env::temp_dir().join(format!("octo-load-html-file-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("page.html"), "<b>hi</b>")?;
+        fs::write(dir.join("page.txt"), "<b>hi</b>")?;
+
+        let mut engine = Engine::default();
+        let html = engine.load(&format!("file://{}/page.html", dir.to_string_lossy()));
+        let text = engine.load(&format!("file://{}/page.txt", dir.to_string_lossy()));
+        fs::remove_dir_all(&dir)?;
+
+        assert_eq!(
+            html?,
+            Some(vec![
+                Token::Tag("b".to_string()),
+                Token::Text("hi".to_string()),
+                Token::Tag("/b".to_string())
+            ])
+        );
+        assert_eq!(text?, Some(vec![Token::Text("<b>hi</b>".to_string())]));
+        Ok(())
+    }
+
+    #[test]
+    fn load_data_url() -> Result<()> {
+        let mut engine = Engine::default();
+        let tokens = engine.load("data:text/html;base64,PGI+aGk8L2I+")?;
+        assert_eq!(
+            tokens,
+            Some(vec![
+                Token::Tag("b".to_string()),
+                Token::Text("hi".to_string()),
+                Token::Tag("/b".to_string())
+            ])
+        );
+
+        let tokens = engine.load("data:text/plain;charset=utf-8,%3Cb%3E%F0%9F%90%99")?;
+        assert_eq!(tokens, Some(vec![Token::Text("<b>🐙".to_string())]));
+        Ok(())
+    }
+
+    #[test]
+    fn about_pages() -> Result<()> {
+        let mut engine = Engine::default();
+        let text = |tokens: Option<Vec<Token>>| {
+            tokens
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|token| match token {
+                    Token::Text(text) => Some(text),
+                    Token::Tag(_) | Token::Image(_) => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert!(text(engine.load("about:history")?).contains(&"No pages visited yet.".to_string()));
+        engine.load("data:,hi")?;
+        let history = text(engine.load("about:history")?);
+        assert!(history.contains(&"1. about:history".to_string()));
+        assert!(history.contains(&"2. data:,hi".to_string()));
+
+        assert!(text(engine.load("about:cache")?).contains(&"The cache is empty.".to_string()));
+        assert!(text(engine.load("about:config")?).contains(&"max_redirects: 20".to_string()));
+        let version = text(engine.load("about:version")?);
+        assert!(version.contains(&format!("octo-url {}", octo_url::VERSION)));
+        Ok(())
+    }
+
+    #[test]
+    fn timeout_page() -> Result<()> {
+        // Accepts the connections, and never answers.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        let server = std::thread::spawn(move || -> Result<Vec<std::net::TcpStream>> {
+            (0..2)
+                .map(|_| Ok(listener.accept()?.0))
+                .collect::<Result<_>>()
+        });
+
+        let mut engine = Engine::default();
+        engine.settings.read_timeout = Duration::from_millis(100);
+        let message = Token::Text(format!(
+            "http://127.0.0.1:{port}/ took too long to respond."
+        ));
+        let tokens = engine.load(&format!("http://127.0.0.1:{port}/"))?;
+        assert!(tokens.unwrap_or_default().contains(&message));
+        let tokens = engine.load(&format!("view-source:http://127.0.0.1:{port}/"))?;
+        assert!(tokens.unwrap_or_default().contains(&message));
+        #[allow(clippy::unwrap_used)]
+        server.join().unwrap()?;
+        Ok(())
+    }
+
+    #[test]
+    fn cookies_survive_redirects() -> Result<()> {
+        let transport = ScriptedTransport::new();
+        transport.add_response(
+            "example.org",
+            80,
+            "HTTP/1.1 302 Found\r\nSet-Cookie: session=abc\r\nLocation: /home\r\nConnection: close\r\nContent-Length: 2\r\n\r\nhi",
+        );
+        transport.add_response(
+            "example.org",
+            80,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nConnection: close\r\nContent-Length: 2\r\n\r\nhi",
+        );
+
+        scripted_engine(&transport).load("http://example.org/login")?;
+        let requests = transport.requests();
+        assert!(!requests[0].contains("cookie:"));
+        assert!(requests[1].starts_with("GET /home "));
+        assert!(requests[1].contains("cookie: session=abc\r\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn revalidates_stale_responses() -> Result<()> {
+        let transport = ScriptedTransport::new();
+        transport.add_response(
+            "example.org",
+            80,
+            "HTTP/1.1 200 OK\r\nCache-Control: no-cache\r\nETag: \"v1\"\r\nContent-Length: 2\r\n\r\nhi",
+        );
+        transport.add_response(
+            "example.org",
+            80,
+            "HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\n\r\n",
+        );
+
+        let mut engine = scripted_engine(&transport);
+        engine.settings.keep_alive = false;
+        let url = "http://example.org/";
+        let first = engine.load(url)?;
+        let second = engine.load(url)?;
+        assert_eq!(first, Some(vec![Token::Text("hi".to_string())]));
+        assert_eq!(second, first);
+        let requests = transport.requests();
+        assert!(!requests[0].contains("if-none-match"));
+        assert!(requests[1].contains("if-none-match: \"v1\"\r\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn revalidation_redirects_are_not_conditional() -> Result<()> {
+        let transport = ScriptedTransport::new();
+        transport.add_connection(
+            "example.org",
+            80,
+            vec![
+                b"HTTP/1.1 200 OK\r\nCache-Control: no-cache\r\nETag: \"v1\"\r\nContent-Length: 3\r\n\r\nold".to_vec(),
+                b"HTTP/1.1 301 Moved Permanently\r\nLocation: /new\r\nContent-Length: 0\r\n\r\n".to_vec(),
+                b"HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\nnew".to_vec(),
+            ],
+        );
+
+        let mut engine = scripted_engine(&transport);
+        engine.load("http://example.org/")?;
+        let tokens = engine.load("http://example.org/")?;
+        assert_eq!(tokens, Some(vec![Token::Text("new".to_string())]));
+        let requests = transport.requests();
+        assert!(requests[1].contains("if-none-match: \"v1\"\r\n"));
+        assert!(requests[2].starts_with("GET /new "));
+        assert!(!requests[2].contains("if-none-match"));
+        Ok(())
+    }
+
     #[test]
     fn load_view_source() -> Result<()> {
-        Engine::default().load("view-source:http://example.org/")?;
+        let transport = ScriptedTransport::new();
+        transport.add_response("example.org", 80, page("<b>hi</b>", ""));
+        let tokens = scripted_engine(&transport).load("view-source:http://example.org/")?;
+        assert_eq!(tokens, Some(vec![Token::Text("<b>hi</b>".to_string())]));
         Ok(())
     }
 
-    fn test_redirect_equality(url_redirect: &str, url_no_redirect: &str) -> Result<()> {
-        let mut browser = Engine::default();
+    /// Loads `url_no_redirect`, and then `url_redirect`, which redirects to each of
+    /// `locations` in turn, the last of them being `url_no_redirect`.
+    fn test_redirect_equality(
+        url_redirect: &str,
+        url_no_redirect: &str,
+        locations: &[&str],
+    ) -> Result<()> {
+        let body = page("<p>HTTP</p>", "");
+        let mut responses = vec![body.clone()];
+        responses.extend(locations.iter().map(|location| {
+            format!("HTTP/1.1 301 Moved Permanently\r\nlocation: {location}\r\ncontent-length: 0\r\n\r\n")
+                .into_bytes()
+        }));
+        responses.push(body);
+        let transport = ScriptedTransport::new();
+        transport.add_connection("browser.engineering", 443, responses);
+
+        let mut browser = scripted_engine(&transport);
         let body_no_redirect = browser.load(url_no_redirect)?;
         let body_redirect = browser.load(url_redirect)?;
         assert_eq!(body_redirect, body_no_redirect);
+        assert_eq!(transport.requests().len(), locations.len() + 2);
         Ok(())
     }
 
@@ -210,6 +579,7 @@ mod tests {
         test_redirect_equality(
             "https://browser.engineering/redirect",
             "https://browser.engineering/http.html",
+            &["/http.html"],
         )
     }
 
@@ -218,6 +588,7 @@ mod tests {
         test_redirect_equality(
             "https://browser.engineering/redirect2",
             "https://browser.engineering/http.html",
+            &["/redirect", "/http.html"],
         )
     }
 
@@ -226,15 +597,32 @@ mod tests {
         test_redirect_equality(
             "https://browser.engineering/redirect3",
             "https://browser.engineering/http.html",
+            &[
+                "https://browser.engineering/redirect2",
+                "/redirect",
+                "/http.html",
+            ],
         )
     }
 
     #[test]
     fn cache() -> Result<()> {
-        let mut browser = Engine::default();
-        browser.load("https://example.org")?;
+        let transport = ScriptedTransport::new();
+        let cache_control = "cache-control: max-age=60\r\n";
+        transport.add_response("example.org", 443, page("<p>Example</p>", cache_control));
+        transport.add_response(
+            "browser.engineering",
+            443,
+            page("<p>HTTP</p>", cache_control),
+        );
+
+        let mut browser = scripted_engine(&transport);
+        let example = browser.load("https://example.org")?;
         browser.load("https://browser.engineering/http.html")?;
-        assert!(!browser.cache.into_iter().collect::<Vec<_>>().is_empty());
+        assert_eq!(browser.cache.into_iter().count(), 2);
+        // The second load is answered from the cache, without another request.
+        assert_eq!(browser.load("https://example.org")?, example);
+        assert_eq!(transport.requests().len(), 2);
         Ok(())
     }
 }
@@ -1,10 +1,14 @@
-use crate::lex::Token;
+use crate::content::Image;
+use crate::lex::{tag_attribute, Token};
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
 use eframe::egui;
 
 const VSTEP: f32 = 18.;
 const DEFAULT_TEXT_SIZE_PIXELS: f32 = 16.;
+const LINK_COLOR: egui::Color32 = egui::Color32::from_rgb(0, 0, 238);
 pub(crate) const PADDING: f32 = 10.;
 
 macro_rules! starting_x {
@@ -13,20 +17,81 @@ macro_rules! starting_x {
     };
 }
 
+/// An image uploaded to the GPU. It's freed when the last clone is dropped.
+#[derive(Clone)]
+pub(crate) struct Texture(egui::TextureHandle);
+
+impl Debug for Texture {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Texture").field(&self.0.id()).finish()
+    }
+}
+
+/// The `href` of the link a token is part of, if any.
+type Link = Option<String>;
+
 #[derive(Debug, Clone)]
 pub(crate) enum ProcessedToken {
-    Text(egui::text::LayoutJob),
+    Text(egui::text::LayoutJob, Link),
+    Image(Texture, Link),
     LineBreak,
 }
 
+#[derive(Clone)]
+pub(crate) enum Content {
+    Text(Arc<egui::Galley>),
+    Image(Texture, egui::Vec2),
+}
+
+impl Content {
+    pub(crate) fn size(&self) -> egui::Vec2 {
+        match self {
+            Self::Text(galley) => galley.rect.size(),
+            Self::Image(_, size) => *size,
+        }
+    }
+
+    /// How far the content reaches above the baseline.
+    fn ascent(&self) -> Option<f32> {
+        match self {
+            Self::Text(galley) => get_max_ascent(galley),
+            Self::Image(_, size) => Some(size.y),
+        }
+    }
+
+    /// How far the content reaches below the baseline.
+    fn descent(&self) -> f32 {
+        match self {
+            Self::Text(galley) => galley.mesh_bounds.bottom() - galley.mesh_bounds.center().y,
+            Self::Image(..) => 0.,
+        }
+    }
+}
+
 pub(crate) struct DisplayListItem {
-    pub(crate) galley: Arc<egui::Galley>,
+    pub(crate) content: Content,
     pub(crate) pos: egui::Pos2,
+    pub(crate) link: Link,
 }
 
 impl DisplayListItem {
-    fn new(galley: Arc<egui::Galley>, pos: egui::Pos2) -> Self {
-        Self { galley, pos }
+    fn new(content: Content, pos: egui::Pos2, link: Link) -> Self {
+        Self { content, pos, link }
+    }
+
+    /// Paints the item with its top left corner at `pos`.
+    pub(crate) fn paint(&self, painter: &egui::Painter, pos: egui::Pos2) {
+        match &self.content {
+            Content::Text(galley) => painter.galley(pos, Arc::clone(galley), Default::default()),
+            Content::Image(texture, size) => {
+                painter.image(
+                    texture.0.id(),
+                    egui::Rect::from_min_size(pos, *size),
+                    egui::Rect::from_min_max(egui::pos2(0., 0.), egui::pos2(1., 1.)),
+                    egui::Color32::WHITE,
+                );
+            }
+        }
     }
 }
 
@@ -61,9 +126,20 @@ impl<'a> Layout<'a> {
         layout.display_list
     }
 
+    fn push_content(&mut self, content: Content, link: &Link, space_width: f32) {
+        let width = content.size().x;
+        if self.current_x + width > self.ui.min_rect().width() - PADDING {
+            self.flush();
+        }
+
+        let line_item = LineItem::new(content, self.current_x, link.clone());
+        self.line.push(line_item);
+        self.current_x += width + space_width;
+    }
+
     fn push_to_line(&mut self, token: &ProcessedToken) {
         match token {
-            ProcessedToken::Text(layout_job) => {
+            ProcessedToken::Text(layout_job, link) => {
                 let galley = self.ui.painter().layout_job(layout_job.clone());
                 let font_id = layout_job
                     .sections
@@ -76,13 +152,18 @@ impl<'a> Layout<'a> {
                         .painter()
                         .layout_no_wrap(" ".to_string(), font_id, Default::default());
 
-                if self.current_x + galley.rect.width() > self.ui.min_rect().width() - PADDING {
-                    self.flush();
-                }
-
-                let line_item = LineItem::new(Arc::clone(&galley), self.current_x);
-                self.line.push(line_item);
-                self.current_x += galley.rect.width() + galley_space.rect.width();
+                self.push_content(Content::Text(galley), link, galley_space.rect.width());
+            }
+            ProcessedToken::Image(texture, link) => {
+                // Scale images down (but never up) to fit the page.
+                let size = texture.0.size_vec2();
+                let max_width = self.ui.min_rect().width() - 2. * PADDING;
+                let size = if size.x > max_width && size.x > 0. {
+                    size * (max_width / size.x)
+                } else {
+                    size
+                };
+                self.push_content(Content::Image(texture.clone(), size), link, 0.);
             }
             ProcessedToken::LineBreak => {
                 self.flush();
@@ -96,29 +177,31 @@ impl<'a> Layout<'a> {
         let max_ascent = self
             .line
             .iter()
-            .flat_map(|item| get_max_ascent(&item.galley))
+            .flat_map(|item| item.content.ascent())
             .reduce(f32::max);
 
         let max_descent = self
             .line
             .iter()
-            .map(|item| item.galley.mesh_bounds.bottom() - item.galley.mesh_bounds.center().y)
+            .map(|item| item.content.descent())
             .reduce(f32::max);
 
         if let (Some(max_ascent), Some(max_descent)) = (max_ascent, max_descent) {
             let baseline = self.current_y + 1.25 * max_ascent;
 
-            for line_item in &self.line {
-                let ascent = get_max_ascent(&line_item.galley).unwrap_or_default();
+            for line_item in self.line.drain(..) {
+                let ascent = line_item.content.ascent().unwrap_or_default();
                 let y = baseline - ascent;
                 let pos = egui::Pos2::new(line_item.x, y);
-                self.display_list
-                    .push(DisplayListItem::new(Arc::clone(&line_item.galley), pos));
+                self.display_list.push(DisplayListItem::new(
+                    line_item.content,
+                    pos,
+                    line_item.link,
+                ));
             }
 
             self.current_y = baseline + 1.25 * max_descent;
             self.current_x = starting_x!(self.ui);
-            self.line.clear();
         }
     }
 }
@@ -135,51 +218,87 @@ fn get_max_ascent(galley: &egui::Galley) -> Option<f32> {
 
 pub(crate) struct TokenProcessor {
     pub(crate) processed_tokens: Vec<ProcessedToken>,
+    ctx: egui::Context,
     text_size: f32,
     italics: bool,
     color: egui::Color32,
+    link: Link,
 }
 
-impl Default for TokenProcessor {
-    fn default() -> Self {
+impl TokenProcessor {
+    fn new(ctx: &egui::Context) -> Self {
         Self {
             processed_tokens: vec![],
+            ctx: ctx.clone(),
             text_size: DEFAULT_TEXT_SIZE_PIXELS,
             italics: false,
             color: egui::Color32::BLACK,
+            link: None,
         }
     }
-}
 
-impl TokenProcessor {
-    pub(crate) fn from_tokens(tokens: Vec<Token>) -> Self {
-        let mut layout = Self::default();
+    /// Processes `tokens`, using `ctx` to upload any images they contain.
+    pub(crate) fn from_tokens(tokens: Vec<Token>, ctx: &egui::Context) -> Self {
+        let mut layout = Self::new(ctx);
         layout.process_all_tokens(tokens);
         layout
     }
 
     fn process_text(&mut self, text: &str) {
         let font_id = egui::FontId::new(self.text_size, egui::FontFamily::Proportional);
+        let color = if self.link.is_some() {
+            LINK_COLOR
+        } else {
+            self.color
+        };
+        let underline = if self.link.is_some() {
+            egui::Stroke::new(1., LINK_COLOR)
+        } else {
+            egui::Stroke::NONE
+        };
         let format = egui::text::TextFormat {
             font_id,
             italics: self.italics,
-            color: self.color,
+            color,
+            underline,
             valign: egui::Align::Min,
             ..Default::default()
         };
         for word in text.split_whitespace() {
             let mut layout_job = egui::text::LayoutJob::default();
             layout_job.append(word, 0.0, format.clone());
-            self.processed_tokens.push(ProcessedToken::Text(layout_job));
+            self.processed_tokens
+                .push(ProcessedToken::Text(layout_job, self.link.clone()));
         }
     }
 
+    fn process_image(&mut self, image: Image) {
+        // Texture names only show up when debugging, but they should still be unique.
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        let name = format!("image-{}", NEXT_ID.fetch_add(1, Ordering::Relaxed));
+        let color_image = egui::ColorImage::from_rgba_unmultiplied(image.size, &image.rgba);
+        let texture = self
+            .ctx
+            .load_texture(name, color_image, egui::TextureOptions::default());
+        self.processed_tokens
+            .push(ProcessedToken::Image(Texture(texture), self.link.clone()));
+    }
+
     fn process_token(&mut self, token: Token) {
         match token {
             Token::Text(text) => {
                 self.process_text(text.as_str());
             }
-            Token::Tag(tag) => match tag.as_str() {
+            Token::Image(image) => {
+                self.process_image(image);
+            }
+            Token::Tag(tag) => match tag.split_whitespace().next().unwrap_or_default() {
+                "a" => {
+                    self.link = tag_attribute(&tag, "href").map(str::to_string);
+                }
+                "/a" => {
+                    self.link = None;
+                }
                 "i" => {
                     self.italics = true;
                 }
@@ -226,12 +345,13 @@ impl TokenProcessor {
 }
 
 struct LineItem {
-    galley: Arc<egui::Galley>,
+    content: Content,
     x: f32,
+    link: Link,
 }
 
 impl LineItem {
-    fn new(galley: Arc<egui::Galley>, x: f32) -> Self {
-        Self { galley, x }
+    fn new(content: Content, x: f32, link: Link) -> Self {
+        Self { content, x, link }
     }
 }
@@ -1,5 +1,7 @@
 use unicode_segmentation::UnicodeSegmentation;
 
+use crate::content::Image;
+
 // AFAIK no entity in the spec is longer than 26 chars.
 const MAX_ENTITY_LEN: usize = 26;
 
@@ -11,6 +13,53 @@ const MAX_ENTITY_LEN: usize = 26;
 pub enum Token {
     Text(String),
     Tag(String),
+    Image(Image),
+}
+
+/// Escapes `text` so that it is lexed as text, for generating HTML.
+pub(crate) fn escape_html(text: &str) -> String {
+    // `&` goes first, so that the ones the other replacements add aren't escaped again.
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Returns the value of the attribute `name` in the contents of a tag,
+/// e.g. `x` for `href` in `a href="x"`. Values can be double-quoted, single-quoted or unquoted.
+pub(crate) fn tag_attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = tag.split_once(char::is_whitespace)?.1;
+    loop {
+        rest = rest.trim_start();
+        let name_end = rest
+            .find(|c: char| c == '=' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let (attribute_name, after_name) = rest.split_at(name_end);
+        if attribute_name.is_empty() {
+            return None;
+        }
+
+        let after_name = after_name.trim_start();
+        let (value, after_value) = match after_name.strip_prefix('=') {
+            Some(value) => {
+                let value = value.trim_start();
+                match value.chars().next() {
+                    Some(quote @ ('"' | '\'')) => {
+                        let value = &value[1..];
+                        let end = value.find(quote).unwrap_or(value.len());
+                        (&value[..end], value.get(end + 1..).unwrap_or_default())
+                    }
+                    _ => value.split_at(value.find(char::is_whitespace).unwrap_or(value.len())),
+                }
+            }
+            // An attribute without a value.
+            None => ("", after_name),
+        };
+
+        if attribute_name.eq_ignore_ascii_case(name) {
+            return Some(value);
+        }
+        rest = after_value;
+    }
 }
 
 pub(crate) fn lex(body: &str, render: bool) -> Vec<Token> {
@@ -53,6 +102,7 @@ pub(crate) fn lex(body: &str, render: bool) -> Vec<Token> {
                 let parsed_entity = match current_entity.as_str() {
                     "&lt;" => Some('<'),
                     "&gt;" => Some('>'),
+                    "&amp;" => Some('&'),
                     _ => None,
                 };
 
@@ -108,6 +158,26 @@ mod tests {
         assert_eq!(parsed, expected);
     }
 
+    #[test]
+    fn escape() {
+        let text = "a &lt; <b> & c";
+        assert_eq!(
+            lex(&escape_html(text), true),
+            vec![Token::Text(text.to_string())]
+        );
+    }
+
+    #[test]
+    fn attributes() {
+        let tag = "a  class=link HREF = \"/a b\" download target='_blank'";
+        assert_eq!(tag_attribute(tag, "href"), Some("/a b"));
+        assert_eq!(tag_attribute(tag, "class"), Some("link"));
+        assert_eq!(tag_attribute(tag, "download"), Some(""));
+        assert_eq!(tag_attribute(tag, "target"), Some("_blank"));
+        assert_eq!(tag_attribute(tag, "title"), None);
+        assert_eq!(tag_attribute("a", "href"), None);
+    }
+
     #[test]
     fn skip_unknown_entities() {
         let example = "&potato;div&chips;";
@@ -1,4 +1,7 @@
+mod about;
 mod browser;
+mod content;
+mod directory;
 mod engine;
 mod layout;
 mod lex;
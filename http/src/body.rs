@@ -0,0 +1,539 @@
+//! Reading response bodies incrementally: undoing the transfer coding (the framing of
+//! the body on the connection), and then the content coding (its compression).
+
+use std::io;
+use std::io::{BufRead, Read, Write};
+use std::mem;
+
+use flate2::write::{DeflateDecoder, GzDecoder, ZlibDecoder};
+
+use crate::headers::Headers;
+use crate::request::ResponseError;
+
+/// How the end of a body is found (https://www.rfc-editor.org/rfc/rfc9112#section-6.3).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Framing {
+    /// This many bytes are left.
+    Length(u64),
+    /// The body comes in chunks, and this many bytes are left in the current one.
+    /// The next chunk's size is read once it reaches 0.
+    Chunked(u64),
+    /// The body goes on until the server closes the connection.
+    UntilClose,
+}
+
+/// Reads the body of a response from `R`, stopping at the end of the body
+/// and removing the chunked transfer coding if it has one.
+#[derive(Debug)]
+pub(crate) struct FramedReader<R> {
+    inner: R,
+    framing: Framing,
+    finished: bool,
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl<R: BufRead> FramedReader<R> {
+    /// Reads the body described by `headers`, if there is one (as some responses
+    /// never have a body, whatever the headers say).
+    pub(crate) fn new(inner: R, headers: &Headers, has_body: bool) -> Result<Self, ResponseError> {
+        let framing = if !has_body {
+            Framing::Length(0)
+        } else if headers.has_given_value("transfer-encoding", "chunked") == Some(true) {
+            Framing::Chunked(0)
+        } else {
+            // The two calls to transpose here are a bit awkward, but they help us deal
+            // with the whole Option<Result> thing and make sure
+            // we handle the errors from both not having a content-length header at all,
+            // and not having a valid value for the content-length.
+            let content_length = headers
+                .get_single_value("content-length")
+                .transpose()?
+                .map(|s| s.parse::<u64>())
+                .transpose()?;
+            content_length.map_or(Framing::UntilClose, Framing::Length)
+        };
+        Ok(Self {
+            inner,
+            framing,
+            finished: framing == Framing::Length(0),
+        })
+    }
+
+    /// Whether the whole body has been read.
+    pub(crate) fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    pub(crate) fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Reads the line with the size of the next chunk, ignoring any chunk extensions.
+    fn read_chunk_size(&mut self) -> io::Result<u64> {
+        let mut line = String::new();
+        if self.inner.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let size = line.split(';').next().unwrap_or_default().trim();
+        u64::from_str_radix(size, 16)
+            .map_err(|e| invalid_data(format!("invalid chunk size {size:?}: {e}")))
+    }
+
+    /// Reads the line that ends a chunk, or a trailer, and returns whether it was empty.
+    fn read_line_is_empty(&mut self) -> io::Result<bool> {
+        let mut line = String::new();
+        if self.inner.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(line.trim_end_matches(['\r', '\n']).is_empty())
+    }
+
+    /// Reads up to `remaining` bytes of the body into `buf`.
+    fn read_part(&mut self, buf: &mut [u8], remaining: u64) -> io::Result<usize> {
+        let max_len = buf
+            .len()
+            .min(usize::try_from(remaining).unwrap_or(usize::MAX));
+        let len = self.inner.read(&mut buf[..max_len])?;
+        if len == 0 && max_len > 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(len)
+    }
+}
+
+impl<R: BufRead> Read for FramedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.finished || buf.is_empty() {
+            return Ok(0);
+        }
+        match self.framing {
+            Framing::Length(remaining) => {
+                let len = self.read_part(buf, remaining)?;
+                let remaining = remaining - len as u64;
+                self.framing = Framing::Length(remaining);
+                self.finished = remaining == 0;
+                Ok(len)
+            }
+            Framing::Chunked(0) => {
+                let size = self.read_chunk_size()?;
+                if size == 0 {
+                    // Skip the trailers, up to the empty line at the end.
+                    while !self.read_line_is_empty()? {}
+                    self.finished = true;
+                    return Ok(0);
+                }
+                self.framing = Framing::Chunked(size);
+                self.read(buf)
+            }
+            Framing::Chunked(remaining) => {
+                let len = self.read_part(buf, remaining)?;
+                let remaining = remaining - len as u64;
+                self.framing = Framing::Chunked(remaining);
+                if remaining == 0 && !self.read_line_is_empty()? {
+                    return Err(invalid_data("a chunk is longer than its size".to_string()));
+                }
+                Ok(len)
+            }
+            Framing::UntilClose => {
+                let len = self.inner.read(buf)?;
+                self.finished = len == 0;
+                Ok(len)
+            }
+        }
+    }
+}
+
+/// Decompresses a body incrementally: compressed bytes go in,
+/// and whatever can be decompressed so far comes out.
+pub(crate) trait Decoder: Send {
+    fn decode(&mut self, input: &[u8]) -> io::Result<Vec<u8>>;
+
+    /// Called at the end of the body, to get the rest of the output.
+    fn finish(&mut self) -> io::Result<Vec<u8>>;
+}
+
+/// Implements [`Decoder`] for decoders that decode whatever is written to them into a `Vec`.
+macro_rules! impl_write_decoder {
+    ($decoder:ty, $finish:ident) => {
+        impl Decoder for $decoder {
+            fn decode(&mut self, input: &[u8]) -> io::Result<Vec<u8>> {
+                self.write_all(input)?;
+                Ok(mem::take(self.get_mut()))
+            }
+
+            fn finish(&mut self) -> io::Result<Vec<u8>> {
+                self.$finish()?;
+                Ok(mem::take(self.get_mut()))
+            }
+        }
+    };
+}
+
+impl_write_decoder!(GzDecoder<Vec<u8>>, try_finish);
+impl_write_decoder!(ZlibDecoder<Vec<u8>>, try_finish);
+impl_write_decoder!(DeflateDecoder<Vec<u8>>, try_finish);
+#[cfg(feature = "brotli")]
+impl_write_decoder!(brotli::DecompressorWriter<Vec<u8>>, close);
+#[cfg(feature = "zstd")]
+impl_write_decoder!(zstd::stream::write::Decoder<'static, Vec<u8>>, flush);
+
+/// Decodes `deflate`, which is meant to be zlib-wrapped, but which some servers send
+/// as raw deflate data instead. Which one it is can be told from the first two bytes.
+#[derive(Default)]
+struct EitherDeflateDecoder {
+    /// The bytes seen before there were enough to tell.
+    start: Vec<u8>,
+    decoder: Option<Box<dyn Decoder>>,
+}
+
+impl EitherDeflateDecoder {
+    /// Whether `header` is a zlib header: deflate with a valid window size and check bits
+    /// (https://www.rfc-editor.org/rfc/rfc1950#section-2.2).
+    fn is_zlib(header: &[u8]) -> bool {
+        match *header {
+            [first, second, ..] => {
+                first & 0x0f == 8
+                    && first >> 4 <= 7
+                    && u16::from_be_bytes([first, second]) % 31 == 0
+            }
+            _ => false,
+        }
+    }
+
+    /// Picks the decoder once there are enough bytes to tell, or it's the end of the body.
+    /// Returns the bytes to decode with it, if it was picked.
+    fn pick_decoder(
+        &mut self,
+        input: &[u8],
+        end: bool,
+    ) -> Option<(&mut Box<dyn Decoder>, Vec<u8>)> {
+        if self.decoder.is_some() {
+            return self
+                .decoder
+                .as_mut()
+                .map(|decoder| (decoder, input.to_vec()));
+        }
+        self.start.extend(input);
+        if self.start.len() < 2 && !end {
+            return None;
+        }
+        let start = mem::take(&mut self.start);
+        let decoder: Box<dyn Decoder> = if Self::is_zlib(&start) {
+            Box::new(ZlibDecoder::new(vec![]))
+        } else {
+            Box::new(DeflateDecoder::new(vec![]))
+        };
+        Some((self.decoder.insert(decoder), start))
+    }
+}
+
+impl Decoder for EitherDeflateDecoder {
+    fn decode(&mut self, input: &[u8]) -> io::Result<Vec<u8>> {
+        match self.pick_decoder(input, false) {
+            Some((decoder, input)) => decoder.decode(&input),
+            None => Ok(vec![]),
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<Vec<u8>> {
+        let Some((decoder, input)) = self.pick_decoder(&[], true) else {
+            return Ok(vec![]);
+        };
+        let mut output = decoder.decode(&input)?;
+        output.extend(decoder.finish()?);
+        Ok(output)
+    }
+}
+
+/// The content codings we can decode, in order of preference,
+/// for the `Accept-Encoding` header of requests.
+pub(crate) fn accept_encoding() -> String {
+    let mut encodings = vec!["gzip", "deflate"];
+    if cfg!(feature = "brotli") {
+        encodings.push("br");
+    }
+    if cfg!(feature = "zstd") {
+        encodings.push("zstd");
+    }
+    encodings.join(", ")
+}
+
+fn decoder(encoding: &str) -> Result<Option<Box<dyn Decoder>>, ResponseError> {
+    Ok(Some(match encoding {
+        "identity" => return Ok(None),
+        "gzip" | "x-gzip" => Box::new(GzDecoder::new(vec![])),
+        "deflate" => Box::<EitherDeflateDecoder>::default(),
+        #[cfg(feature = "brotli")]
+        "br" => Box::new(brotli::DecompressorWriter::new(vec![], 4096)),
+        #[cfg(feature = "zstd")]
+        "zstd" => Box::new(zstd::stream::write::Decoder::new(vec![])?),
+        _ => {
+            return Err(ResponseError::UnsupportedContentEncoding(
+                encoding.to_string(),
+            ))
+        }
+    }))
+}
+
+/// The decoders for the `Content-Encoding` in `headers`. The encodings are listed
+/// in the order they were applied, so they are decoded the other way round.
+pub(crate) fn decoders(headers: &Headers) -> Result<Vec<Box<dyn Decoder>>, ResponseError> {
+    let encodings = headers
+        .get("content-encoding")
+        .into_iter()
+        .flatten()
+        .flat_map(|value| value.split(','))
+        .map(|encoding| encoding.trim().to_ascii_lowercase())
+        .filter(|encoding| !encoding.is_empty())
+        .collect::<Vec<_>>();
+    encodings
+        .iter()
+        .rev()
+        .filter_map(|encoding| decoder(encoding).transpose())
+        .collect()
+}
+
+/// Reads a body from `R`, decoding its content coding.
+pub(crate) struct DecodedReader<R> {
+    inner: R,
+    decoders: Vec<Box<dyn Decoder>>,
+    /// Decoded bytes that haven't been read yet, from `position` on.
+    decoded: Vec<u8>,
+    position: usize,
+    finished: bool,
+}
+
+impl<R: Read> DecodedReader<R> {
+    pub(crate) fn new(inner: R, decoders: Vec<Box<dyn Decoder>>) -> Self {
+        Self {
+            inner,
+            decoders,
+            decoded: vec![],
+            position: 0,
+            finished: false,
+        }
+    }
+
+    /// Decodes the next bytes read from `inner` into `decoded`.
+    fn decode_more(&mut self) -> io::Result<()> {
+        let mut input = [0; 8192];
+        let len = self.inner.read(&mut input)?;
+        let mut data = input[..len].to_vec();
+        for decoder in &mut self.decoders {
+            data = decoder.decode(&data)?;
+            if len == 0 {
+                data.extend(decoder.finish()?);
+            }
+        }
+        self.decoded = data;
+        self.position = 0;
+        self.finished = len == 0;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecodedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.decoders.is_empty() {
+            return self.inner.read(buf);
+        }
+        while self.position == self.decoded.len() {
+            if self.finished || buf.is_empty() {
+                return Ok(0);
+            }
+            self.decode_more()?;
+        }
+        let len = buf.len().min(self.decoded.len() - self.position);
+        buf[..len].copy_from_slice(&self.decoded[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    /// A reader that returns at most `max_len` bytes at a time,
+    /// like a connection that the body trickles in on.
+    struct Trickle<'a> {
+        bytes: &'a [u8],
+        max_len: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.max_len).min(self.bytes.len());
+            buf[..len].copy_from_slice(&self.bytes[..len]);
+            self.bytes = &self.bytes[len..];
+            Ok(len)
+        }
+    }
+
+    fn headers(pairs: &[(&str, &str)]) -> Headers {
+        let mut headers = Headers::default();
+        for (key, value) in pairs {
+            headers.add(key, value);
+        }
+        headers
+    }
+
+    /// Reads the whole body from `bytes`, a few bytes at a time,
+    /// and returns it along with whatever comes after it.
+    fn read_body(bytes: &[u8], headers: &Headers) -> Result<(Vec<u8>, Vec<u8>)> {
+        let inner = io::BufReader::with_capacity(3, Trickle { bytes, max_len: 5 });
+        let mut reader =
+            DecodedReader::new(FramedReader::new(inner, headers, true)?, decoders(headers)?);
+        let mut body = vec![];
+        reader.read_to_end(&mut body)?;
+        assert!(reader.inner.is_finished());
+        let mut rest = vec![];
+        reader.inner.into_inner().read_to_end(&mut rest)?;
+        Ok((body, rest))
+    }
+
+    #[test]
+    fn framing() -> Result<()> {
+        let length = headers(&[("content-length", "5")]);
+        assert_eq!(
+            read_body(b"hello, world", &length)?,
+            (b"hello".to_vec(), b", world".to_vec())
+        );
+
+        let chunked = headers(&[("transfer-encoding", "chunked")]);
+        let body = b"4\r\nWiki\r\n7;name=value\r\npedia i\r\nB\r\nn \r\nchunks.\r\n0\r\nExpires: never\r\n\r\nnext";
+        assert_eq!(
+            read_body(body, &chunked)?,
+            (b"Wikipedia in \r\nchunks.".to_vec(), b"next".to_vec())
+        );
+        assert!(read_body(b"4\r\nWikipedia\r\n0\r\n\r\n", &chunked).is_err());
+        assert!(read_body(b"4\r\nWi", &chunked).is_err());
+        assert!(read_body(b"zz\r\n", &chunked).is_err());
+
+        assert_eq!(
+            read_body(b"until the end", &Headers::default())?,
+            (b"until the end".to_vec(), vec![])
+        );
+        assert!(read_body(b"hel", &length).is_err());
+
+        let reader = FramedReader::new(&b"body"[..], &length, false)?;
+        assert!(reader.is_finished());
+        Ok(())
+    }
+
+    /// Splits `bytes` into small chunks, with the chunked transfer coding.
+    fn chunked(bytes: &[u8]) -> Vec<u8> {
+        let mut chunked = vec![];
+        for chunk in bytes.chunks(7) {
+            chunked.extend(format!("{:x}\r\n", chunk.len()).as_bytes());
+            chunked.extend(chunk);
+            chunked.extend(b"\r\n");
+        }
+        chunked.extend(b"0\r\n\r\n");
+        chunked
+    }
+
+    fn gzip(bytes: &[u8]) -> Result<Vec<u8>> {
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(bytes)?;
+        Ok(encoder.finish()?)
+    }
+
+    #[cfg(feature = "brotli")]
+    fn brotli(bytes: &[u8]) -> Result<Vec<u8>> {
+        let mut encoder = brotli::CompressorWriter::new(vec![], 4096, 5, 22);
+        encoder.write_all(bytes)?;
+        Ok(encoder.into_inner())
+    }
+
+    /// Reads `encoded`, chunked, as a body with the given `Content-Encoding`.
+    fn decode(encoded: &[u8], content_encoding: &str) -> Result<Vec<u8>> {
+        let headers = headers(&[
+            ("transfer-encoding", "chunked"),
+            ("content-encoding", content_encoding),
+        ]);
+        let (body, rest) = read_body(&chunked(encoded), &headers)?;
+        assert!(rest.is_empty());
+        Ok(body)
+    }
+
+    #[test]
+    fn gzip_decoding() -> Result<()> {
+        let text = "hello ".repeat(10_000);
+        assert_eq!(decode(&gzip(text.as_bytes())?, "gzip")?, text.as_bytes());
+        assert_eq!(decode(&gzip(b"")?, "x-gzip")?, b"");
+        assert!(decode(b"not gzip", "gzip").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn deflate_decoding() -> Result<()> {
+        let text = "hello ".repeat(1000);
+        let mut zlib = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+        zlib.write_all(text.as_bytes())?;
+        assert_eq!(decode(&zlib.finish()?, "deflate")?, text.as_bytes());
+
+        let mut raw = flate2::write::DeflateEncoder::new(vec![], flate2::Compression::default());
+        raw.write_all(text.as_bytes())?;
+        assert_eq!(decode(&raw.finish()?, "deflate")?, text.as_bytes());
+
+        assert!(decode(b"x", "deflate").is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "brotli")]
+    #[test]
+    fn brotli_decoding() -> Result<()> {
+        let text = "hello ".repeat(1000);
+        assert_eq!(decode(&brotli(text.as_bytes())?, "br")?, text.as_bytes());
+        Ok(())
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_decoding() -> Result<()> {
+        let text = "hello ".repeat(1000);
+        let encoded = zstd::encode_all(text.as_bytes(), 3)?;
+        assert_eq!(decode(&encoded, "zstd")?, text.as_bytes());
+        Ok(())
+    }
+
+    #[cfg(feature = "brotli")]
+    #[test]
+    fn stacked_encodings() -> Result<()> {
+        let text = b"first gzipped, then brotli";
+        let encoded = brotli(&gzip(text)?)?;
+        assert_eq!(decode(&encoded, "gzip, br")?, text);
+        assert_eq!(decode(&encoded, "identity, gzip, BR")?, text);
+        // The same, with each encoding on its own header line.
+        let mut headers = headers(&[("content-encoding", "gzip")]);
+        headers.add("content-encoding", "br");
+        let (body, _) = read_body(&encoded, &headers)?;
+        assert_eq!(body, text);
+        Ok(())
+    }
+
+    #[test]
+    fn unsupported_encodings() {
+        let headers = headers(&[("content-encoding", "gzip, compress")]);
+        assert_eq!(
+            decoders(&headers).map(|_| ()).map_err(|e| e.to_string()),
+            Err("unsupported content encoding: compress".to_string())
+        );
+    }
+
+    #[test]
+    fn accept_encoding_lists_decoders() {
+        let accept_encoding = accept_encoding();
+        assert!(accept_encoding.starts_with("gzip, deflate"));
+        assert_eq!(accept_encoding.contains("br"), cfg!(feature = "brotli"));
+        assert_eq!(accept_encoding.contains("zstd"), cfg!(feature = "zstd"));
+        for encoding in accept_encoding.split(", ") {
+            assert!(decoder(encoding).is_ok_and(|decoder| decoder.is_some()));
+        }
+    }
+}
@@ -1,55 +1,284 @@
+//! A cache for responses to GET and HEAD requests, following the rules of
+//! https://www.rfc-editor.org/rfc/rfc9111: responses are used while they are fresh,
+//! and revalidated with the server (with `If-None-Match` or `If-Modified-Since`)
+//! once they are stale. Responses with a `Vary` header are only used for requests
+//! whose headers match the ones they were a response to.
+//!
+//! Responses are partitioned by the top-level site they were loaded for, so that one
+//! site can't tell whether another has been visited by what's already cached.
+//!
+//! The cache keeps to a budget of bytes, dropping expired responses and then
+//! the least recently used ones when it goes over. It can also be kept on disk,
+//! so that it lasts between runs.
+
+mod disk;
+
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::{Arc, Weak};
-use std::time::Duration;
 
 use anyhow::{anyhow, Result};
-use chrono::{DateTime, FixedOffset, Local, TimeDelta};
+use chrono::{DateTime, TimeDelta, Utc};
+
+use crate::headers::{CacheControl, Headers};
+use crate::request::{Request, RequestMethod, Response};
+use disk::DiskStore;
+use octo_url::{Site, WebUrl};
+
+/// How many bytes of responses a cache keeps, unless it's given another budget.
+pub const DEFAULT_MAX_SIZE: usize = 64 * 1024 * 1024;
+
+/// The longest a response is considered fresh for when it doesn't say,
+/// however long ago it was last modified.
+const MAX_HEURISTIC_FRESHNESS: TimeDelta = TimeDelta::weeks(1);
+
+/// The largest number of seconds that a cache has to handle, where bigger ones
+/// are treated as this (https://www.rfc-editor.org/rfc/rfc9111#section-1.2.2).
+const MAX_DELTA_SECONDS: u64 = 1 << 31;
+
+/// Status codes whose responses can be cached, with a heuristic freshness lifetime,
+/// even if they don't say how long they stay fresh
+/// (https://www.rfc-editor.org/rfc/rfc9110#section-15.1).
+const HEURISTICALLY_CACHEABLE: [u16; 12] =
+    [200, 203, 204, 206, 300, 301, 308, 404, 405, 410, 414, 501];
+
+/// Headers of a `304 Not Modified` response that aren't copied to the stored response,
+/// as they describe the message rather than the stored content
+/// (https://www.rfc-editor.org/rfc/rfc9111#section-3.2).
+const NOT_UPDATED_HEADERS: [&str; 7] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "te",
+    "transfer-encoding",
+    "upgrade",
+    "content-length",
+];
+
+fn seconds(seconds: u64) -> TimeDelta {
+    TimeDelta::seconds(seconds.min(MAX_DELTA_SECONDS) as i64)
+}
+
+/// The longest age or freshness lifetime that's stored for a response.
+fn max_delta() -> TimeDelta {
+    seconds(MAX_DELTA_SECONDS)
+}
+
+/// The value of the `key` header of a request, normalized so that
+/// it can be compared with another request's.
+fn request_header(headers: &Headers, key: &str) -> Option<String> {
+    let values = headers.get(key)?;
+    Some(
+        values
+            .iter()
+            .flat_map(|value| value.split(','))
+            .map(|value| value.split_whitespace().collect::<Vec<_>>().join(" "))
+            .collect::<Vec<_>>()
+            .join(", "),
+    )
+}
 
-use crate::request::Response;
-use octo_url::WebUrl;
+/// How many bytes of memory `response` takes up, counting its headers and body.
+fn response_size(response: &Response) -> usize {
+    let headers = response
+        .headers
+        .iter()
+        .map(|(key, values)| {
+            values
+                .iter()
+                .map(|value| key.len() + value.len())
+                .sum::<usize>()
+        })
+        .sum::<usize>();
+    headers + response.body().map_or(0, <[u8]>::len)
+}
 
-type ResponseCacheProperties = (DateTime<FixedOffset>, TimeDelta);
+fn parse_date(headers: &Headers, key: &str) -> Option<DateTime<Utc>> {
+    let value = headers.get_single_value(key)?.ok()?;
+    DateTime::parse_from_rfc2822(value)
+        .ok()
+        .map(|date| date.to_utc())
+}
 
 #[derive(Debug, PartialEq)]
-struct ResponseWithCacheProperties {
+struct StoredResponse {
     response: Arc<Response>,
-    date: DateTime<FixedOffset>,
-    // Store as TimeDelta instead of Duration to avoid recomputing it and handling potential
-    // errors every time we query the cache.
-    max_age: TimeDelta,
+    /// When the response was received.
+    response_time: DateTime<Utc>,
+    /// How old the response already was when it was received
+    /// (https://www.rfc-editor.org/rfc/rfc9111#section-4.2.3).
+    initial_age: TimeDelta,
+    /// How long the response stays fresh for, from when it was generated.
+    freshness_lifetime: TimeDelta,
+    /// Whether the response has to be revalidated every time it's used.
+    no_cache: bool,
+    /// Whether the response can't be used once it's stale, even if the server can't be reached.
+    must_revalidate: bool,
+    /// The request headers named in the response's `Vary`, with the values they had
+    /// in its request (https://www.rfc-editor.org/rfc/rfc9111#section-4.1).
+    vary: Vec<(String, Option<String>)>,
+    /// How many bytes the response's headers and body take up.
+    size: usize,
+    /// When the response was last stored or used, by the cache's clock.
+    last_used: u64,
+    /// What the body's file is called, if the cache is kept on disk.
+    id: u64,
+    /// Whether the body is still on disk, and `response` only has the head.
+    unloaded_body: bool,
 }
 
-impl ResponseWithCacheProperties {
-    fn parse_cache_properties(response: &Response) -> Result<ResponseCacheProperties> {
+impl StoredResponse {
+    /// Works out how long `response` to `request` stays fresh, where the request
+    /// was sent at `request_time`. Fails if the response can't be stored at all.
+    fn new(
+        request: &Request,
+        response: Response,
+        request_time: DateTime<Utc>,
+        shared: bool,
+    ) -> Result<Self> {
+        let response_time = Utc::now();
         let headers = &response.headers;
+        let cache_control = CacheControl::from_headers(headers);
 
-        let date = headers
-            .get_single_value("date")
-            .ok_or_else(|| anyhow!("Missing date in headers"))?
-            .map(|s| DateTime::parse_from_rfc2822(s.as_str()))??;
-
-        let max_age = if let Some(Ok(cache_control)) = headers.get_single_value("cache-control") {
-            let max_age = cache_control
-                .strip_prefix("max-age=")
-                .ok_or_else(|| anyhow!("Invalid value for cache-control: {cache_control}"))?;
-            let max_age = max_age.parse::<u64>().map(Duration::from_secs)?;
+        if !matches!(request.method(), RequestMethod::Get | RequestMethod::Head) {
+            return Err(anyhow!(
+                "Only responses to GET and HEAD requests are stored"
+            ));
+        }
+        let vary = headers
+            .get("vary")
+            .into_iter()
+            .flatten()
+            .flat_map(|value| value.split(','))
+            .map(|name| name.trim().to_ascii_lowercase())
+            .filter(|name| !name.is_empty())
+            .collect::<Vec<_>>();
+        if vary.iter().any(|name| name == "*") {
+            return Err(anyhow!("The response varies on more than request headers"));
+        }
+        if cache_control.no_store {
+            return Err(anyhow!("The response says not to store it"));
+        }
+        if shared && cache_control.private.is_some() {
+            return Err(anyhow!("The response is private"));
+        }
+        if response.status_code() == 206 || response.status_code() == 304 {
+            return Err(anyhow!(
+                "Partial and 304 responses can't be stored on their own"
+            ));
+        }
 
-            TimeDelta::from_std(max_age)?
-        } else {
-            return Err(anyhow!("No cache-control header/value: {headers:?}"));
+        let date = parse_date(headers, "date").unwrap_or(response_time);
+        let explicit_lifetime = cache_control
+            .s_maxage
+            .filter(|_| shared)
+            .or(cache_control.max_age)
+            .map(seconds)
+            // An invalid `Expires` means that the response has already expired.
+            .or_else(|| {
+                headers.get("expires")?;
+                Some(
+                    parse_date(headers, "expires")
+                        .map_or(TimeDelta::zero(), |expires| expires - date),
+                )
+            });
+        // Responses that don't say how long they stay fresh can only be stored
+        // if they say they can be, or if their status code allows it
+        // (https://www.rfc-editor.org/rfc/rfc9111#section-3).
+        let cacheable_without_lifetime = HEURISTICALLY_CACHEABLE.contains(&response.status_code())
+            || cache_control.public
+            || (cache_control.private.is_some() && !shared);
+        if explicit_lifetime.is_none() && !cacheable_without_lifetime {
+            return Err(anyhow!("The response doesn't say it can be cached"));
+        }
+        let heuristic_lifetime = || {
+            // 10% of the time since the response was last modified.
+            let last_modified = parse_date(headers, "last-modified")?;
+            Some(((date - last_modified) / 10).min(MAX_HEURISTIC_FRESHNESS))
         };
+        let freshness_lifetime = explicit_lifetime
+            .or_else(heuristic_lifetime)
+            .unwrap_or_default()
+            .clamp(TimeDelta::zero(), max_delta());
 
-        Ok((date, max_age))
-    }
+        let has_validators =
+            headers.get("etag").is_some() || headers.get("last-modified").is_some();
+        if freshness_lifetime.is_zero() && !has_validators {
+            return Err(anyhow!(
+                "The response is stale right away, and can't be revalidated"
+            ));
+        }
+
+        let apparent_age = (response_time - date).max(TimeDelta::zero());
+        let age_value = headers
+            .get_single_value("age")
+            .and_then(Result::ok)
+            .and_then(|age| age.parse::<u64>().ok())
+            .map_or(TimeDelta::zero(), seconds);
+        let response_delay = response_time - request_time;
+        let initial_age = apparent_age
+            .max(age_value + response_delay)
+            .min(max_delta());
 
-    fn new(response: Response) -> Result<Self> {
-        let (date, max_age) = Self::parse_cache_properties(&response)?;
+        let size = response_size(&response);
         Ok(Self {
             response: Arc::new(response),
-            date,
-            max_age,
+            response_time,
+            initial_age,
+            freshness_lifetime,
+            no_cache: cache_control.no_cache.is_some(),
+            must_revalidate: cache_control.must_revalidate
+                || (shared && (cache_control.proxy_revalidate || cache_control.s_maxage.is_some())),
+            vary: vary
+                .into_iter()
+                .map(|name| {
+                    let value = request_header(request.headers(), &name);
+                    (name, value)
+                })
+                .collect(),
+            size,
+            last_used: 0,
+            id: 0,
+            unloaded_body: false,
         })
     }
+
+    /// Whether `request` has the same values as the stored response's request
+    /// for the headers the response varies on.
+    fn matches(&self, request: &Request) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| request_header(request.headers(), name) == *value)
+    }
+
+    /// How old the response is now, counting from when it was generated.
+    fn age(&self) -> TimeDelta {
+        self.initial_age
+            .checked_add(&(Utc::now() - self.response_time))
+            .unwrap_or(TimeDelta::max_value())
+    }
+
+    fn is_fresh(&self) -> bool {
+        !self.no_cache && self.age() < self.freshness_lifetime
+    }
+
+    /// Whether the response is stale, and can't be revalidated, so it's of no more use.
+    fn is_expired(&self) -> bool {
+        !self.is_fresh() && self.conditional_headers().is_empty()
+    }
+
+    /// The headers that make a request conditional on the stored response being out of date.
+    fn conditional_headers(&self) -> Vec<(&'static str, String)> {
+        let headers = &self.response.headers;
+        let mut conditional_headers = vec![];
+        if let Some(Ok(etag)) = headers.get_single_value("etag") {
+            conditional_headers.push(("If-None-Match", etag.clone()));
+        }
+        if let Some(Ok(last_modified)) = headers.get_single_value("last-modified") {
+            conditional_headers.push(("If-Modified-Since", last_modified.clone()));
+        }
+        conditional_headers
+    }
 }
 
 #[derive(Default)]
@@ -77,61 +306,1032 @@ impl MaybeCachedResponse {
     }
 }
 
-#[derive(Debug, Default, PartialEq)]
+/// What the cache has for a request.
+#[derive(Debug, PartialEq)]
+pub enum Lookup {
+    /// A fresh response, which can be used without asking the server.
+    Fresh(Response),
+    /// A stale response, which can be revalidated by sending these headers with the request.
+    /// If the server answers `304 Not Modified`, [`Cache::update`] gives the stored response.
+    Stale(Vec<(&'static str, String)>),
+    /// Nothing that can be used.
+    Miss,
+}
+
+/// What responses are stored by: the top-level site they were loaded for,
+/// and the method and URL of their request.
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+struct CacheKey {
+    site: Site,
+    method: RequestMethod,
+    url: WebUrl,
+}
+
+impl CacheKey {
+    fn new(site: &Site, request: &Request) -> Self {
+        Self {
+            site: site.clone(),
+            method: request.method(),
+            url: request.url().clone(),
+        }
+    }
+}
+
+/// How well a cache is doing, for showing to the user.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// How many lookups found a fresh response.
+    pub hits: u64,
+    /// How many lookups found nothing, or only a stale response.
+    pub misses: u64,
+    /// How many responses were dropped, to keep to the budget.
+    pub evictions: u64,
+    /// How many responses are stored.
+    pub entries: usize,
+    /// How many bytes the stored responses take up.
+    pub size: usize,
+    /// How many bytes of responses the cache keeps at most.
+    pub max_size: usize,
+}
+
+/// A cache of responses to GET and HEAD requests, by the top-level site they're for
+/// and their method and URL, with a response for each combination of the request
+/// headers they vary on. It's a private cache
+/// (like a browser's) by default, and can also be a shared one, which doesn't store
+/// `private` responses.
+#[derive(Debug, PartialEq)]
 pub struct Cache {
-    cache: HashMap<WebUrl, ResponseWithCacheProperties>,
+    cache: HashMap<CacheKey, Vec<StoredResponse>>,
+    shared: bool,
+    /// How many bytes the stored responses take up.
+    size: usize,
+    max_size: usize,
+    /// Counts lookups and insertions, to tell which responses were used least recently.
+    clock: u64,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+    disk: Option<DiskStore>,
+    /// The id of the next response to be stored.
+    next_id: u64,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self {
+            cache: HashMap::new(),
+            shared: false,
+            size: 0,
+            max_size: DEFAULT_MAX_SIZE,
+            clock: 0,
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+            disk: None,
+            next_id: 0,
+        }
+    }
 }
 
 impl Cache {
-    pub fn insert(&mut self, url: WebUrl, response: Response) -> Result<()> {
-        let response_with_cache_properties = ResponseWithCacheProperties::new(response)?;
-        self.cache.insert(url, response_with_cache_properties);
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes the cache keep at most `max_size` bytes of responses.
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self.evict();
+        self
+    }
+
+    /// Keeps the cache in `directory`, starting with the responses stored there before.
+    /// Only their heads are read now, and bodies are read the first time they're used.
+    /// Entries that can't be read are dropped.
+    pub fn with_directory(mut self, directory: impl Into<PathBuf>) -> Result<Self> {
+        let disk = DiskStore::open(directory.into())?;
+        for (key, stored_response) in disk.load()? {
+            self.size += stored_response.size;
+            self.clock = self.clock.max(stored_response.last_used);
+            self.next_id = self.next_id.max(stored_response.id + 1);
+            self.cache.entry(key).or_default().push(stored_response);
+        }
+        self.disk = Some(disk);
+        self.evict();
+        self.write_index()?;
+        Ok(self)
+    }
+
+    fn write_index(&mut self) -> Result<()> {
+        if let Some(disk) = &mut self.disk {
+            let entries = self
+                .cache
+                .iter()
+                .flat_map(|(key, variants)| variants.iter().map(move |variant| (key, variant)));
+            disk.write_index(entries)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the index whole again if too much of it is out of date.
+    fn compact_index(&mut self) -> Result<()> {
+        let entries = self.cache.values().map(Vec::len).sum();
+        if self
+            .disk
+            .as_ref()
+            .is_some_and(|disk| disk.needs_compacting(entries))
+        {
+            self.write_index()?;
+        }
         Ok(())
     }
 
-    #[allow(dead_code)]
-    fn remove(&mut self, url: &WebUrl) -> Option<Response> {
-        self.cache
-            .remove(url)
-            .map(|r| Arc::unwrap_or_clone(r.response))
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            evictions: self.evictions,
+            entries: self.cache.values().map(Vec::len).sum(),
+            size: self.size,
+            max_size: self.max_size,
+        }
+    }
+
+    /// A cache shared between users, like a proxy's, which follows `s-maxage`
+    /// and `proxy-revalidate`, and doesn't store `private` responses.
+    pub fn shared() -> Self {
+        Self {
+            shared: true,
+            ..Self::default()
+        }
+    }
+
+    /// Stores `response` to `request`, which was sent at `request_time` for a page on
+    /// `site`, replacing any
+    /// response stored for the same request before. Fails if the response can't be
+    /// stored, e.g. because of `Cache-Control: no-store` or because it's bigger than
+    /// the whole budget, in which case the old one is removed. Other responses are
+    /// dropped if the cache goes over its budget.
+    pub fn insert(
+        &mut self,
+        site: &Site,
+        request: &Request,
+        response: Response,
+        request_time: DateTime<Utc>,
+    ) -> Result<()> {
+        let key = CacheKey::new(site, request);
+        self.remove_where(|variant_key, variant| *variant_key == key && variant.matches(request));
+        let mut stored_response =
+            StoredResponse::new(request, response, request_time, self.shared)?;
+        if stored_response.size > self.max_size {
+            return Err(anyhow!("The response is bigger than the whole cache"));
+        }
+        self.clock += 1;
+        stored_response.last_used = self.clock;
+        stored_response.id = self.next_id;
+        self.next_id += 1;
+        if let Some(disk) = &mut self.disk {
+            if let Some(body) = stored_response.response.body() {
+                disk.write_body(stored_response.id, body)?;
+            }
+            disk.append_entry(&key, &stored_response)?;
+        }
+        self.size += stored_response.size;
+        self.cache.entry(key).or_default().push(stored_response);
+        self.evict();
+        self.compact_index()
+    }
+
+    /// Removes the stored responses that `predicate` is true for,
+    /// and returns how many there were.
+    fn remove_where(&mut self, predicate: impl Fn(&CacheKey, &StoredResponse) -> bool) -> usize {
+        let mut removed = vec![];
+        self.cache.retain(|key, variants| {
+            variants.retain(|variant| {
+                let remove = predicate(key, variant);
+                if remove {
+                    self.size -= variant.size;
+                    removed.push(variant.id);
+                }
+                !remove
+            });
+            !variants.is_empty()
+        });
+        if let Some(disk) = &mut self.disk {
+            // A body that's left behind is removed the next time the cache is opened,
+            // and a response whose removal isn't in the index is dropped when it's used,
+            // as its body is gone.
+            for id in &removed {
+                let _ = disk.remove_body(*id);
+            }
+            let _ = disk.append_removals(&removed);
+        }
+        removed.len()
+    }
+
+    /// Removes the responses that are stale and can't be revalidated,
+    /// and returns how many there were.
+    pub fn remove_expired(&mut self) -> usize {
+        let removed = self.remove_where(|_, variant| variant.is_expired());
+        self.evictions += removed as u64;
+        removed
+    }
+
+    /// Gets the cache back within its budget, by removing expired responses,
+    /// and then the least recently used ones.
+    fn evict(&mut self) {
+        if self.size <= self.max_size {
+            return;
+        }
+        self.remove_expired();
+        while self.size > self.max_size {
+            let least_recently_used = self
+                .cache
+                .values()
+                .flatten()
+                .map(|variant| variant.last_used)
+                .min();
+            let Some(last_used) = least_recently_used else {
+                break;
+            };
+            self.evictions += self.remove_where(|_, variant| variant.last_used == last_used) as u64;
+        }
+    }
+
+    /// The stored response that `request` can use, fresh or not, marked as just used.
+    /// Its body is read from disk if it hasn't been yet, and if that fails, it's removed.
+    fn use_stored_response(&mut self, site: &Site, request: &Request) -> Option<&StoredResponse> {
+        self.clock += 1;
+        let key = CacheKey::new(site, request);
+        let variants = self.cache.get_mut(&key)?;
+        let index = variants
+            .iter()
+            .position(|variant| variant.matches(request))?;
+        let stored_response = &mut variants[index];
+        stored_response.last_used = self.clock;
+        if stored_response.unloaded_body {
+            let body = self
+                .disk
+                .as_ref()
+                .and_then(|disk| disk.read_body(stored_response.id).ok());
+            let response =
+                body.map(|body| Response::clone(&stored_response.response).with_body(body));
+            match response {
+                Some(response) if response_size(&response) == stored_response.size => {
+                    stored_response.response = Arc::new(response);
+                    stored_response.unloaded_body = false;
+                }
+                _ => {
+                    let id = stored_response.id;
+                    self.remove_where(|_, variant| variant.id == id);
+                    return None;
+                }
+            }
+        }
+        self.cache.get(&key)?.get(index)
+    }
+
+    /// The stored response for `request` from a page on `site`, if it's fresh.
+    pub fn get(&mut self, site: &Site, request: &Request) -> MaybeCachedResponse {
+        let response = match self.use_stored_response(site, request) {
+            Some(stored_response) if stored_response.is_fresh() => {
+                MaybeCachedResponse::new(&stored_response.response)
+            }
+            _ => MaybeCachedResponse::default(),
+        };
+        self.count(response.inner.is_some());
+        response
+    }
+
+    fn count(&mut self, hit: bool) {
+        if hit {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
     }
 
-    pub fn get(&self, url: &WebUrl) -> MaybeCachedResponse {
-        if let Some(response_with_cache_props) = self.cache.get(url) {
-            let current_time = Local::now().fixed_offset();
-            let delta = current_time - response_with_cache_props.date;
-            if delta < response_with_cache_props.max_age {
-                return MaybeCachedResponse::new(&response_with_cache_props.response);
+    /// Looks up `request` from a page on `site`, to see whether it has to be sent,
+    /// and whether it can be a conditional one.
+    pub fn lookup(&mut self, site: &Site, request: &Request) -> Lookup {
+        let lookup = match self.use_stored_response(site, request) {
+            None => Lookup::Miss,
+            Some(stored_response) if stored_response.is_fresh() => {
+                Lookup::Fresh(Response::clone(&stored_response.response))
             }
+            Some(stored_response) => match stored_response.conditional_headers() {
+                conditional_headers if conditional_headers.is_empty() => Lookup::Miss,
+                conditional_headers => Lookup::Stale(conditional_headers),
+            },
+        };
+        self.count(matches!(lookup, Lookup::Fresh(_)));
+        lookup
+    }
+
+    /// Updates the stored response for `request` with the headers of `not_modified`,
+    /// a `304` response to the conditional version of `request` sent at `request_time`,
+    /// and returns it. Returns `None` if there's no stored response that `not_modified`
+    /// is for, which is the case if its `ETag` is a different one.
+    pub fn update(
+        &mut self,
+        site: &Site,
+        request: &Request,
+        not_modified: &Response,
+        request_time: DateTime<Utc>,
+    ) -> Option<Response> {
+        let stored = &self.use_stored_response(site, request)?.response;
+        let etag_of = |response: &Response| {
+            response
+                .headers
+                .get_single_value("etag")
+                .and_then(Result::ok)
+                .cloned()
+        };
+        if etag_of(not_modified).is_some_and(|etag| Some(etag) != etag_of(stored)) {
+            return None;
         }
 
-        MaybeCachedResponse::default()
+        let mut headers = stored.headers.clone();
+        for (key, values) in not_modified.headers.iter() {
+            if NOT_UPDATED_HEADERS.contains(&key.as_str()) {
+                continue;
+            }
+            headers.remove(key);
+            for value in values {
+                headers.add(key, value);
+            }
+        }
+        let response = Response::clone(stored).with_headers(headers);
+        // If the new headers say not to store it any more, it can still be used this once.
+        let _ = self.insert(site, request, response.clone(), request_time);
+        Some(response)
+    }
+
+    /// The stored response for `request` from a page on `site`, even if it's stale, for when
+    /// the server can't be reached. Responses that have to be revalidated once they're stale
+    /// aren't returned.
+    pub fn get_stale(&mut self, site: &Site, request: &Request) -> Option<Response> {
+        self.use_stored_response(site, request)
+            .filter(|stored_response| !stored_response.must_revalidate && !stored_response.no_cache)
+            .map(|stored_response| Response::clone(&stored_response.response))
+    }
+
+    /// Removes everything stored for pages on `site`, and every response from `site`
+    /// stored for other sites, and returns how many responses there were.
+    pub fn clear_site(&mut self, site: &Site) -> usize {
+        self.remove_where(|key, _| key.site == *site || key.url.origin().site() == *site)
+    }
+}
+
+/// A response in the cache, as returned by iterating over a [`Cache`].
+#[derive(Debug)]
+pub struct CacheEntry<'a> {
+    /// The top-level site the response was loaded for.
+    pub site: &'a Site,
+    pub method: RequestMethod,
+    pub url: &'a WebUrl,
+    /// The stored response, without its body if that's still on disk.
+    pub response: &'a Response,
+    /// How long ago the response was generated, as far as the cache can tell.
+    pub age: TimeDelta,
+    /// How long the response stays fresh, from `Cache-Control: max-age`, `Expires`,
+    /// or a guess based on `Last-Modified`.
+    pub max_age: TimeDelta,
+    /// Whether the response has to be revalidated every time, because of `no-cache`.
+    pub no_cache: bool,
+}
+
+impl CacheEntry<'_> {
+    /// Whether `get` would still return this response.
+    pub fn is_fresh(&self) -> bool {
+        !self.no_cache && self.age < self.max_age
     }
 }
 
 pub struct Iter<'a> {
-    base: std::collections::hash_map::Iter<'a, WebUrl, ResponseWithCacheProperties>,
+    base: std::collections::hash_map::Iter<'a, CacheKey, Vec<StoredResponse>>,
+    variants: Option<(&'a CacheKey, std::slice::Iter<'a, StoredResponse>)>,
 }
 
 impl<'a> Iterator for Iter<'a> {
-    type Item = (String, String);
+    type Item = CacheEntry<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let (url, response) = self.base.next()?;
-        let url = url.to_string();
-        // TODO: Replace with some reasonable representation of the response.
-        let response_string = response.response.headers.to_string();
-        Some((url, response_string))
+        let (key, response) = loop {
+            if let Some((key, variants)) = &mut self.variants {
+                if let Some(response) = variants.next() {
+                    break (*key, response);
+                }
+            }
+            let (key, variants) = self.base.next()?;
+            self.variants = Some((key, variants.iter()));
+        };
+        Some(CacheEntry {
+            site: &key.site,
+            method: key.method,
+            url: &key.url,
+            response: &response.response,
+            age: response.age(),
+            max_age: response.freshness_lifetime,
+            no_cache: response.no_cache,
+        })
     }
 }
 
 impl<'a> IntoIterator for &'a Cache {
-    type Item = (String, String);
+    type Item = CacheEntry<'a>;
     type IntoIter = Iter<'a>;
 
     fn into_iter(self) -> Self::IntoIter {
         Iter {
             base: self.cache.iter(),
+            variants: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::web_url;
+    use anyhow::Result;
+
+    /// An HTTP date `seconds` from now (or ago, if it's negative).
+    fn http_date(seconds: i64) -> String {
+        (Utc::now() + TimeDelta::seconds(seconds))
+            .format("%a, %d %b %Y %H:%M:%S GMT")
+            .to_string()
+    }
+
+    /// A response with `headers`, and a body.
+    fn response(status: &str, headers: &[String]) -> Result<Response> {
+        let headers = headers
+            .iter()
+            .map(|header| format!("{header}\r\n"))
+            .collect::<String>();
+        Ok(format!("HTTP/1.1 {status}\r\n{headers}content-length: 4\r\n\r\nbody").parse()?)
+    }
+
+    /// The top-level site everything in the tests is loaded for.
+    fn site() -> Result<Site> {
+        Ok(web_url("https://top.example/")?.origin().site())
+    }
+
+    fn get(url: &WebUrl) -> Request {
+        Request::builder(RequestMethod::Get, url).build()
+    }
+
+    /// Stores a `200 OK` response with `headers` for a GET of `url` in a new cache.
+    fn cache_with(url: &WebUrl, headers: &[String]) -> Result<Cache> {
+        let mut cache = Cache::new();
+        cache.insert(
+            &site()?,
+            &get(url),
+            response("200 OK", headers)?,
+            Utc::now(),
+        )?;
+        Ok(cache)
+    }
+
+    /// The response stored for a GET of `url`.
+    fn stored_response<'a>(cache: &'a Cache, url: &WebUrl) -> Result<&'a StoredResponse> {
+        cache
+            .cache
+            .get(&CacheKey::new(&site()?, &get(url)))
+            .and_then(|variants| variants.first())
+            .ok_or_else(|| anyhow!("Nothing stored for {url}"))
+    }
+
+    fn freshness_lifetime(headers: &[String]) -> Result<i64> {
+        let url = web_url("http://example.org/")?;
+        let cache = cache_with(&url, headers)?;
+        Ok(stored_response(&cache, &url)?
+            .freshness_lifetime
+            .num_seconds())
+    }
+
+    #[test]
+    fn freshness() -> Result<()> {
+        let date = format!("Date: {}", http_date(0));
+        assert_eq!(
+            freshness_lifetime(&["Cache-Control: max-age=60".into()])?,
+            60
+        );
+        assert_eq!(
+            freshness_lifetime(&[
+                date.clone(),
+                format!("Expires: {}", http_date(3600)),
+                "Cache-Control: public, max-age=120".into(),
+            ])?,
+            120
+        );
+        assert_eq!(
+            freshness_lifetime(&[date.clone(), format!("Expires: {}", http_date(3600))])?,
+            3600
+        );
+        // 10% of the time since it was last modified.
+        assert_eq!(
+            freshness_lifetime(&[date.clone(), format!("Last-Modified: {}", http_date(-1000))])?,
+            100
+        );
+        assert_eq!(
+            freshness_lifetime(&[
+                date.clone(),
+                format!("Last-Modified: {}", http_date(-1000 * 24 * 3600))
+            ])?,
+            MAX_HEURISTIC_FRESHNESS.num_seconds()
+        );
+        // An invalid Expires has already expired.
+        assert_eq!(
+            freshness_lifetime(&["Expires: 0".into(), "ETag: \"1\"".into()])?,
+            0
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn age() -> Result<()> {
+        let url = web_url("http://example.org/")?;
+        let mut cache = cache_with(
+            &url,
+            &["Cache-Control: max-age=60".into(), "Age: 100".into()],
+        )?;
+        assert!(stored_response(&cache, &url)?.age() >= TimeDelta::seconds(100));
+        assert_eq!(cache.lookup(&site()?, &get(&url)), Lookup::Miss);
+
+        // Generated 30 seconds ago, going by the Date.
+        let mut cache = cache_with(
+            &url,
+            &[
+                "Cache-Control: max-age=60".into(),
+                format!("Date: {}", http_date(-30)),
+            ],
+        )?;
+        assert!(stored_response(&cache, &url)?.age() >= TimeDelta::seconds(30));
+        assert!(matches!(
+            cache.lookup(&site()?, &get(&url)),
+            Lookup::Fresh(_)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn storing() -> Result<()> {
+        let url = web_url("http://example.org/")?;
+        let mut cache = cache_with(&url, &["Cache-Control: max-age=60".into()])?;
+        let stores = |cache: &mut Cache, status: &str, headers: &[&str]| -> Result<bool> {
+            let headers = headers
+                .iter()
+                .map(|header| header.to_string())
+                .collect::<Vec<_>>();
+            Ok(cache
+                .insert(
+                    &site()?,
+                    &get(&url),
+                    response(status, &headers)?,
+                    Utc::now(),
+                )
+                .is_ok())
+        };
+        assert!(!stores(
+            &mut cache,
+            "200 OK",
+            &["Cache-Control: no-store, max-age=60"]
+        )?);
+        // A response that can't be stored replaces the old one all the same.
+        assert_eq!(cache.lookup(&site()?, &get(&url)), Lookup::Miss);
+
+        assert!(!stores(&mut cache, "200 OK", &[])?);
+        assert!(!stores(
+            &mut cache,
+            "500 Oops",
+            &["Last-Modified: Sun, 06 Nov 1994 08:49:37 GMT"]
+        )?);
+        assert!(stores(
+            &mut cache,
+            "200 OK",
+            &["Cache-Control: private, max-age=60"]
+        )?);
+        assert!(!stores(
+            &mut Cache::shared(),
+            "200 OK",
+            &["Cache-Control: private, max-age=60"]
+        )?);
+        assert!(stores(
+            &mut Cache::shared(),
+            "200 OK",
+            &["Cache-Control: s-maxage=60"]
+        )?);
+        Ok(())
+    }
+
+    #[test]
+    fn revalidation() -> Result<()> {
+        let url = web_url("http://example.org/")?;
+        let last_modified = "Sun, 06 Nov 1994 08:49:37 GMT";
+        let mut cache = cache_with(
+            &url,
+            &[
+                "Cache-Control: no-cache".into(),
+                "ETag: \"v1\"".into(),
+                format!("Last-Modified: {last_modified}"),
+                "Content-Type: text/plain".into(),
+            ],
+        )?;
+        assert_eq!(
+            cache.lookup(&site()?, &get(&url)),
+            Lookup::Stale(vec![
+                ("If-None-Match", "\"v1\"".to_string()),
+                ("If-Modified-Since", last_modified.to_string()),
+            ])
+        );
+
+        let not_modified = response(
+            "304 Not Modified",
+            &[
+                "ETag: \"v1\"".into(),
+                "Cache-Control: max-age=60".into(),
+                "X-New: yes".into(),
+            ],
+        )?;
+        #[allow(clippy::unwrap_used)]
+        let updated = cache
+            .update(&site()?, &get(&url), &not_modified, Utc::now())
+            .unwrap();
+        assert_eq!(updated.status_code(), 200);
+        assert_eq!(updated.text(), "body");
+        assert_eq!(updated.headers.get("x-new"), Some(&vec!["yes".to_string()]));
+        assert_eq!(
+            updated.headers.get("content-type"),
+            Some(&vec!["text/plain".to_string()])
+        );
+        // The new Cache-Control makes it fresh.
+        assert_eq!(cache.lookup(&site()?, &get(&url)), Lookup::Fresh(updated));
+
+        // A 304 for another version of the response doesn't update this one.
+        let other = response("304 Not Modified", &["ETag: \"v2\"".into()])?;
+        assert_eq!(cache.update(&site()?, &get(&url), &other, Utc::now()), None);
+        Ok(())
+    }
+
+    #[test]
+    fn stale_responses() -> Result<()> {
+        let url = web_url("http://example.org/")?;
+        let expired = format!("Expires: {}", http_date(-60));
+        let mut cache = cache_with(&url, &[expired.clone(), "ETag: \"1\"".into()])?;
+        assert!(cache.get(&site()?, &get(&url)).get().is_none());
+        assert!(cache.get_stale(&site()?, &get(&url)).is_some());
+
+        let mut cache = cache_with(
+            &url,
+            &[
+                expired,
+                "ETag: \"1\"".into(),
+                "Cache-Control: must-revalidate".into(),
+            ],
+        )?;
+        assert!(cache.get_stale(&site()?, &get(&url)).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn vary() -> Result<()> {
+        let url = web_url("http://example.org/")?;
+        let request = |language: &str| {
+            Request::builder(RequestMethod::Get, &url)
+                .header("Accept-Language", language)
+                .build()
+        };
+        let mut cache = Cache::new();
+        let english = response(
+            "200 OK",
+            &[
+                "Cache-Control: max-age=60".into(),
+                "Vary: Accept-Language".into(),
+            ],
+        )?;
+        cache.insert(&site()?, &request("en"), english.clone(), Utc::now())?;
+        assert_eq!(
+            cache.lookup(&site()?, &request("en")),
+            Lookup::Fresh(english.clone())
+        );
+        assert_eq!(cache.lookup(&site()?, &request("fr")), Lookup::Miss);
+        assert_eq!(cache.lookup(&site()?, &get(&url)), Lookup::Miss);
+
+        // Each version of the response is stored separately.
+        let french = response(
+            "200 OK",
+            &[
+                "Cache-Control: max-age=60".into(),
+                "Vary: accept-language".into(),
+                "Content-Language: fr".into(),
+            ],
+        )?;
+        cache.insert(&site()?, &request("fr"), french.clone(), Utc::now())?;
+        assert_eq!(
+            cache.lookup(&site()?, &request("en")),
+            Lookup::Fresh(english)
+        );
+        assert_eq!(
+            cache.lookup(&site()?, &request("fr")),
+            Lookup::Fresh(french)
+        );
+        assert_eq!(cache.into_iter().count(), 2);
+
+        // `Vary: *` can never match another request.
+        let mut cache = Cache::new();
+        let anything = response(
+            "200 OK",
+            &["Cache-Control: max-age=60".into(), "Vary: *".into()],
+        )?;
+        assert!(cache
+            .insert(&site()?, &get(&url), anything, Utc::now())
+            .is_err());
+        assert_eq!(cache.lookup(&site()?, &get(&url)), Lookup::Miss);
+        Ok(())
+    }
+
+    #[test]
+    fn methods() -> Result<()> {
+        let url = web_url("http://example.org/")?;
+        let mut cache = cache_with(&url, &["Cache-Control: max-age=60".into()])?;
+        let head = Request::builder(RequestMethod::Head, &url).build();
+        assert_eq!(cache.lookup(&site()?, &head), Lookup::Miss);
+
+        let mut cache = Cache::new();
+        let post = Request::builder(RequestMethod::Post, &url).build();
+        let fresh = response("200 OK", &["Cache-Control: max-age=60".into()])?;
+        assert!(cache
+            .insert(&site()?, &post, fresh.clone(), Utc::now())
+            .is_err());
+        cache.insert(&site()?, &head, fresh, Utc::now())?;
+        assert!(matches!(cache.lookup(&site()?, &head), Lookup::Fresh(_)));
+        assert_eq!(cache.lookup(&site()?, &get(&url)), Lookup::Miss);
+        Ok(())
+    }
+
+    #[test]
+    fn eviction() -> Result<()> {
+        let urls = [
+            "http://a.example/",
+            "http://b.example/",
+            "http://c.example/",
+        ]
+        .into_iter()
+        .map(web_url)
+        .collect::<Result<Vec<_>>>()?;
+        let fresh = response("200 OK", &["Cache-Control: max-age=60".into()])?;
+        let size = response_size(&fresh);
+        let mut cache = Cache::new().with_max_size(2 * size);
+        cache.insert(&site()?, &get(&urls[0]), fresh.clone(), Utc::now())?;
+        cache.insert(&site()?, &get(&urls[1]), fresh.clone(), Utc::now())?;
+        // Using the first response makes the second one the least recently used.
+        assert!(matches!(
+            cache.lookup(&site()?, &get(&urls[0])),
+            Lookup::Fresh(_)
+        ));
+        cache.insert(&site()?, &get(&urls[2]), fresh.clone(), Utc::now())?;
+        assert!(matches!(
+            cache.lookup(&site()?, &get(&urls[0])),
+            Lookup::Fresh(_)
+        ));
+        assert_eq!(cache.lookup(&site()?, &get(&urls[1])), Lookup::Miss);
+        assert!(matches!(
+            cache.lookup(&site()?, &get(&urls[2])),
+            Lookup::Fresh(_)
+        ));
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 3,
+                misses: 1,
+                evictions: 1,
+                entries: 2,
+                size: 2 * size,
+                max_size: 2 * size,
+            }
+        );
+
+        // A response bigger than the whole budget isn't stored.
+        let mut cache = Cache::new().with_max_size(size - 1);
+        assert!(cache
+            .insert(&site()?, &get(&urls[0]), fresh, Utc::now())
+            .is_err());
+        assert_eq!(cache.stats().entries, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn expired_responses() -> Result<()> {
+        let url = web_url("http://example.org/")?;
+        let other_url = web_url("http://example.org/other")?;
+        let expired = format!("Expires: {}", http_date(-60));
+        // Stale as soon as it's stored, and without validators.
+        let too_old = ["Cache-Control: max-age=60".to_string(), "Age: 100".into()];
+        let mut cache = cache_with(&url, &too_old)?;
+        let revalidatable = response("200 OK", &[expired, "ETag: \"1\"".into()])?;
+        cache.insert(
+            &site()?,
+            &get(&other_url),
+            revalidatable.clone(),
+            Utc::now(),
+        )?;
+        assert_eq!(cache.remove_expired(), 1);
+        assert_eq!(cache.lookup(&site()?, &get(&url)), Lookup::Miss);
+        assert!(matches!(
+            cache.lookup(&site()?, &get(&other_url)),
+            Lookup::Stale(_)
+        ));
+        assert_eq!(cache.stats().size, response_size(&revalidatable));
+
+        // Expired responses go before the least recently used ones.
+        let mut cache = cache_with(&url, &too_old)?;
+        let fresh = response("200 OK", &["Cache-Control: max-age=60".into()])?;
+        cache.insert(&site()?, &get(&other_url), fresh.clone(), Utc::now())?;
+        let mut cache = cache.with_max_size(response_size(&fresh));
+        assert!(matches!(
+            cache.lookup(&site()?, &get(&other_url)),
+            Lookup::Fresh(_)
+        ));
+        assert_eq!(cache.stats().evictions, 1);
+        Ok(())
+    }
+
+    /// A new directory for a cache, called `name`.
+    fn cache_directory(name: &str) -> Result<PathBuf> {
+        let directory =
+            std::env::temp_dir().join(format!("octo-cache-{name}-{}", std::process::id()));
+        if directory.exists() {
+            std::fs::remove_dir_all(&directory)?;
+        }
+        Ok(directory)
+    }
+
+    #[test]
+    fn persistence() -> Result<()> {
+        let directory = cache_directory("persistence")?;
+        let url = web_url("http://example.org/")?;
+        let english = Request::builder(RequestMethod::Get, &url)
+            .header("Accept-Language", "en")
+            .build();
+        let head = Request::builder(RequestMethod::Head, &url).build();
+        let fresh = response(
+            "200 OK",
+            &[
+                "Cache-Control: max-age=60".into(),
+                "Vary: Accept-Language".into(),
+                "Set-Cookie: a=1".into(),
+                "Set-Cookie: b=2".into(),
+            ],
+        )?;
+        let stale = response(
+            "200 OK",
+            &[
+                "Cache-Control: max-age=60".into(),
+                "Age: 100".into(),
+                "ETag: \"tab\there\"".into(),
+            ],
+        )?
+        .with_body(vec![]);
+
+        let mut cache = Cache::new().with_directory(&directory)?;
+        cache.insert(&site()?, &english, fresh.clone(), Utc::now())?;
+        cache.insert(&site()?, &head, stale, Utc::now())?;
+        let stats = cache.stats();
+        drop(cache);
+
+        let mut cache = Cache::new().with_directory(&directory)?;
+        assert_eq!(cache.stats().entries, 2);
+        assert_eq!(cache.stats().size, stats.size);
+        assert_eq!(cache.lookup(&site()?, &english), Lookup::Fresh(fresh));
+        assert_eq!(cache.lookup(&site()?, &get(&url)), Lookup::Miss);
+        assert_eq!(
+            cache.lookup(&site()?, &head),
+            Lookup::Stale(vec![("If-None-Match", "\"tab\there\"".to_string())])
+        );
+        std::fs::remove_dir_all(directory)?;
+        Ok(())
+    }
+
+    #[test]
+    fn index_journal() -> Result<()> {
+        let directory = cache_directory("journal")?;
+        let index_lines = || -> Result<usize> {
+            Ok(std::fs::read_to_string(directory.join("index"))?
+                .lines()
+                .count())
+        };
+        let first = get(&web_url("http://example.org/1")?);
+        let second = get(&web_url("http://example.org/2")?);
+        let fresh = response("200 OK", &["Cache-Control: max-age=60".into()])?;
+        let mut cache = Cache::new().with_directory(&directory)?;
+        cache.insert(&site()?, &first, fresh.clone(), Utc::now())?;
+        cache.insert(&site()?, &second, fresh.clone(), Utc::now())?;
+        assert_eq!(index_lines()?, 3);
+        // Replacing a response appends its removal, and the new one.
+        cache.insert(&site()?, &first, fresh.clone(), Utc::now())?;
+        assert_eq!(index_lines()?, 5);
+        drop(cache);
+
+        // The index is compacted when the cache is opened.
+        let mut cache = Cache::new().with_directory(&directory)?;
+        assert_eq!(cache.stats().entries, 2);
+        assert_eq!(index_lines()?, 3);
+        assert_eq!(cache.lookup(&site()?, &first), Lookup::Fresh(fresh.clone()));
+        assert_eq!(cache.clear_site(&site()?), 2);
+        drop(cache);
+        let cache = Cache::new().with_directory(&directory)?;
+        assert_eq!(cache.stats().entries, 0);
+        drop(cache);
+
+        // And once it has grown too much.
+        let mut cache = Cache::new().with_directory(&directory)?;
+        for _ in 0..100 {
+            cache.insert(&site()?, &first, fresh.clone(), Utc::now())?;
+        }
+        assert!(index_lines()? < 100);
+        drop(cache);
+        let mut cache = Cache::new().with_directory(&directory)?;
+        assert_eq!(cache.stats().entries, 1);
+        assert_eq!(cache.lookup(&site()?, &first), Lookup::Fresh(fresh));
+        std::fs::remove_dir_all(directory)?;
+        Ok(())
+    }
+
+    #[test]
+    fn corrupted_directories() -> Result<()> {
+        let directory = cache_directory("corruption")?;
+        let first = get(&web_url("http://example.org/1")?);
+        let second = get(&web_url("http://example.org/2")?);
+        let fresh = response("200 OK", &["Cache-Control: max-age=60".into()])?;
+        let mut cache = Cache::new().with_directory(&directory)?;
+        cache.insert(&site()?, &first, fresh.clone(), Utc::now())?;
+        cache.insert(&site()?, &second, fresh.clone(), Utc::now())?;
+        drop(cache);
+
+        // A line that can't be parsed is dropped, and so is an entry without its body.
+        let index_path = directory.join("index");
+        let index = std::fs::read_to_string(&index_path)?;
+        // So is one whose age is out of range, even though it parses.
+        let mut fields = index
+            .lines()
+            .nth(1)
+            .ok_or(anyhow!("Expected an entry"))?
+            .split('\t')
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        fields[0] = "2".to_string();
+        fields[3] = "http://example.org/3".to_string();
+        fields[5] = "9223372036854775000".to_string();
+        std::fs::write(
+            &index_path,
+            format!("{index}nonsense\n{}\n", fields.join("\t")),
+        )?;
+        std::fs::copy(directory.join("0.body"), directory.join("2.body"))?;
+        std::fs::remove_file(directory.join("1.body"))?;
+        let mut cache = Cache::new().with_directory(&directory)?;
+        assert_eq!(cache.stats().entries, 2);
+        assert!(!directory.join("2.body").exists());
+        assert_eq!(
+            cache.lookup(&site()?, &get(&web_url("http://example.org/3")?)),
+            Lookup::Miss
+        );
+        assert_eq!(cache.lookup(&site()?, &first), Lookup::Fresh(fresh));
+        assert_eq!(cache.lookup(&site()?, &second), Lookup::Miss);
+        assert_eq!(cache.stats().entries, 1);
+        drop(cache);
+
+        // An index from another version is thrown away, with its bodies.
+        std::fs::write(&index_path, "# Octo cache 0\n")?;
+        let cache = Cache::new().with_directory(&directory)?;
+        assert_eq!(cache.stats().entries, 0);
+        assert!(!directory.join("0.body").exists());
+        std::fs::remove_dir_all(directory)?;
+        Ok(())
+    }
+
+    #[test]
+    fn partitioning() -> Result<()> {
+        let news = web_url("https://www.news.example/")?.origin().site();
+        let shop = web_url("https://shop.example/")?.origin().site();
+        let tracker = get(&web_url("https://tracker.example/pixel.gif")?);
+        let logo = get(&web_url("https://static.news.example/logo.png")?);
+        let fresh = response("200 OK", &["Cache-Control: max-age=60".into()])?;
+        let mut cache = Cache::new();
+        cache.insert(&news, &tracker, fresh.clone(), Utc::now())?;
+        cache.insert(&news, &logo, fresh.clone(), Utc::now())?;
+        // The shop can't tell that the tracker was loaded on the news site.
+        assert_eq!(cache.lookup(&shop, &tracker), Lookup::Miss);
+        assert_eq!(cache.lookup(&news, &tracker), Lookup::Fresh(fresh.clone()));
+        cache.insert(&shop, &tracker, fresh.clone(), Utc::now())?;
+        cache.insert(&shop, &logo, fresh.clone(), Utc::now())?;
+        assert_eq!(cache.stats().entries, 4);
+
+        // Clearing a site removes what was loaded for it, and what was loaded from it.
+        assert_eq!(cache.clear_site(&news), 3);
+        assert_eq!(cache.lookup(&news, &tracker), Lookup::Miss);
+        assert_eq!(cache.lookup(&shop, &logo), Lookup::Miss);
+        assert_eq!(cache.lookup(&shop, &tracker), Lookup::Fresh(fresh));
+        Ok(())
+    }
+}
@@ -0,0 +1,290 @@
+//! Keeping a [`Cache`](super::Cache) on disk, in a directory with an index file
+//! and a file for each body. The index is a journal: a line is appended for each
+//! response stored (with its head) and for each one removed, and it's compacted
+//! into a line for each response left once it has more lines that are out of date
+//! than ones that aren't. Whole files are written to a temporary file first, and then
+//! renamed, so that they're never left half-written. Index lines and bodies that
+//! can't be read are dropped.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use chrono::{DateTime, TimeDelta};
+
+use super::{max_delta, response_size, CacheKey, StoredResponse};
+use crate::request::{RequestMethod, Response};
+use crate::write_atomically;
+use octo_url::{Site, Url};
+
+const INDEX_FILE: &str = "index";
+/// The first line of the index, which changes whenever its format does.
+/// An index from another version is thrown away, along with the bodies.
+const INDEX_HEADER: &str = "# Octo cache 3";
+const BODY_EXTENSION: &str = "body";
+/// How a line that removes the response with an id starts.
+const REMOVAL_PREFIX: &str = "-\t";
+/// How many lines can be appended to the index before it's compacted, however
+/// few responses there are, so that a small cache isn't compacted all the time.
+const MIN_APPENDED_LINES: usize = 64;
+
+#[derive(Debug, PartialEq)]
+pub(super) struct DiskStore {
+    directory: PathBuf,
+    /// How many lines have been appended to the index since it was last written whole.
+    appended_lines: usize,
+}
+
+impl DiskStore {
+    /// Uses `directory`, creating it if need be.
+    pub(super) fn open(directory: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(&directory)?;
+        Ok(Self {
+            directory,
+            appended_lines: 0,
+        })
+    }
+
+    fn body_path(&self, id: u64) -> PathBuf {
+        self.directory.join(format!("{id}.{BODY_EXTENSION}"))
+    }
+
+    /// Reads the stored responses in the index, without their bodies.
+    /// Bodies that aren't in the index any more are removed.
+    pub(super) fn load(&self) -> io::Result<Vec<(CacheKey, StoredResponse)>> {
+        let index = match fs::read_to_string(self.directory.join(INDEX_FILE)) {
+            Ok(index) if index.lines().next() == Some(INDEX_HEADER) => index,
+            Ok(_) => String::new(),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => String::new(),
+            // An index that isn't UTF-8 is as good as one from another version.
+            Err(error) if error.kind() == io::ErrorKind::InvalidData => String::new(),
+            Err(error) => return Err(error),
+        };
+        let mut entries = BTreeMap::new();
+        for line in index.lines().skip(1) {
+            if let Some(id) = line.strip_prefix(REMOVAL_PREFIX) {
+                if let Ok(id) = id.parse::<u64>() {
+                    entries.remove(&id);
+                }
+            } else if let Some((key, stored_response)) = parse_entry(line) {
+                entries.insert(stored_response.id, (key, stored_response));
+            }
+        }
+
+        let ids = entries.keys().copied().collect::<HashSet<_>>();
+        for file in fs::read_dir(&self.directory)? {
+            let path = file?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == BODY_EXTENSION)
+            {
+                let id = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str()?.parse::<u64>().ok());
+                if id.is_none_or(|id| !ids.contains(&id)) {
+                    fs::remove_file(path)?;
+                }
+            }
+        }
+        Ok(entries.into_values().collect())
+    }
+
+    /// Writes the index of `entries`, replacing the old one. Responses for opaque
+    /// sites are left out, as there's no telling them apart once they're written.
+    pub(super) fn write_index<'a>(
+        &mut self,
+        entries: impl Iterator<Item = (&'a CacheKey, &'a StoredResponse)>,
+    ) -> io::Result<()> {
+        let mut index = format!("{INDEX_HEADER}\n");
+        for (key, stored_response) in entries {
+            if matches!(key.site, Site::Opaque(_)) {
+                continue;
+            }
+            index.push_str(&format_entry(key, stored_response));
+            index.push('\n');
+        }
+        write_atomically(&self.directory.join(INDEX_FILE), index.as_bytes())?;
+        self.appended_lines = 0;
+        Ok(())
+    }
+
+    /// Whether the index has so many lines that are out of date, for a cache
+    /// with `entries` responses, that it should be written whole again.
+    pub(super) fn needs_compacting(&self, entries: usize) -> bool {
+        self.appended_lines > entries.max(MIN_APPENDED_LINES)
+    }
+
+    /// Adds a newly stored response to the index.
+    pub(super) fn append_entry(
+        &mut self,
+        key: &CacheKey,
+        stored_response: &StoredResponse,
+    ) -> io::Result<()> {
+        if matches!(key.site, Site::Opaque(_)) {
+            return Ok(());
+        }
+        self.append(&[format_entry(key, stored_response)])
+    }
+
+    /// Records in the index that the responses with `ids` were removed.
+    pub(super) fn append_removals(&mut self, ids: &[u64]) -> io::Result<()> {
+        let lines = ids
+            .iter()
+            .map(|id| format!("{REMOVAL_PREFIX}{id}"))
+            .collect::<Vec<_>>();
+        self.append(&lines)
+    }
+
+    fn append(&mut self, lines: &[String]) -> io::Result<()> {
+        if lines.is_empty() {
+            return Ok(());
+        }
+        let mut index = OpenOptions::new()
+            .append(true)
+            .open(self.directory.join(INDEX_FILE))?;
+        let mut contents = lines.join("\n");
+        contents.push('\n');
+        index.write_all(contents.as_bytes())?;
+        self.appended_lines += lines.len();
+        Ok(())
+    }
+
+    pub(super) fn write_body(&self, id: u64, body: &[u8]) -> io::Result<()> {
+        write_atomically(&self.body_path(id), body)
+    }
+
+    pub(super) fn read_body(&self, id: u64) -> io::Result<Vec<u8>> {
+        fs::read(self.body_path(id))
+    }
+
+    pub(super) fn remove_body(&self, id: u64) -> io::Result<()> {
+        match fs::remove_file(self.body_path(id)) {
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+}
+
+/// Escapes the characters that separate fields and lines in the index.
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\r', "\\r")
+        .replace('\n', "\\n")
+}
+
+fn unescape(s: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        unescaped.push(match c {
+            '\\' => match chars.next()? {
+                '\\' => '\\',
+                't' => '\t',
+                'r' => '\r',
+                'n' => '\n',
+                _ => return None,
+            },
+            c => c,
+        });
+    }
+    Some(unescaped)
+}
+
+/// A line of the index. The head goes last, as it's the longest field.
+fn format_entry(key: &CacheKey, stored_response: &StoredResponse) -> String {
+    // The request headers a response varies on are written like headers,
+    // without a value if the request didn't have them.
+    let vary = stored_response
+        .vary
+        .iter()
+        .map(|(name, value)| match value {
+            Some(value) => format!("{name}: {value}\r\n"),
+            None => format!("{name}\r\n"),
+        })
+        .collect::<String>();
+    [
+        stored_response.id.to_string(),
+        key.site.to_string(),
+        key.method.to_string(),
+        key.url.to_string(),
+        stored_response.response_time.timestamp_millis().to_string(),
+        stored_response.initial_age.num_milliseconds().to_string(),
+        stored_response
+            .freshness_lifetime
+            .num_milliseconds()
+            .to_string(),
+        stored_response.no_cache.to_string(),
+        stored_response.must_revalidate.to_string(),
+        stored_response.size.to_string(),
+        stored_response.last_used.to_string(),
+        stored_response.response.body().is_some().to_string(),
+        escape(&vary),
+        escape(&stored_response.response.head()),
+    ]
+    .join("\t")
+}
+
+/// Parses a line written by [`format_entry`]. The response it gives only has a head,
+/// and the body is left on disk if it has one.
+fn parse_entry(line: &str) -> Option<(CacheKey, StoredResponse)> {
+    let fields = line.split('\t').collect::<Vec<_>>();
+    let [id, site, method, url, response_time, initial_age, freshness_lifetime, no_cache, must_revalidate, size, last_used, has_body, vary, head] =
+        fields[..]
+    else {
+        return None;
+    };
+    let site = match format!("{site}/").parse::<Url>().ok()?.origin().site() {
+        Site::Opaque(_) => return None,
+        site => site,
+    };
+    let method = match method {
+        "GET" => RequestMethod::Get,
+        "HEAD" => RequestMethod::Head,
+        _ => return None,
+    };
+    let url = match url.parse::<Url>().ok()? {
+        Url::Web(url) => url,
+        _ => return None,
+    };
+    // Ages and lifetimes out of the range that's stored can't come from a response.
+    let milliseconds = |s: &str| {
+        s.parse()
+            .ok()
+            .and_then(TimeDelta::try_milliseconds)
+            .filter(|delta| (TimeDelta::zero()..=max_delta()).contains(delta))
+    };
+    let vary = unescape(vary)?
+        .lines()
+        .map(|line| match line.split_once(": ") {
+            Some((name, value)) => (name.to_string(), Some(value.to_string())),
+            None => (line.to_string(), None),
+        })
+        .collect();
+    let head = unescape(head)?;
+    let response = Response::read_head(&mut head.as_bytes()).ok()?;
+    let unloaded_body = has_body.parse::<bool>().ok()?;
+    let size = size.parse().ok()?;
+    if !unloaded_body && response_size(&response) != size {
+        return None;
+    }
+
+    Some((
+        CacheKey { site, method, url },
+        StoredResponse {
+            response: Arc::new(response),
+            response_time: DateTime::from_timestamp_millis(response_time.parse().ok()?)?,
+            initial_age: milliseconds(initial_age)?,
+            freshness_lifetime: milliseconds(freshness_lifetime)?,
+            no_cache: no_cache.parse().ok()?,
+            must_revalidate: must_revalidate.parse().ok()?,
+            vary,
+            size,
+            last_used: last_used.parse().ok()?,
+            id: id.parse().ok()?,
+            unloaded_body,
+        },
+    ))
+}
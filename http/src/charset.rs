@@ -0,0 +1,109 @@
+//! Decoding bodies into text, in whichever character encoding they use
+//! (https://html.spec.whatwg.org/multipage/parsing.html#determining-the-character-encoding).
+
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252, X_USER_DEFINED};
+use octo_url::MimeType;
+
+/// How far into an HTML document to look for a `<meta charset>`.
+const META_PRESCAN_LEN: usize = 1024;
+
+/// Looks for the encoding in a `<meta charset="...">` or
+/// `<meta http-equiv="Content-Type" content="text/html; charset=...">` near the start of `bytes`.
+/// This is a simplified version of
+/// https://html.spec.whatwg.org/multipage/parsing.html#prescan-a-byte-stream-to-determine-its-encoding.
+fn meta_charset(bytes: &[u8]) -> Option<&'static Encoding> {
+    let start = bytes[..bytes.len().min(META_PRESCAN_LEN)].to_ascii_lowercase();
+    let start = String::from_utf8_lossy(&start);
+    start.match_indices("<meta").find_map(|(i, _)| {
+        let tag = &start[i..];
+        let tag = &tag[..tag.find('>').unwrap_or(tag.len())];
+        let value = tag[tag.find("charset")? + "charset".len()..].trim_start();
+        let value = value.strip_prefix('=')?.trim_start();
+        let value = value.trim_start_matches(['"', '\'']);
+        let label_end = value
+            .find(|c: char| c.is_ascii_whitespace() || matches!(c, '"' | '\'' | ';' | '/'))
+            .unwrap_or(value.len());
+        let encoding = Encoding::for_label(&value.as_bytes()[..label_end])?;
+        // The document can't really be in UTF-16 if its ASCII could be read like this.
+        Some(match encoding {
+            encoding if encoding == UTF_16BE || encoding == UTF_16LE => UTF_8,
+            encoding if encoding == X_USER_DEFINED => WINDOWS_1252,
+            encoding => encoding,
+        })
+    })
+}
+
+/// Works out the encoding of `bytes`, whose MIME type is `mime_type` if it's known:
+/// a byte order mark wins, then the `charset` parameter, then (for HTML) a `<meta charset>`.
+/// Anything else is assumed to be UTF-8.
+pub fn encoding(bytes: &[u8], mime_type: Option<&MimeType>) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return encoding;
+    }
+    let charset = mime_type
+        .and_then(MimeType::charset)
+        .and_then(|charset| Encoding::for_label(charset.as_bytes()));
+    let is_html = mime_type.is_none_or(MimeType::is_html);
+    charset
+        .or_else(|| is_html.then(|| meta_charset(bytes)).flatten())
+        .unwrap_or(UTF_8)
+}
+
+/// Decodes `bytes` into text, in the encoding [`encoding`] finds for them.
+/// Invalid sequences are replaced with U+FFFD.
+pub fn decode(bytes: &[u8], mime_type: Option<&MimeType>) -> String {
+    let (text, _, _) = encoding(bytes, mime_type).decode(bytes);
+    text.into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn mime_type(mime_type: &str) -> Result<MimeType> {
+        Ok(mime_type.parse::<MimeType>()?)
+    }
+
+    #[test]
+    fn charset_parameter() -> Result<()> {
+        let latin_1 = mime_type("text/html; charset=ISO-8859-1")?;
+        assert_eq!(decode(b"caf\xe9", Some(&latin_1)), "café");
+        let shift_jis = mime_type("text/plain;charset=shift_jis")?;
+        assert_eq!(decode(b"\x93\xfa\x96\x7b", Some(&shift_jis)), "日本");
+        let unknown = mime_type("text/plain;charset=nonsense")?;
+        assert_eq!(decode("café".as_bytes(), Some(&unknown)), "café");
+        Ok(())
+    }
+
+    #[test]
+    fn byte_order_marks() -> Result<()> {
+        let latin_1 = mime_type("text/plain;charset=iso-8859-1")?;
+        assert_eq!(decode(b"\xef\xbb\xbfcaf\xc3\xa9", Some(&latin_1)), "café");
+        assert_eq!(decode(b"\xff\xfeh\x00i\x00", None), "hi");
+        assert_eq!(decode(b"\xfe\xff\x00h\x00i", None), "hi");
+        Ok(())
+    }
+
+    #[test]
+    fn meta_charsets() -> Result<()> {
+        let html = mime_type("text/html")?;
+        assert_eq!(
+            decode(b"<meta charset=\"windows-1252\"><p>caf\xe9", Some(&html)),
+            "<meta charset=\"windows-1252\"><p>caf\u{e9}"
+        );
+        let http_equiv = b"<META http-equiv=Content-Type content='text/html; charset=Shift_JIS'>";
+        assert_eq!(encoding(http_equiv, None), encoding_rs::SHIFT_JIS);
+        assert_eq!(encoding(b"<meta charset=utf-16le>", Some(&html)), UTF_8);
+        // Only HTML is sniffed, and the charset parameter wins.
+        let text = mime_type("text/plain")?;
+        assert_eq!(encoding(b"<meta charset=gbk>", Some(&text)), UTF_8);
+        let html_in_gbk = mime_type("text/html;charset=gbk")?;
+        assert_eq!(
+            encoding(b"<meta charset=windows-1252>", Some(&html_in_gbk)),
+            encoding_rs::GBK
+        );
+        assert_eq!(encoding(b"<p>no charset here", Some(&html)), UTF_8);
+        Ok(())
+    }
+}
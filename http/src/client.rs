@@ -0,0 +1,1052 @@
+//! Sends [`Request`]s over the network, following redirects
+//! and reusing connections where it can.
+
+use std::borrow::Cow;
+use std::io;
+use std::io::{BufReader, Read, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use octo_url::WebUrl;
+
+use crate::body::{self, DecodedReader, FramedReader};
+use crate::connection::GenericTcpStream;
+use crate::cookies::CookieJar;
+use crate::headers::Headers;
+use crate::pool::{ConnectionPool, PooledConnection, DEFAULT_MAX_PER_HOST, DEFAULT_MAX_TOTAL};
+use crate::request::{
+    can_have_body, HttpError, NetworkError, RedirectPolicy, Request, RequestError, RequestMethod,
+    Response, ResponseError, Timeout,
+};
+use crate::transport::{ScriptedTransport, TcpTransport, Transport};
+
+/// How long a request without a connect timeout waits for a connection to free up,
+/// when it's over the limits. Without a limit, a thread holding the only connections
+/// to an origin (e.g. with a streamed body) would wait forever for them.
+const POOL_WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Sends requests, keeping connections open for the next requests to the same origin
+/// if both ends agree to it. Clones of a client share its connections,
+/// so it can be cloned to send requests from several threads at once.
+#[derive(Debug, Clone)]
+pub struct Client {
+    pool: Arc<ConnectionPool>,
+    cookie_jar: Option<Arc<CookieJar>>,
+    transport: Arc<dyn Transport>,
+    /// Whether `https` requests skip TLS, which is only ever the case
+    /// with a [`ScriptedTransport`], as there's no server on the other end.
+    plain_text_https: bool,
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Client {
+    /// A client that keeps at most 6 connections open to each origin,
+    /// and 64 in total.
+    pub fn new() -> Self {
+        Self::with_connection_limits(DEFAULT_MAX_PER_HOST, DEFAULT_MAX_TOTAL)
+    }
+
+    /// A client that keeps at most `max_per_host` connections open to each origin,
+    /// and `max_total` in total. Requests wait for a connection once the limits are hit,
+    /// for as long as their connect timeout (or 30 seconds without one).
+    pub fn with_connection_limits(max_per_host: usize, max_total: usize) -> Self {
+        Self {
+            pool: Arc::new(ConnectionPool::new(max_per_host, max_total)),
+            cookie_jar: None,
+            transport: Arc::new(TcpTransport),
+            plain_text_https: false,
+        }
+    }
+
+    /// Stores the cookies set by responses in `cookie_jar`, including the ones
+    /// along the way of a redirect, and sends them back with the requests they match.
+    pub fn with_cookie_jar(mut self, cookie_jar: Arc<CookieJar>) -> Self {
+        self.cookie_jar = Some(cookie_jar);
+        self
+    }
+
+    /// Opens connections with `transport` instead of over TCP.
+    /// Connections for `https` URLs always use TLS on top of them.
+    pub fn with_transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.transport = transport;
+        self.plain_text_https = false;
+        self
+    }
+
+    /// Answers requests with the responses scripted in `transport`, for tests.
+    /// Connections for `https` URLs are in plain text, so that the responses
+    /// can be scripted as they are.
+    pub fn with_scripted_transport(mut self, transport: ScriptedTransport) -> Self {
+        self.transport = Arc::new(transport);
+        self.plain_text_https = true;
+        self
+    }
+
+    /// Sends `request`, follows any redirects according to its [`RedirectPolicy`],
+    /// and reads the whole response.
+    pub fn execute(&self, request: &Request) -> Result<Response, HttpError> {
+        self.execute_streaming(request)?.into_response()
+    }
+
+    /// Like [`Client::execute`], but returns as soon as the headers of the final response
+    /// have arrived, leaving its body to be read as it comes in.
+    pub fn execute_streaming(&self, request: &Request) -> Result<StreamingResponse<'_>, HttpError> {
+        let deadline = request.timeout().map(|timeout| Instant::now() + timeout);
+        let mut request = Cow::Borrowed(request);
+        let mut num_redirects = 0;
+        // Whether all the URLs so far have been on the same site, for `SameSite` cookies.
+        let mut same_site = true;
+        loop {
+            let mut response = self.send(&request, deadline, same_site)?;
+            let RedirectPolicy::Limit(max_redirects) = request.redirect_policy() else {
+                return Ok(response);
+            };
+            let Some(location) = response.head.redirect_location() else {
+                return Ok(response);
+            };
+            if num_redirects == max_redirects {
+                return Err(
+                    NetworkError::from(RequestError::TooManyRedirects(max_redirects)).into(),
+                );
+            }
+            let next_request = request
+                .redirect(response.status_code(), location)
+                .map_err(NetworkError::from)?;
+            // Read the rest of the redirect, so its connection can be reused.
+            io::copy(&mut response.body, &mut io::sink())
+                .map_err(|e| NetworkError::from(ResponseError::from(e)))?;
+            same_site &= next_request
+                .url()
+                .origin()
+                .is_same_site(&request.url().origin());
+            request = Cow::Owned(next_request);
+            num_redirects += 1;
+        }
+    }
+
+    /// Convenience method to make a GET request with the default settings
+    /// to the given URL, and return the resulting `Response` or error.
+    pub fn get(&self, url: &WebUrl) -> Result<Response, HttpError> {
+        self.execute(&Request::builder(RequestMethod::Get, url).build())
+    }
+
+    /// Sends `request` without following redirects, on an idle connection from the pool
+    /// if there is one, or else on a new connection.
+    fn send(
+        &self,
+        request: &Request,
+        deadline: Option<Instant>,
+        same_site: bool,
+    ) -> Result<StreamingResponse<'_>, NetworkError> {
+        let cookie_header = self
+            .cookie_jar
+            .as_ref()
+            .filter(|_| request.headers().get("cookie").is_none())
+            .and_then(|jar| jar.cookie_header_for(request.url(), request.method(), same_site));
+        let with_cookies;
+        let request = match cookie_header {
+            Some(cookie_header) => {
+                with_cookies = request.with_header("Cookie", &cookie_header);
+                &with_cookies
+            }
+            None => request,
+        };
+
+        let limits = Limits {
+            read: request.read_timeout(),
+            deadline,
+        };
+        // Waiting for a connection counts as connecting.
+        let (pool_wait, kind) = limits
+            .next(
+                Some(request.connect_timeout().unwrap_or(POOL_WAIT_TIMEOUT)),
+                Timeout::Connect,
+            )
+            .map_err(RequestError::from)?;
+        let mut connection = self
+            .pool
+            .checkout(
+                &request.url().origin(),
+                pool_wait.map(|pool_wait| Instant::now() + pool_wait),
+            )
+            .ok_or_else(|| RequestError::from(timeout_error(kind)))?;
+
+        let (reader, head) = match connection.take_idle_stream() {
+            Some(stream) => match send_on(stream, request, limits) {
+                // The server may have closed the connection after we last checked,
+                // so try again once on a new one.
+                Err(error) if is_closed_connection(&error) => {
+                    send_on(self.connect(request, limits)?, request, limits)?
+                }
+                result => result?,
+            },
+            None => send_on(self.connect(request, limits)?, request, limits)?,
+        };
+
+        if let Some(cookie_jar) = &self.cookie_jar {
+            cookie_jar.store_all(request.url(), &head.headers);
+        }
+
+        let has_body = can_have_body(request.method(), head.status_code());
+        let framed = FramedReader::new(reader, &head.headers, has_body)?;
+        let decoders = body::decoders(&head.headers)?;
+        let reuse = (request.keep_alive() && head.can_reuse_connection(request.method()))
+            .then(|| head.keep_alive_timeout());
+        let mut connection_reader = ConnectionReader {
+            framed: Some(framed),
+            connection: Some(connection),
+            reuse,
+        };
+        connection_reader.release_if_finished();
+        let body = ResponseBody {
+            reader: DecodedReader::new(connection_reader, decoders),
+        };
+        Ok(StreamingResponse { head, body })
+    }
+
+    fn connect(&self, request: &Request, limits: Limits) -> Result<GenericTcpStream, RequestError> {
+        let (timeout, kind) = limits.next(request.connect_timeout(), Timeout::Connect)?;
+        GenericTcpStream::connect(
+            self.transport.as_ref(),
+            request.url(),
+            timeout,
+            self.plain_text_https,
+        )
+        .map_err(|error| match error {
+            RequestError::ConnectionFailed(error) if is_timeout(&error) => {
+                timeout_error(kind).into()
+            }
+            error => error,
+        })
+    }
+}
+
+/// A response whose body hasn't been read yet. The status and headers are there
+/// as soon as they arrive, and the body can be read as it comes in,
+/// with its chunked transfer coding and compression undone on the way.
+pub struct StreamingResponse<'a> {
+    /// The response without its body.
+    head: Response,
+    body: ResponseBody<'a>,
+}
+
+impl<'a> StreamingResponse<'a> {
+    pub fn status_code(&self) -> u16 {
+        self.head.status_code()
+    }
+
+    pub fn headers(&self) -> &Headers {
+        &self.head.headers
+    }
+
+    pub fn body(&mut self) -> &mut ResponseBody<'a> {
+        &mut self.body
+    }
+
+    pub fn into_body(self) -> ResponseBody<'a> {
+        self.body
+    }
+
+    /// Reads the rest of the body, and returns the whole response.
+    pub fn into_response(mut self) -> Result<Response, HttpError> {
+        let mut body = vec![];
+        self.body
+            .read_to_end(&mut body)
+            .map_err(|e| NetworkError::from(ResponseError::from(e)))?;
+        Ok(self.head.with_body(body))
+    }
+}
+
+/// The body of a [`StreamingResponse`], read from the connection as it's needed.
+/// If it's dropped before the end, the connection gets closed.
+pub struct ResponseBody<'a> {
+    reader: DecodedReader<ConnectionReader<'a>>,
+}
+
+impl Read for ResponseBody<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+/// Reads a body from a connection, and puts the connection back into the pool
+/// once all of the body has been read, if it can be reused.
+struct ConnectionReader<'a> {
+    framed: Option<FramedReader<BufReader<TimedStream>>>,
+    connection: Option<PooledConnection<'a>>,
+    /// How long the connection can stay idle, if it can be reused at all.
+    reuse: Option<Option<Duration>>,
+}
+
+impl ConnectionReader<'_> {
+    fn release_if_finished(&mut self) {
+        if !self.framed.as_ref().is_some_and(FramedReader::is_finished) {
+            return;
+        }
+        let (Some(framed), Some(connection)) = (self.framed.take(), self.connection.take()) else {
+            return;
+        };
+        let reader = framed.into_inner();
+        // Anything the server sent after the body would be taken as the next response.
+        match self.reuse {
+            Some(idle_timeout) if reader.buffer().is_empty() => {
+                connection.release(reader.into_inner().stream, idle_timeout);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Read for ConnectionReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(framed) = &mut self.framed else {
+            return Ok(0);
+        };
+        let len = framed.read(buf)?;
+        self.release_if_finished();
+        Ok(len)
+    }
+}
+
+/// The time limits for each step of sending a request.
+#[derive(Debug, Copy, Clone)]
+struct Limits {
+    /// How long each read or write can take.
+    read: Option<Duration>,
+    /// When the whole request (including redirects) has to be done.
+    deadline: Option<Instant>,
+}
+
+impl Limits {
+    /// How long the next step can take: `step`, unless the deadline comes first.
+    /// Also returns which of the two limits it is, to report if it runs out.
+    fn next(
+        &self,
+        step: Option<Duration>,
+        kind: Timeout,
+    ) -> io::Result<(Option<Duration>, Timeout)> {
+        let Some(deadline) = self.deadline else {
+            return Ok((step, kind));
+        };
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(timeout_error(Timeout::Total));
+        }
+        match step {
+            Some(step) if step < remaining => Ok((Some(step), kind)),
+            _ => Ok((Some(remaining), Timeout::Total)),
+        }
+    }
+}
+
+/// An I/O error for a timeout running out, which [`HttpError`] turns into
+/// [`HttpError::Timeout`].
+fn timeout_error(timeout: Timeout) -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, timeout)
+}
+
+/// Whether the operation failed because the socket's timeout ran out.
+/// Unix reports it as `WouldBlock`, and Windows as `TimedOut`.
+fn is_timeout(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// A stream that limits each read and write by the timeouts of a request.
+struct TimedStream {
+    stream: GenericTcpStream,
+    limits: Limits,
+}
+
+impl TimedStream {
+    fn limit<T>(
+        &mut self,
+        f: impl FnOnce(&mut GenericTcpStream) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let (timeout, kind) = self.limits.next(self.limits.read, Timeout::Read)?;
+        self.stream.set_timeout(timeout)?;
+        f(&mut self.stream).map_err(|error| {
+            if is_timeout(&error) {
+                timeout_error(kind)
+            } else {
+                error
+            }
+        })
+    }
+}
+
+impl Read for TimedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.limit(|stream| stream.read(buf))
+    }
+}
+
+impl Write for TimedStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.limit(|stream| stream.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.limit(|stream| stream.flush())
+    }
+}
+
+/// Sends `request` on `stream`, and reads the head of the response.
+/// Returns the response, and the reader to read its body from.
+fn send_on(
+    stream: GenericTcpStream,
+    request: &Request,
+    limits: Limits,
+) -> Result<(BufReader<TimedStream>, Response), NetworkError> {
+    let mut stream = TimedStream { stream, limits };
+    stream
+        .write_all(&request.to_bytes())
+        .map_err(RequestError::from)?;
+    let mut reader = BufReader::new(stream);
+    let head = Response::read_head(&mut reader)?;
+    Ok((reader, head))
+}
+
+/// Whether `error` means the connection was closed before the response started,
+/// in which case it's safe to send the request again.
+fn is_closed_connection(error: &NetworkError) -> bool {
+    match error {
+        NetworkError::Request(RequestError::ConnectionFailed(error)) => matches!(
+            error.kind(),
+            io::ErrorKind::BrokenPipe
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+        ),
+        NetworkError::Response(ResponseError::ConnectionClosed) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::RequestBuilder;
+    use crate::test_util::web_url;
+    use anyhow::Result;
+    use std::io::BufRead;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread::{self, JoinHandle};
+
+    /// Reads one raw request (head and body) from `stream`.
+    fn read_request(stream: &mut impl Read) -> Result<String> {
+        let mut reader = BufReader::new(stream);
+        let mut request = String::new();
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            request.push_str(&line);
+            if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                content_length = value.trim().parse()?;
+            }
+            if line == "\r\n" || line.is_empty() {
+                break;
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+        request.push_str(&String::from_utf8(body)?);
+        Ok(request)
+    }
+
+    fn listen() -> Result<(TcpListener, u16)> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        Ok((listener, port))
+    }
+
+    /// Accepts one connection on `listener` for each list of responses in `connections`,
+    /// and answers one request on it with each response in its list, before closing it.
+    /// Returns a handle that gives the raw requests, from all connections in order.
+    fn serve_connections(
+        listener: TcpListener,
+        connections: Vec<Vec<Vec<u8>>>,
+    ) -> JoinHandle<Result<Vec<String>>> {
+        thread::spawn(move || -> Result<Vec<String>> {
+            let mut requests = vec![];
+            for responses in connections {
+                let (mut stream, _) = listener.accept()?;
+                for response in responses {
+                    requests.push(read_request(&mut stream)?);
+                    stream.write_all(&response)?;
+                }
+            }
+            Ok(requests)
+        })
+    }
+
+    /// Like [`serve_connections`], with one response per connection.
+    fn serve(listener: TcpListener, responses: Vec<Vec<u8>>) -> JoinHandle<Result<Vec<String>>> {
+        let connections = responses
+            .into_iter()
+            .map(|response| vec![response])
+            .collect();
+        serve_connections(listener, connections)
+    }
+
+    /// Like [`serve`], on a new local port, which is returned with the handle.
+    fn serve_local(responses: &[&[u8]]) -> Result<(u16, JoinHandle<Result<Vec<String>>>)> {
+        let (listener, port) = listen()?;
+        let responses = responses.iter().map(|response| response.to_vec()).collect();
+        Ok((port, serve(listener, responses)))
+    }
+
+    #[test]
+    fn connect_to_ip_address() -> Result<()> {
+        let (port, server) = serve_local(&[b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nhi"])?;
+        let url = web_url(&format!("http://0x7f.1:{port}/x"))?;
+        let response = Client::new().get(&url)?;
+        assert_eq!(response.text(), "hi");
+        #[allow(clippy::unwrap_used)]
+        let requests = server.join().unwrap()?;
+        assert!(requests[0].starts_with("GET /x HTTP/1.1\r\n"));
+        assert!(requests[0].contains(&format!("host: 127.0.0.1:{port}\r\n")));
+        Ok(())
+    }
+
+    #[test]
+    fn post_sends_body() -> Result<()> {
+        let (port, server) =
+            serve_local(&[b"HTTP/1.1 201 Created\r\ncontent-length: 2\r\n\r\nok"])?;
+        let url = web_url(&format!("http://127.0.0.1:{port}/form"))?;
+        let request = Request::builder(RequestMethod::Post, &url)
+            .body("a=1&b=%C3%A9")
+            .build();
+        let response = Client::new().execute(&request)?;
+        assert_eq!(response.status_code(), 201);
+        assert_eq!(response.text(), "ok");
+
+        #[allow(clippy::unwrap_used)]
+        let requests = server.join().unwrap()?;
+        assert!(requests[0].starts_with("POST /form HTTP/1.1\r\n"));
+        assert!(requests[0].contains("content-length: 12\r\n"));
+        assert!(requests[0].ends_with("\r\n\r\na=1&b=%C3%A9"));
+        Ok(())
+    }
+
+    #[test]
+    fn head_has_no_body() -> Result<()> {
+        // The server closes the connection without sending the 100 bytes,
+        // so reading them would fail.
+        let (port, server) = serve_local(&[b"HTTP/1.1 200 OK\r\ncontent-length: 100\r\n\r\n"])?;
+        let url = web_url(&format!("http://127.0.0.1:{port}/"))?;
+        let request = Request::builder(RequestMethod::Head, &url).build();
+        let response = Client::new().execute(&request)?;
+        assert_eq!(response.body(), None);
+        assert_eq!(
+            response
+                .headers
+                .get_single_value("content-length")
+                .transpose()?,
+            Some(&"100".to_string())
+        );
+        #[allow(clippy::unwrap_used)]
+        server.join().unwrap()?;
+        Ok(())
+    }
+
+    #[test]
+    fn redirect_to_other_host() -> Result<()> {
+        let (listener, port) = listen()?;
+        // Both 127.0.0.1 and localhost are served by the same listener, but they are
+        // different origins, so the second request has to get its own connection
+        // and its own Host header.
+        let redirect = format!(
+            "HTTP/1.1 302 Found\r\nlocation: http://localhost:{port}/done\r\ncontent-length: 0\r\n\r\n"
+        );
+        let server = serve(
+            listener,
+            vec![
+                redirect.into_bytes(),
+                b"HTTP/1.1 200 OK\r\ncontent-length: 4\r\n\r\ndone".to_vec(),
+            ],
+        );
+
+        let url = web_url(&format!("http://127.0.0.1:{port}/form"))?;
+        let request = Request::builder(RequestMethod::Post, &url)
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Authorization", "Basic b2N0bzpvY3Rv")
+            .body("a=1")
+            .build();
+        let response = Client::new().execute(&request)?;
+        assert_eq!(response.text(), "done");
+
+        #[allow(clippy::unwrap_used)]
+        let requests = server.join().unwrap()?;
+        assert!(requests[0].starts_with("POST /form HTTP/1.1\r\n"));
+        assert!(requests[0].contains(&format!("host: 127.0.0.1:{port}\r\n")));
+        assert!(requests[0].contains("authorization: "));
+        // A POST redirected with a 302 turns into a GET, without the body,
+        // and the credentials don't go to the other origin.
+        assert!(requests[1].starts_with("GET /done HTTP/1.1\r\n"));
+        assert!(requests[1].contains(&format!("host: localhost:{port}\r\n")));
+        assert!(!requests[1].contains("content-type"));
+        assert!(!requests[1].contains("content-length"));
+        assert!(!requests[1].contains("authorization"));
+        Ok(())
+    }
+
+    #[test]
+    fn cookies_follow_redirects() -> Result<()> {
+        let (listener, port) = listen()?;
+        let redirect = |location: &str, set_cookies: &str| {
+            format!("HTTP/1.1 302 Found\r\nlocation: {location}\r\n{set_cookies}content-length: 0\r\n\r\n")
+                .into_bytes()
+        };
+        let server = serve(
+            listener,
+            vec![
+                redirect(
+                    "/second",
+                    "set-cookie: a=1\r\nset-cookie: strict=1; SameSite=Strict\r\n",
+                ),
+                redirect(
+                    &format!("http://localhost:{port}/third"),
+                    "set-cookie: b=2\r\n",
+                ),
+                redirect(&format!("http://127.0.0.1:{port}/fourth"), ""),
+                OK.to_vec(),
+            ],
+        );
+
+        let cookie_jar = Arc::new(CookieJar::new());
+        let client = Client::new().with_cookie_jar(cookie_jar.clone());
+        let url = web_url(&format!("http://127.0.0.1:{port}/"))?;
+        assert_eq!(client.get(&url)?.text(), "ok");
+
+        #[allow(clippy::unwrap_used)]
+        let requests = server.join().unwrap()?;
+        assert!(!requests[0].contains("cookie:"));
+        assert!(requests[1].contains("cookie: a=1; strict=1\r\n"));
+        // localhost is another site, so it doesn't get them.
+        assert!(!requests[2].contains("cookie:"));
+        // Coming back from another site, the strict cookie is left out.
+        assert!(requests[3].contains("cookie: a=1; b=2\r\n"));
+        assert_eq!(
+            cookie_jar.cookie_header(&url).as_deref(),
+            Some("a=1; strict=1; b=2")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn redirect_policies() -> Result<()> {
+        const REDIRECT: &[u8] =
+            b"HTTP/1.1 301 Moved Permanently\r\nlocation: /again\r\nconnection: close\r\ncontent-length: 0\r\n\r\n";
+
+        let (port, server) = serve_local(&[REDIRECT; 3])?;
+        let url = web_url(&format!("http://127.0.0.1:{port}/"))?;
+        let request = Request::builder(RequestMethod::Get, &url)
+            .redirect_policy(RedirectPolicy::Limit(2))
+            .build();
+        let error = Client::new().execute(&request);
+        assert_eq!(
+            error.map(|_| ()).map_err(|e| e.to_string()),
+            Err("more than 2 redirects in a row".to_string())
+        );
+        #[allow(clippy::unwrap_used)]
+        let requests = server.join().unwrap()?;
+        assert_eq!(requests.len(), 3);
+
+        let (port, server) = serve_local(&[REDIRECT])?;
+        let url = web_url(&format!("http://127.0.0.1:{port}/"))?;
+        let request = Request::builder(RequestMethod::Get, &url)
+            .redirect_policy(RedirectPolicy::None)
+            .build();
+        let response = Client::new().execute(&request)?;
+        assert_eq!(response.status_code(), 301);
+        #[allow(clippy::unwrap_used)]
+        server.join().unwrap()?;
+        Ok(())
+    }
+
+    const OK: &[u8] = b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok";
+
+    /// A GET request that fails instead of hanging if the server doesn't answer,
+    /// e.g. because it's waiting for a connection that the client never opens.
+    fn get_request(port: u16) -> Result<Request> {
+        let url = web_url(&format!("http://127.0.0.1:{port}/"))?;
+        Ok(Request::builder(RequestMethod::Get, &url)
+            .timeout(Duration::from_secs(5))
+            .build())
+    }
+
+    #[test]
+    fn reuses_connections() -> Result<()> {
+        let (listener, port) = listen()?;
+        let server = serve_connections(listener, vec![vec![OK.to_vec(); 3]]);
+        let client = Client::new();
+        let request = get_request(port)?;
+        client.execute(&request)?;
+        // Clones share their connections.
+        client.clone().execute(&request)?;
+        client.execute(&request)?;
+        assert_eq!(client.pool.idle_count(), 1);
+        #[allow(clippy::unwrap_used)]
+        let requests = server.join().unwrap()?;
+        assert_eq!(requests.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn retries_closed_connections() -> Result<()> {
+        let (listener, port) = listen()?;
+        // The server closes the first connection after one response,
+        // without saying so.
+        let server = serve_connections(listener, vec![vec![OK.to_vec()], vec![OK.to_vec()]]);
+        let client = Client::new();
+        let request = get_request(port)?;
+        client.execute(&request)?;
+        assert_eq!(client.pool.idle_count(), 1);
+        assert_eq!(client.execute(&request)?.text(), "ok");
+        #[allow(clippy::unwrap_used)]
+        server.join().unwrap()?;
+        Ok(())
+    }
+
+    #[test]
+    fn closes_connections() -> Result<()> {
+        let (listener, port) = listen()?;
+        let server = serve(
+            listener,
+            vec![
+                b"HTTP/1.1 200 OK\r\nConnection: close\r\ncontent-length: 0\r\n\r\n".to_vec(),
+                b"HTTP/1.1 200 OK\r\n\r\nuntil the end".to_vec(),
+                b"HTTP/1.1 200 OK\r\nkeep-alive: timeout=0\r\ncontent-length: 0\r\n\r\n".to_vec(),
+                OK.to_vec(),
+            ],
+        );
+        let client = Client::new();
+        let request = get_request(port)?;
+        client.execute(&request)?;
+        assert_eq!(client.pool.idle_count(), 0);
+        let response = client.execute(&request)?;
+        assert_eq!(response.text(), "until the end");
+        assert_eq!(client.pool.idle_count(), 0);
+        // Kept for 0 seconds, so it's closed instead of being used for the next request.
+        client.execute(&request)?;
+        client.execute(&request)?;
+        assert_eq!(client.pool.idle_count(), 1);
+
+        // Neither are connections for requests that asked for them to be closed.
+        let (listener, port) = listen()?;
+        let server_2 = serve(listener, vec![OK.to_vec()]);
+        let url = web_url(&format!("http://127.0.0.1:{port}/"))?;
+        let one_off_request = Request::builder(RequestMethod::Get, &url)
+            .keep_alive(false)
+            .build();
+        client.execute(&one_off_request)?;
+        assert_eq!(client.pool.idle_count(), 1);
+        #[allow(clippy::unwrap_used)]
+        server.join().unwrap()?;
+        #[allow(clippy::unwrap_used)]
+        server_2.join().unwrap()?;
+        Ok(())
+    }
+
+    #[test]
+    fn streams_bodies() -> Result<()> {
+        let (listener, port) = listen()?;
+        let (sender, receiver) = mpsc::channel();
+        let server = thread::spawn(move || -> Result<()> {
+            let (mut stream, _) = listener.accept()?;
+            read_request(&mut stream)?;
+            stream.write_all(
+                b"HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n5\r\nhello\r\n",
+            )?;
+            // Wait until the client has read the first chunk before sending the rest.
+            receiver.recv()?;
+            stream.write_all(b"7\r\n, world\r\n0\r\n\r\n")?;
+            read_request(&mut stream)?;
+            stream.write_all(OK)?;
+            Ok(())
+        });
+
+        let client = Client::new();
+        let request = get_request(port)?;
+        let mut response = client.execute_streaming(&request)?;
+        assert_eq!(response.status_code(), 200);
+        assert_eq!(
+            response
+                .headers()
+                .get_single_value("transfer-encoding")
+                .transpose()?,
+            Some(&"chunked".to_string())
+        );
+        let mut hello = [0; 5];
+        response.body().read_exact(&mut hello)?;
+        assert_eq!(&hello, b"hello");
+        assert_eq!(client.pool.idle_count(), 0);
+
+        sender.send(())?;
+        let mut rest = String::new();
+        response.into_body().read_to_string(&mut rest)?;
+        assert_eq!(rest, ", world");
+        // The connection went back into the pool as soon as the body ended.
+        assert_eq!(client.pool.idle_count(), 1);
+        assert_eq!(client.execute(&request)?.text(), "ok");
+        #[allow(clippy::unwrap_used)]
+        server.join().unwrap()?;
+        Ok(())
+    }
+
+    #[test]
+    fn unread_bodies_close_connections() -> Result<()> {
+        let (listener, port) = listen()?;
+        let server = serve(
+            listener,
+            vec![
+                b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nhello".to_vec(),
+                OK.to_vec(),
+            ],
+        );
+        let client = Client::new();
+        let request = get_request(port)?;
+        let mut response = client.execute_streaming(&request)?;
+        let mut buf = [0; 2];
+        response.body().read_exact(&mut buf)?;
+        drop(response);
+        assert_eq!(client.pool.idle_count(), 0);
+        assert_eq!(client.execute(&request)?.text(), "ok");
+        #[allow(clippy::unwrap_used)]
+        server.join().unwrap()?;
+        Ok(())
+    }
+
+    #[test]
+    fn waiting_for_a_held_connection_times_out() -> Result<()> {
+        let url = web_url("http://example.org/")?;
+        let transport = ScriptedTransport::new();
+        transport.add_response(
+            "example.org",
+            80,
+            b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nhello".to_vec(),
+        );
+        let client = Client::with_connection_limits(1, 1).with_scripted_transport(transport);
+        let request = Request::builder(RequestMethod::Get, &url)
+            .connect_timeout(Duration::from_millis(50))
+            .build();
+        let mut response = client.execute_streaming(&request)?;
+        // The only connection is still in use for the body, on this very thread.
+        assert!(matches!(
+            client.execute(&request),
+            Err(HttpError::Timeout(Timeout::Connect))
+        ));
+        let mut body = String::new();
+        response.body().read_to_string(&mut body)?;
+        assert_eq!(body, "hello");
+        Ok(())
+    }
+
+    /// Accepts one connection on `listener`, and never answers.
+    fn hang(listener: TcpListener) -> JoinHandle<Result<()>> {
+        thread::spawn(move || -> Result<()> {
+            let (mut stream, _) = listener.accept()?;
+            io::copy(&mut stream, &mut io::sink())?;
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn timeouts() -> Result<()> {
+        const SHORT: Duration = Duration::from_millis(50);
+        let timeout = |configure: fn(RequestBuilder) -> RequestBuilder| -> Result<Option<Timeout>> {
+            let (listener, port) = listen()?;
+            let server = hang(listener);
+            let url = web_url(&format!("http://127.0.0.1:{port}/"))?;
+            let request = configure(Request::builder(RequestMethod::Get, &url)).build();
+            // The client is dropped, and the connection closed, so the server stops.
+            let result = Client::new().execute(&request);
+            #[allow(clippy::unwrap_used)]
+            server.join().unwrap()?;
+            Ok(match result {
+                Err(HttpError::Timeout(timeout)) => Some(timeout),
+                _ => None,
+            })
+        };
+        assert_eq!(
+            timeout(|request| request.read_timeout(SHORT))?,
+            Some(Timeout::Read)
+        );
+        assert_eq!(
+            timeout(|request| request.timeout(SHORT))?,
+            Some(Timeout::Total)
+        );
+        assert_eq!(
+            timeout(|request| request.read_timeout(Duration::from_secs(5)).timeout(SHORT))?,
+            Some(Timeout::Total)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn limits() {
+        let limits = Limits {
+            read: Some(Duration::from_secs(1)),
+            deadline: Some(Instant::now() + Duration::from_secs(60)),
+        };
+        let connect_timeout = Some(Duration::from_secs(2));
+        assert!(matches!(
+            limits.next(connect_timeout, Timeout::Connect),
+            Ok((Some(timeout), Timeout::Connect)) if timeout == Duration::from_secs(2)
+        ));
+        assert!(matches!(
+            limits.next(None, Timeout::Connect),
+            Ok((Some(_), Timeout::Total))
+        ));
+
+        let past = Limits {
+            read: None,
+            deadline: Some(Instant::now()),
+        };
+        assert!(past.next(connect_timeout, Timeout::Connect).is_err());
+        assert!(matches!(
+            Limits {
+                read: None,
+                deadline: None
+            }
+            .next(None, Timeout::Read),
+            Ok((None, Timeout::Read))
+        ));
+    }
+
+    fn page_body(url: &WebUrl) -> String {
+        format!("<html><body>Hello from {url}</body></html>")
+    }
+
+    /// A page like the one at `url`, for a [`ScriptedTransport`] to answer with.
+    fn page(url: &WebUrl, extra_headers: &str) -> Vec<u8> {
+        let body = page_body(url);
+        format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: text/html\r\n{extra_headers}content-length: {}\r\n\r\n{body}",
+            body.len()
+        )
+        .into_bytes()
+    }
+
+    fn scripted_client(transport: &ScriptedTransport) -> Client {
+        Client::new().with_scripted_transport(transport.clone())
+    }
+
+    #[test]
+    fn close() -> Result<()> {
+        let transport = ScriptedTransport::new();
+        let client = scripted_client(&transport);
+        for url in [
+            "http://example.com",
+            "https://browser.engineering/http.html",
+        ] {
+            let url = web_url(url)?;
+            transport.add_response(
+                &url.host.to_string(),
+                url.port,
+                page(&url, "connection: close\r\n"),
+            );
+            let response = client.get(&url)?;
+            assert!(response.body().is_some());
+        }
+        assert_eq!(client.pool.idle_count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn https_uses_tls_with_other_transports() -> Result<()> {
+        let url = web_url("https://browser.engineering/http.html")?;
+        let transport = ScriptedTransport::new();
+        transport.add_response("browser.engineering", 443, page(&url, ""));
+        let client = Client::new().with_transport(Arc::new(transport.clone()));
+        // The plain text response isn't what a TLS handshake expects.
+        assert!(client.get(&url).is_err());
+        assert!(transport.requests().is_empty());
+        Ok(())
+    }
+
+    fn test_url_keepalive(url: &str) -> Result<()> {
+        let url = web_url(url)?;
+        let host = url.host.to_string();
+        let transport = ScriptedTransport::new();
+        transport.add_connection(&host, url.port, vec![page(&url, ""), page(&url, "")]);
+        transport.add_response(&host, url.port, page(&url, "connection: close\r\n"));
+        let request = Request::builder(RequestMethod::Get, &url).build();
+
+        let client = scripted_client(&transport);
+        let first_response = client.execute(&request)?;
+        assert!(first_response.body().is_some());
+        let second_response = client.execute(&request)?;
+        assert_eq!(first_response.body(), second_response.body());
+
+        let one_off_request = Request::builder(RequestMethod::Get, &url)
+            .keep_alive(false)
+            .build();
+        let one_off_response = scripted_client(&transport).execute(&one_off_request)?;
+        assert_eq!(first_response.body(), one_off_response.body());
+        assert_eq!(second_response.body(), one_off_response.body());
+
+        // The first two requests went on the same connection.
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 3);
+        assert!(requests[1].contains("connection: keep-alive\r\n"));
+        assert!(requests[2].contains("connection: close\r\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn keep_alive() -> Result<()> {
+        test_url_keepalive("http://example.com")?;
+        test_url_keepalive("http://browser.engineering/http.html")
+    }
+
+    #[test]
+    fn keep_alive_https() -> Result<()> {
+        test_url_keepalive("https://example.com")?;
+        test_url_keepalive("https://browser.engineering/http.html")
+    }
+
+    #[test]
+    fn gzipped_matches_uncompressed() -> Result<()> {
+        let url = web_url("https://browser.engineering/http.html")?;
+        let uncompressed = page(&url, "");
+        let body = page_body(&url);
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(body.as_bytes())?;
+        let gzipped = encoder.finish()?;
+        let mut compressed = format!(
+            "HTTP/1.1 200 OK\r\ncontent-encoding: gzip\r\ncontent-length: {}\r\n\r\n",
+            gzipped.len()
+        )
+        .into_bytes();
+        compressed.extend(gzipped);
+
+        let transport = ScriptedTransport::new();
+        transport.add_response("browser.engineering", 443, uncompressed.clone());
+        transport.add_response("browser.engineering", 443, compressed);
+
+        let request_uncompressed = Request::builder(RequestMethod::Get, &url)
+            .compression(false)
+            .build();
+        let response_uncompressed = scripted_client(&transport).execute(&request_uncompressed)?;
+
+        let request_compressed = Request::builder(RequestMethod::Get, &url).build();
+        let response_compressed = scripted_client(&transport).execute(&request_compressed)?;
+
+        assert_eq!(response_compressed.body(), response_uncompressed.body());
+        assert_eq!(response_uncompressed.body(), Some(body.as_bytes()));
+        let requests = transport.requests();
+        assert!(!requests[0].contains("accept-encoding"));
+        assert!(requests[1].contains("accept-encoding: gzip"));
+        Ok(())
+    }
+}
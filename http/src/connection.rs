@@ -0,0 +1,128 @@
+//! Plain and TLS connections to web servers.
+
+use std::io;
+use std::io::{Read, Write};
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+
+use octo_url::{Scheme, WebUrl};
+use rustls::pki_types::ServerName;
+
+use crate::request::RequestError;
+use crate::transport::{Stream, Transport};
+
+static ROOT_STORE: LazyLock<Arc<rustls::RootCertStore>> = LazyLock::new(|| {
+    Arc::new(rustls::RootCertStore::from_iter(
+        webpki_roots::TLS_SERVER_ROOTS.iter().cloned(),
+    ))
+});
+
+/// The number of root certificates HTTPS connections are verified against.
+pub fn tls_root_count() -> usize {
+    ROOT_STORE.len()
+}
+
+static CONFIG: LazyLock<Arc<rustls::ClientConfig>> = LazyLock::new(|| {
+    Arc::new(
+        rustls::ClientConfig::builder()
+            .with_root_certificates(ROOT_STORE.clone())
+            .with_no_client_auth(),
+    )
+});
+
+/// Abstraction over both plain connections and `rustls::StreamOwned` on top of them,
+/// opened by a [`Transport`].
+#[derive(Debug)]
+pub(crate) enum GenericTcpStream {
+    Insecure(Box<dyn Stream>),
+    Secure(Box<rustls::StreamOwned<rustls::ClientConnection, Box<dyn Stream>>>),
+}
+
+impl GenericTcpStream {
+    /// Connects to the host of `url` with `transport`, over TLS if it's an `https` URL
+    /// (unless `plain_text_https`, which is only for scripted transports).
+    /// `timeout` applies to connecting and to every read and write after that.
+    pub(crate) fn connect(
+        transport: &dyn Transport,
+        url: &WebUrl,
+        timeout: Option<Duration>,
+        plain_text_https: bool,
+    ) -> Result<Self, RequestError> {
+        match url.scheme {
+            Scheme::Http => Self::connect_insecure(transport, url, timeout),
+            Scheme::Https if plain_text_https => Self::connect_insecure(transport, url, timeout),
+            Scheme::Https => Self::connect_secure(transport, url, timeout),
+            scheme => Err(RequestError::InvalidScheme(scheme)),
+        }
+    }
+
+    fn connect_insecure(
+        transport: &dyn Transport,
+        url: &WebUrl,
+        timeout: Option<Duration>,
+    ) -> Result<Self, RequestError> {
+        let stream = transport.connect(&url.host, url.port, timeout)?;
+        Ok(Self::Insecure(stream))
+    }
+
+    fn connect_secure(
+        transport: &dyn Transport,
+        url: &WebUrl,
+        timeout: Option<Duration>,
+    ) -> Result<Self, RequestError> {
+        // rustls doesn't send SNI for IP addresses, and verifies them against
+        // the IP address SANs of the certificate instead.
+        let server_name = match url.host.ip_addr() {
+            Some(address) => ServerName::IpAddress(address.into()),
+            None => ServerName::try_from(url.host.to_string())?,
+        };
+        let stream = transport.connect(&url.host, url.port, timeout)?;
+        let client = rustls::ClientConnection::new(CONFIG.clone(), server_name)?;
+        let tls = rustls::StreamOwned::new(client, stream);
+        Ok(Self::Secure(Box::new(tls)))
+    }
+
+    fn stream(&self) -> &dyn Stream {
+        match self {
+            Self::Insecure(stream) => stream.as_ref(),
+            Self::Secure(stream) => stream.sock.as_ref(),
+        }
+    }
+
+    /// Whether the server has closed the connection (or sent something we didn't ask for,
+    /// which makes it just as unusable), checked without blocking.
+    pub(crate) fn is_closed(&self) -> bool {
+        self.stream().is_closed()
+    }
+
+    /// Changes the timeout of reads and writes, e.g. when reusing the connection
+    /// for a request with a different timeout.
+    pub(crate) fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream().set_timeout(timeout)
+    }
+}
+
+impl Read for GenericTcpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Insecure(stream) => stream.read(buf),
+            Self::Secure(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for GenericTcpStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Insecure(stream) => stream.write(buf),
+            Self::Secure(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Insecure(stream) => stream.flush(),
+            Self::Secure(stream) => stream.flush(),
+        }
+    }
+}
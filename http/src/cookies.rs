@@ -0,0 +1,874 @@
+//! Storing the cookies that servers set, and sending them back
+//! (https://datatracker.ietf.org/doc/html/draft-ietf-httpbis-rfc6265bis).
+
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use octo_url::{Host, Scheme, WebUrl};
+use thiserror::Error;
+
+use crate::headers::Headers;
+use crate::request::RequestMethod;
+use crate::write_atomically;
+
+/// Cookies can't be kept for longer than this, whatever they say.
+const MAX_AGE_DAYS: i64 = 400;
+/// The longest name and value a cookie can have, together.
+const MAX_NAME_VALUE_LEN: usize = 4096;
+/// The longest value an attribute can have.
+const MAX_ATTRIBUTE_VALUE_LEN: usize = 1024;
+/// The first line of a saved cookie jar.
+const FILE_HEADER: &str = "# Octo cookies";
+
+#[derive(Error, Debug)]
+pub enum CookieJarError {
+    #[error("error reading or writing the cookie file: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// When a cookie is sent with requests from other sites.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum SameSite {
+    /// Only with requests from the same site.
+    Strict,
+    /// Also when navigating to the site from elsewhere. Cookies that don't say are `Lax`.
+    #[default]
+    Lax,
+    /// With every request. Only allowed for `Secure` cookies.
+    None,
+}
+
+impl Display for SameSite {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let same_site = match self {
+            Self::Strict => "Strict",
+            Self::Lax => "Lax",
+            Self::None => "None",
+        };
+        write!(f, "{same_site}")
+    }
+}
+
+impl FromStr for SameSite {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s.to_ascii_lowercase().as_str() {
+            "strict" => Ok(Self::Strict),
+            "lax" => Ok(Self::Lax),
+            "none" => Ok(Self::None),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A cookie in a [`CookieJar`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    /// The host the cookie was set by, or the domain it was set for.
+    pub domain: String,
+    /// Whether the cookie is only sent to `domain` itself, and not to its subdomains,
+    /// which is the case unless it had a `Domain` attribute.
+    pub host_only: bool,
+    pub path: String,
+    /// When the cookie expires, or `None` if it only lasts until the browser is closed.
+    pub expires: Option<DateTime<Utc>>,
+    /// Whether the cookie is only sent over HTTPS.
+    pub secure: bool,
+    /// Whether the cookie is hidden from scripts.
+    pub http_only: bool,
+    pub same_site: SameSite,
+    pub created: DateTime<Utc>,
+}
+
+impl Cookie {
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    /// Whether the cookie would be sent to `host`.
+    fn matches_host(&self, host: &str) -> bool {
+        if self.host_only {
+            host == self.domain
+        } else {
+            domain_matches(host, &self.domain)
+        }
+    }
+
+    /// Whether this cookie replaces `other` when it's stored.
+    fn is_same_cookie(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.domain == other.domain
+            && self.host_only == other.host_only
+            && self.path == other.path
+    }
+
+    /// The cookie as it's sent in a `Cookie` header.
+    fn pair(&self) -> String {
+        if self.name.is_empty() {
+            self.value.clone()
+        } else {
+            format!("{}={}", self.name, self.value)
+        }
+    }
+}
+
+/// Whether `host` is `domain`, or a subdomain of it. IP addresses only match themselves.
+fn domain_matches(host: &str, domain: &str) -> bool {
+    host == domain
+        || (host
+            .strip_suffix(domain)
+            .is_some_and(|subdomain| subdomain.ends_with('.'))
+            && host
+                .parse::<Host>()
+                .is_ok_and(|host| host.ip_addr().is_none()))
+}
+
+/// Whether a cookie with `cookie_path` is sent with requests for `path`.
+fn path_matches(path: &str, cookie_path: &str) -> bool {
+    path.strip_prefix(cookie_path)
+        .is_some_and(|rest| cookie_path.ends_with('/') || rest.is_empty() || rest.starts_with('/'))
+}
+
+/// The path of a cookie set by a response to `path` without a `Path` attribute:
+/// the "directory" of `path`, e.g. `/docs` for `/docs/index.html`.
+fn default_path(path: &str) -> String {
+    match path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(i) => path[..i].to_string(),
+    }
+}
+
+/// Parses the leading 1 or 2 (or `min_len` to `max_len`) digits of a token in a cookie date.
+fn leading_number(token: &str, min_len: usize, max_len: usize) -> Option<u32> {
+    let len = token
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(token.len());
+    (min_len..=max_len)
+        .contains(&len)
+        .then(|| token[..len].parse().ok())
+        .flatten()
+}
+
+/// Parses the date of an `Expires` attribute, which comes in many formats, e.g.
+/// `Wed, 21 Oct 2015 07:28:00 GMT` or `Wednesday, 21-Oct-15 07:28:00 GMT`
+/// (https://www.rfc-editor.org/rfc/rfc6265#section-5.1.1).
+fn parse_date(date: &str) -> Option<DateTime<Utc>> {
+    const MONTHS: [&str; 12] = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+    let is_delimiter = |c: char| matches!(c, '\t' | ' '..='/' | ';'..='@' | '['..='`' | '{'..='~');
+
+    let (mut time, mut day, mut month, mut year) = (None, None, None, None);
+    for token in date.split(is_delimiter).filter(|token| !token.is_empty()) {
+        if time.is_none() {
+            let parts = token.splitn(3, ':').collect::<Vec<_>>();
+            if let [hour, minute, second] = parts[..] {
+                if let (Some(hour), Some(minute), Some(second)) = (
+                    leading_number(hour, 1, 2).filter(|_| hour.len() <= 2),
+                    leading_number(minute, 1, 2).filter(|_| minute.len() <= 2),
+                    leading_number(second, 1, 2),
+                ) {
+                    time = Some((hour, minute, second));
+                    continue;
+                }
+            }
+        }
+        if day.is_none() {
+            if let Some(number) = leading_number(token, 1, 2) {
+                day = Some(number);
+                continue;
+            }
+        }
+        if month.is_none() {
+            let prefix = token.get(..3).unwrap_or_default().to_ascii_lowercase();
+            if let Some(i) = MONTHS.iter().position(|month| *month == prefix) {
+                month = Some(i as u32 + 1);
+                continue;
+            }
+        }
+        if year.is_none() {
+            if let Some(number) = leading_number(token, 2, 4) {
+                year = Some(match number {
+                    70..=99 => number + 1900,
+                    0..=69 => number + 2000,
+                    _ => number,
+                });
+            }
+        }
+    }
+
+    let (hour, minute, second) = time?;
+    let year = i32::try_from(year?).ok().filter(|year| *year >= 1601)?;
+    NaiveDate::from_ymd_opt(year, month?, day?)?
+        .and_hms_opt(hour, minute, second)
+        .map(|date| date.and_utc())
+}
+
+/// The parts of a `Set-Cookie` header, before they're checked against the URL that set it.
+#[derive(Debug, Default)]
+struct SetCookie {
+    name: String,
+    value: String,
+    expires: Option<DateTime<Utc>>,
+    domain: Option<Host>,
+    path: Option<String>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl SetCookie {
+    /// Parses a `Set-Cookie` header, received at `now`
+    /// (https://datatracker.ietf.org/doc/html/draft-ietf-httpbis-rfc6265bis#section-5.6).
+    fn parse(header: &str, now: DateTime<Utc>) -> Option<Self> {
+        if header.chars().any(|c| c.is_control() && c != '\t') {
+            return None;
+        }
+        let trim = |s: &str| s.trim_matches([' ', '\t']).to_string();
+        let mut parts = header.split(';');
+        let name_value = parts.next().unwrap_or_default();
+        let (name, value) = match name_value.split_once('=') {
+            Some((name, value)) => (trim(name), trim(value)),
+            None => (String::new(), trim(name_value)),
+        };
+        if (name.is_empty() && value.is_empty()) || name.len() + value.len() > MAX_NAME_VALUE_LEN {
+            return None;
+        }
+
+        let mut cookie = Self {
+            name,
+            value,
+            ..Self::default()
+        };
+        let max_expires = now + TimeDelta::days(MAX_AGE_DAYS);
+        let mut max_age = None;
+        for attribute in parts {
+            let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+            let value = trim(value);
+            if value.len() > MAX_ATTRIBUTE_VALUE_LEN {
+                continue;
+            }
+            match trim(key).to_ascii_lowercase().as_str() {
+                "expires" => {
+                    if let Some(expires) = parse_date(&value) {
+                        cookie.expires = Some(expires.min(max_expires));
+                    }
+                }
+                "max-age" => {
+                    let digits = value.strip_prefix('-').unwrap_or(&value);
+                    if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+                        // Anything too big to parse is well past the limit anyway.
+                        let seconds = value.parse::<i64>().unwrap_or(i64::MAX);
+                        max_age = Some(if seconds <= 0 {
+                            DateTime::<Utc>::MIN_UTC
+                        } else {
+                            TimeDelta::try_seconds(seconds)
+                                .and_then(|max_age| now.checked_add_signed(max_age))
+                                .map_or(max_expires, |expires| expires.min(max_expires))
+                        });
+                    }
+                }
+                "domain" if !value.is_empty() => {
+                    // Parsed like the host of a URL, so that it's in the same (ASCII) form.
+                    // A cookie for a domain that isn't valid can't be set.
+                    let domain = value.strip_prefix('.').unwrap_or(&value);
+                    cookie.domain = Some(domain.parse().ok()?);
+                }
+                // Paths are saved as they are, so they can't have tabs or other controls.
+                "path" => {
+                    cookie.path = (value.starts_with('/') && !value.contains(char::is_control))
+                        .then_some(value)
+                }
+                "secure" => cookie.secure = true,
+                "httponly" => cookie.http_only = true,
+                "samesite" => cookie.same_site = value.parse().ok(),
+                _ => {}
+            }
+        }
+        // Max-Age wins over Expires, whichever comes first.
+        cookie.expires = max_age.or(cookie.expires);
+        Some(cookie)
+    }
+
+    /// Turns this into a cookie set by a response from `url`, or `None` if `url`
+    /// isn't allowed to set it
+    /// (https://datatracker.ietf.org/doc/html/draft-ietf-httpbis-rfc6265bis#section-5.7).
+    fn into_cookie(self, url: &WebUrl, now: DateTime<Utc>) -> Option<Cookie> {
+        let host = url.host.to_string();
+        let is_secure = url.scheme == Scheme::Https;
+
+        let (domain, host_only) = match self.domain {
+            // Cookies can't be set for a whole public suffix like `com` or `github.io`,
+            // unless that's the host that sets them.
+            Some(domain) if domain.registrable_domain().is_none() => {
+                (domain.to_string() == host).then_some((host, true))?
+            }
+            Some(domain) => {
+                let domain = domain.to_string();
+                domain_matches(&host, &domain).then_some((domain, false))?
+            }
+            None => (host, true),
+        };
+        let path = self.path.unwrap_or_else(|| default_path(&url.path));
+        let same_site = self.same_site.unwrap_or_default();
+
+        if self.secure && !is_secure {
+            return None;
+        }
+        if same_site == SameSite::None && !self.secure {
+            return None;
+        }
+        let name = self.name.to_ascii_lowercase();
+        if name.starts_with("__secure-") && !self.secure {
+            return None;
+        }
+        if name.starts_with("__host-") && !(self.secure && host_only && path == "/") {
+            return None;
+        }
+
+        Some(Cookie {
+            name: self.name,
+            value: self.value,
+            domain,
+            host_only,
+            path,
+            expires: self.expires,
+            secure: self.secure,
+            http_only: self.http_only,
+            same_site,
+            created: now,
+        })
+    }
+}
+
+/// The cookies set by the responses to a [`Client`](crate::client::Client)'s requests.
+/// It's shared by all the clones of a client, and can be saved to a file
+/// and loaded again, to keep the cookies that last beyond the session.
+#[derive(Debug, Default)]
+pub struct CookieJar {
+    cookies: Mutex<Vec<Cookie>>,
+}
+
+impl CookieJar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Cookie>> {
+        // Every change to the cookies is a single push or remove, so they're never
+        // left half-changed by a panic.
+        self.cookies.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Stores the cookie from a `Set-Cookie` header in a response from `url`.
+    /// Returns whether it was stored: invalid cookies, and cookies that `url`
+    /// isn't allowed to set, are ignored.
+    pub fn store(&self, url: &WebUrl, set_cookie: &str) -> bool {
+        self.store_at(url, set_cookie, Utc::now())
+    }
+
+    /// Stores the cookies from all the `Set-Cookie` headers in `headers`.
+    pub(crate) fn store_all(&self, url: &WebUrl, headers: &Headers) {
+        for set_cookie in headers.get("set-cookie").into_iter().flatten() {
+            self.store(url, set_cookie);
+        }
+    }
+
+    fn store_at(&self, url: &WebUrl, set_cookie: &str, now: DateTime<Utc>) -> bool {
+        let Some(mut cookie) =
+            SetCookie::parse(set_cookie, now).and_then(|cookie| cookie.into_cookie(url, now))
+        else {
+            return false;
+        };
+        let mut cookies = self.lock();
+        // Plain HTTP can't overwrite (or shadow) secure cookies.
+        if url.scheme != Scheme::Https
+            && cookies.iter().any(|existing| {
+                existing.secure
+                    && existing.name == cookie.name
+                    && (domain_matches(&existing.domain, &cookie.domain)
+                        || domain_matches(&cookie.domain, &existing.domain))
+                    && path_matches(&cookie.path, &existing.path)
+            })
+        {
+            return false;
+        }
+        let existing = cookies
+            .iter()
+            .position(|existing| existing.is_same_cookie(&cookie));
+        // An expired cookie only removes the one it replaces.
+        match existing {
+            Some(i) if cookie.is_expired(now) => {
+                cookies.remove(i);
+                false
+            }
+            Some(i) => {
+                cookie.created = cookies[i].created;
+                cookies[i] = cookie;
+                true
+            }
+            None if cookie.is_expired(now) => false,
+            None => {
+                cookies.push(cookie);
+                true
+            }
+        }
+    }
+
+    /// The value of the `Cookie` header for a request to `url` from the same site.
+    pub fn cookie_header(&self, url: &WebUrl) -> Option<String> {
+        self.cookie_header_at(url, RequestMethod::Get, true, Utc::now())
+    }
+
+    /// The value of the `Cookie` header for a top-level navigation to `url` with `method`,
+    /// where `same_site` says whether every URL so far was on the same site.
+    /// Cross-site navigations don't get the `Strict` cookies, nor the `Lax` ones
+    /// unless they're GET (or HEAD) requests.
+    pub(crate) fn cookie_header_for(
+        &self,
+        url: &WebUrl,
+        method: RequestMethod,
+        same_site: bool,
+    ) -> Option<String> {
+        self.cookie_header_at(url, method, same_site, Utc::now())
+    }
+
+    fn cookie_header_at(
+        &self,
+        url: &WebUrl,
+        method: RequestMethod,
+        same_site: bool,
+        now: DateTime<Utc>,
+    ) -> Option<String> {
+        let host = url.host.to_string();
+        let is_secure = url.scheme == Scheme::Https;
+        let is_safe_method = matches!(method, RequestMethod::Get | RequestMethod::Head);
+
+        let mut cookies = self.lock();
+        cookies.retain(|cookie| !cookie.is_expired(now));
+        let mut matching = cookies
+            .iter()
+            .filter(|cookie| {
+                cookie.matches_host(&host)
+                    && path_matches(&url.path, &cookie.path)
+                    && (is_secure || !cookie.secure)
+                    && match cookie.same_site {
+                        SameSite::Strict => same_site,
+                        SameSite::Lax => same_site || is_safe_method,
+                        SameSite::None => true,
+                    }
+            })
+            .collect::<Vec<_>>();
+        if matching.is_empty() {
+            return None;
+        }
+        // Cookies with longer paths go first, then the oldest ones.
+        matching.sort_by(|a, b| {
+            b.path
+                .len()
+                .cmp(&a.path.len())
+                .then_with(|| a.created.cmp(&b.created))
+        });
+        Some(
+            matching
+                .iter()
+                .map(|cookie| cookie.pair())
+                .collect::<Vec<_>>()
+                .join("; "),
+        )
+    }
+
+    /// The cookies that haven't expired yet.
+    pub fn cookies(&self) -> Vec<Cookie> {
+        let now = Utc::now();
+        let mut cookies = self.lock();
+        cookies.retain(|cookie| !cookie.is_expired(now));
+        cookies.clone()
+    }
+
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// Saves the cookies that last beyond the session to `path`, one per line.
+    pub fn save(&self, path: &Path) -> Result<(), CookieJarError> {
+        let now = Utc::now();
+        let mut contents = format!("{FILE_HEADER}\n");
+        for cookie in self.lock().iter() {
+            let Some(expires) = cookie.expires.filter(|_| !cookie.is_expired(now)) else {
+                continue;
+            };
+            // The name and value go last, as the value can have tabs in it.
+            contents.push_str(&format!(
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}={}\n",
+                cookie.domain,
+                cookie.host_only,
+                cookie.path,
+                cookie.secure,
+                cookie.http_only,
+                cookie.same_site,
+                expires.timestamp(),
+                cookie.created.timestamp(),
+                cookie.name,
+                cookie.value,
+            ));
+        }
+        write_atomically(path, contents.as_bytes())?;
+        Ok(())
+    }
+
+    /// Loads the cookies saved to `path` with [`CookieJar::save`].
+    /// Lines that can't be read are skipped, so that one bad cookie doesn't lose the rest.
+    pub fn load(path: &Path) -> Result<Self, CookieJarError> {
+        let contents = fs::read_to_string(path)?;
+        let cookies = contents
+            .lines()
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(parse_saved_cookie)
+            .collect();
+        Ok(Self {
+            cookies: Mutex::new(cookies),
+        })
+    }
+}
+
+/// Parses a line written by [`CookieJar::save`].
+fn parse_saved_cookie(line: &str) -> Option<Cookie> {
+    let fields = line.splitn(9, '\t').collect::<Vec<_>>();
+    let [domain, host_only, path, secure, http_only, same_site, expires, created, name_value] =
+        fields[..]
+    else {
+        return None;
+    };
+    let (name, value) = name_value.split_once('=')?;
+    let timestamp = |s: &str| DateTime::from_timestamp(s.parse().ok()?, 0);
+    Some(Cookie {
+        name: name.to_string(),
+        value: value.to_string(),
+        domain: domain.to_string(),
+        host_only: host_only.parse().ok()?,
+        path: path.to_string(),
+        expires: Some(timestamp(expires)?),
+        secure: secure.parse().ok()?,
+        http_only: http_only.parse().ok()?,
+        same_site: same_site.parse().ok()?,
+        created: timestamp(created)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::web_url;
+    use anyhow::Result;
+    use std::env;
+
+    fn date(date: &str) -> Result<DateTime<Utc>> {
+        Ok(DateTime::parse_from_rfc3339(date)?.to_utc())
+    }
+
+    /// Stores each of `set_cookies` as if they were received from `url`,
+    /// and returns whether each one was stored.
+    fn store_all(jar: &CookieJar, url: &str, set_cookies: &[&str]) -> Result<Vec<bool>> {
+        let url = web_url(url)?;
+        Ok(set_cookies
+            .iter()
+            .map(|set_cookie| jar.store(&url, set_cookie))
+            .collect())
+    }
+
+    fn cookie_header(jar: &CookieJar, url: &str) -> Result<Option<String>> {
+        Ok(jar.cookie_header(&web_url(url)?))
+    }
+
+    #[test]
+    fn dates() -> Result<()> {
+        let expected = date("2015-10-21T07:28:00Z")?;
+        for formatted in [
+            "Wed, 21 Oct 2015 07:28:00 GMT",
+            "Wednesday, 21-Oct-15 07:28:00 GMT",
+            "Wed Oct 21 07:28:00 2015",
+            "21 october 2015 7:28:0",
+        ] {
+            assert_eq!(parse_date(formatted), Some(expected), "{formatted}");
+        }
+        assert_eq!(
+            parse_date("Thu, 01 Jan 1970 00:00:00 GMT"),
+            Some(DateTime::UNIX_EPOCH)
+        );
+        assert_eq!(parse_date("Wed, 31 Feb 2015 07:28:00 GMT"), None);
+        assert_eq!(parse_date("Wed, 21 Oct 2015"), None);
+        assert_eq!(parse_date("Wed, 21 Oct 1600 07:28:00 GMT"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn attributes() -> Result<()> {
+        let now = date("2024-01-01T00:00:00Z")?;
+        let parse = |header: &str| SetCookie::parse(header, now);
+
+        #[allow(clippy::unwrap_used)]
+        let cookie = parse(
+            " id = a3fWa ; Expires=Wed, 21 Oct 2026 07:28:00 GMT; Domain=.Example.COM; \
+             Path=/docs; Secure; HttpOnly; SameSite=strict; Unknown=1",
+        )
+        .unwrap();
+        assert_eq!(
+            (cookie.name.as_str(), cookie.value.as_str()),
+            ("id", "a3fWa")
+        );
+        assert_eq!(cookie.expires, Some(now + TimeDelta::days(MAX_AGE_DAYS)));
+        assert_eq!(cookie.domain, Some(Host::Domain("example.com".to_string())));
+        assert_eq!(cookie.path.as_deref(), Some("/docs"));
+        assert!(cookie.secure && cookie.http_only);
+        assert_eq!(cookie.same_site, Some(SameSite::Strict));
+
+        // Max-Age wins over Expires.
+        let expires = |header: &str| parse(header).and_then(|cookie| cookie.expires);
+        assert_eq!(
+            expires("a=b; Max-Age=60; Expires=Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(now + TimeDelta::seconds(60))
+        );
+        assert_eq!(expires("a=b; Max-Age=0"), Some(DateTime::<Utc>::MIN_UTC));
+        assert_eq!(expires("a=b; Max-Age=1e3"), None);
+        assert_eq!(expires("a=b; Expires=tomorrow"), None);
+
+        #[allow(clippy::unwrap_used)]
+        let nameless = parse("just-a-value; Path=nowhere").unwrap();
+        assert_eq!(
+            (nameless.name.as_str(), nameless.value.as_str()),
+            ("", "just-a-value")
+        );
+        assert_eq!(nameless.path, None);
+
+        #[allow(clippy::unwrap_used)]
+        let unicode = parse("a=b; Domain=B\u{fc}cher.example").unwrap();
+        assert_eq!(
+            unicode.domain,
+            Some(Host::Domain("xn--bcher-kva.example".to_string()))
+        );
+        assert!(parse("a=b; Domain=exa mple.com").is_none());
+
+        assert!(parse("=").is_none());
+        assert!(parse("a=b\x07").is_none());
+        assert!(parse(&format!("a={}", "b".repeat(MAX_NAME_VALUE_LEN))).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn domains_and_paths() -> Result<()> {
+        let jar = CookieJar::new();
+        assert_eq!(
+            store_all(
+                &jar,
+                "https://www.example.co.uk/docs/page.html",
+                &[
+                    "host=1",
+                    "domain=2; Domain=example.co.uk",
+                    "root=3; Path=/",
+                    "public_suffix=4; Domain=co.uk",
+                    "other_domain=5; Domain=example.org",
+                    "subdomain=6; Domain=sub.www.example.co.uk",
+                ]
+            )?,
+            [true, true, true, false, false, false]
+        );
+
+        assert_eq!(
+            cookie_header(&jar, "https://www.example.co.uk/docs/other")?.as_deref(),
+            Some("host=1; domain=2; root=3")
+        );
+        assert_eq!(
+            cookie_header(&jar, "https://www.example.co.uk/documents")?.as_deref(),
+            Some("root=3")
+        );
+        assert_eq!(
+            cookie_header(&jar, "https://static.example.co.uk/docs")?.as_deref(),
+            Some("domain=2")
+        );
+        assert_eq!(cookie_header(&jar, "https://example.org/")?, None);
+
+        // Internationalized domains match the host they're set by, which is in Punycode.
+        assert_eq!(
+            store_all(
+                &jar,
+                "https://www.b\u{fc}cher.example/",
+                &["idn=7; Domain=b\u{fc}cher.example"]
+            )?,
+            [true]
+        );
+        assert_eq!(
+            cookie_header(&jar, "https://xn--bcher-kva.example/")?.as_deref(),
+            Some("idn=7")
+        );
+
+        // A public suffix can set a cookie for itself, as a host-only cookie.
+        assert_eq!(
+            store_all(&jar, "https://github.io/", &["a=b; Domain=github.io"])?,
+            [true]
+        );
+        assert_eq!(cookie_header(&jar, "https://x.github.io/")?, None);
+
+        // IP addresses only match exactly.
+        assert_eq!(
+            store_all(
+                &jar,
+                "http://127.0.0.1/",
+                &["ip=1; Domain=127.0.0.1", "ip=2; Domain=0.1"]
+            )?,
+            [true, false]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn secure_cookies() -> Result<()> {
+        let jar = CookieJar::new();
+        assert_eq!(
+            store_all(
+                &jar,
+                "http://example.com/",
+                &[
+                    "insecure=1",
+                    "secure=1; Secure",
+                    "__Secure-a=1",
+                    "none=1; SameSite=None"
+                ]
+            )?,
+            [true, false, false, false]
+        );
+        assert_eq!(
+            store_all(
+                &jar,
+                "https://example.com/",
+                &[
+                    "secure=2; Secure",
+                    "__Secure-a=2; Secure",
+                    "__Host-a=2; Secure; Path=/",
+                    "__Host-b=2; Secure; Path=/; Domain=example.com",
+                    "none=2; SameSite=None; Secure",
+                ]
+            )?,
+            [true, true, true, false, true]
+        );
+        assert_eq!(
+            cookie_header(&jar, "http://example.com/")?.as_deref(),
+            Some("insecure=1")
+        );
+        // Plain HTTP can't overwrite secure cookies.
+        assert_eq!(
+            store_all(&jar, "http://example.com/", &["secure=3"])?,
+            [false]
+        );
+        assert_eq!(
+            cookie_header(&jar, "https://example.com/")?.as_deref(),
+            Some("insecure=1; secure=2; __Secure-a=2; __Host-a=2; none=2")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn same_site() -> Result<()> {
+        let jar = CookieJar::new();
+        let url = web_url("https://example.com/")?;
+        store_all(
+            &jar,
+            "https://example.com/",
+            &[
+                "strict=1; SameSite=Strict",
+                "lax=1; SameSite=Lax",
+                "default=1",
+                "none=1; SameSite=None; Secure",
+            ],
+        )?;
+        let header = |method, same_site| jar.cookie_header_for(&url, method, same_site);
+        assert_eq!(
+            header(RequestMethod::Get, true).as_deref(),
+            Some("strict=1; lax=1; default=1; none=1")
+        );
+        assert_eq!(
+            header(RequestMethod::Get, false).as_deref(),
+            Some("lax=1; default=1; none=1")
+        );
+        assert_eq!(
+            header(RequestMethod::Post, false).as_deref(),
+            Some("none=1")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn replacing_and_expiring() -> Result<()> {
+        let jar = CookieJar::new();
+        let url = web_url("https://example.com/")?;
+        let now = date("2024-01-01T00:00:00Z")?;
+        assert!(jar.store_at(&url, "a=1; Max-Age=60", now));
+        assert!(jar.store_at(&url, "b=1", now));
+        assert!(jar.store_at(&url, "a=2; Max-Age=60", now + TimeDelta::seconds(30)));
+        let header_at = |seconds| {
+            jar.cookie_header_at(
+                &url,
+                RequestMethod::Get,
+                true,
+                now + TimeDelta::seconds(seconds),
+            )
+        };
+        // The new value keeps the place of the old one.
+        assert_eq!(header_at(40).as_deref(), Some("a=2; b=1"));
+        assert_eq!(header_at(90).as_deref(), Some("b=1"));
+
+        // Setting an expired cookie deletes the one it replaces.
+        assert!(!jar.store_at(&url, "b=; Expires=Thu, 01 Jan 1970 00:00:00 GMT", now));
+        assert_eq!(header_at(0), None);
+        Ok(())
+    }
+
+    #[test]
+    fn save_and_load() -> Result<()> {
+        let jar = CookieJar::new();
+        store_all(
+            &jar,
+            "https://www.example.com/docs/",
+            &[
+                "session=1",
+                "persistent=a\tb=c; Max-Age=3600; Domain=example.com; HttpOnly",
+                "secure=1; Max-Age=3600; Secure; SameSite=Strict; Path=/",
+                "expired=1; Max-Age=0",
+                // A path with a tab is ignored, so it can't shift the saved fields.
+                "tabbed=1; Max-Age=3600; Path=/a\tb",
+            ],
+        )?;
+        let path = env::temp_dir().join(format!("octo-cookies-{}.txt", std::process::id()));
+        jar.save(&path)?;
+        // A line that can't be read is skipped, and the others are still loaded.
+        let saved = fs::read_to_string(&path)?;
+        fs::write(&path, format!("{saved}example.com\tmaybe\n"))?;
+        let loaded = CookieJar::load(&path);
+        fs::remove_file(&path)?;
+
+        let mut expected = jar.cookies();
+        expected.retain(|cookie| cookie.expires.is_some());
+        // Only whole seconds are saved.
+        for cookie in &mut expected {
+            cookie.expires = cookie
+                .expires
+                .and_then(|expires| DateTime::from_timestamp(expires.timestamp(), 0));
+            cookie.created =
+                DateTime::from_timestamp(cookie.created.timestamp(), 0).unwrap_or(cookie.created);
+        }
+        assert_eq!(loaded?.cookies(), expected);
+        assert_eq!(expected.len(), 3);
+        assert!(expected
+            .iter()
+            .any(|cookie| cookie.name == "tabbed" && cookie.path == "/docs"));
+        Ok(())
+    }
+}
@@ -42,12 +42,6 @@ impl Headers {
         }
     }
 
-    pub(crate) fn add_many(&mut self, kv_pairs: &[(&str, &[&str])]) {
-        for (key, values) in kv_pairs {
-            self.add_header_values(key, values);
-        }
-    }
-
     /// Adds one header key/value pair, where the value is a single header value.
     /// Example: `add_one_pair("content-encoding", "gzip")`
     /// **Note:** The header **key** will be converted to lowercase, but the **value** will not.
@@ -56,6 +50,16 @@ impl Headers {
         self.add_header_values(key, &[value]);
     }
 
+    /// Removes a header and all of its values.
+    pub(crate) fn remove(&mut self, key: &str) {
+        self.headers.remove(&key.to_lowercase());
+    }
+
+    /// All the headers, in no particular order, with their keys in lowercase.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Vec<String>)> {
+        self.headers.iter()
+    }
+
     #[inline]
     pub fn get(&self, key: &str) -> Option<&Vec<String>> {
         self.headers.get(key)
@@ -91,12 +95,6 @@ impl Headers {
             .get(key)
             .map(|values| values.iter().any(|s| s.as_str() == value))
     }
-
-    pub(crate) fn from(kv_pairs: &[(&str, &[&str])]) -> Self {
-        let mut headers = Headers::default();
-        headers.add_many(kv_pairs);
-        headers
-    }
 }
 
 impl Display for Headers {
@@ -109,3 +107,417 @@ impl Display for Headers {
         write!(f, "{s}")
     }
 }
+
+/// The directives of `Cache-Control` headers, in requests and in responses
+/// (https://www.rfc-editor.org/rfc/rfc9111#section-5.2). Directive names are
+/// case-insensitive, and values can be tokens or quoted strings.
+/// If a directive is given more than once, the first one counts.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheControl {
+    /// How long the response stays fresh, or (in a request) how old a response can be.
+    /// An invalid value in a response makes it stale, as if it was 0.
+    pub max_age: Option<u64>,
+    /// How stale a response can be (requests only). `u64::MAX` if any staleness will do.
+    pub max_stale: Option<u64>,
+    /// How much longer a response has to stay fresh (requests only).
+    pub min_fresh: Option<u64>,
+    /// Like `max_age`, for shared caches only (responses only).
+    pub s_maxage: Option<u64>,
+    /// Whether the response has to be revalidated before each use. In a response,
+    /// it can be limited to some fields, which are the ones listed here.
+    pub no_cache: Option<Vec<String>>,
+    pub no_store: bool,
+    pub no_transform: bool,
+    /// Whether only a cached response will do (requests only).
+    pub only_if_cached: bool,
+    /// Whether a stale response must never be used without revalidating it (responses only).
+    pub must_revalidate: bool,
+    /// Whether the response can only be cached by caches that understand its status code
+    /// (responses only).
+    pub must_understand: bool,
+    /// Like `must_revalidate`, for shared caches only (responses only).
+    pub proxy_revalidate: bool,
+    /// Whether any cache can store the response (responses only).
+    pub public: bool,
+    /// Whether only private caches can store the response, or the fields listed here
+    /// (responses only).
+    pub private: Option<Vec<String>>,
+    /// Whether the response never changes while it's fresh, so there's no point
+    /// in revalidating it then, even on a reload (responses only,
+    /// https://www.rfc-editor.org/rfc/rfc8246).
+    pub immutable: bool,
+    /// How long a stale response can be used while it's revalidated in the background
+    /// (responses only, https://www.rfc-editor.org/rfc/rfc5861).
+    pub stale_while_revalidate: Option<u64>,
+    /// How long a stale response can be used if revalidating it fails
+    /// (https://www.rfc-editor.org/rfc/rfc5861).
+    pub stale_if_error: Option<u64>,
+    /// Directives we don't know about, with their values if they have one.
+    pub extensions: Vec<(String, Option<String>)>,
+}
+
+/// Splits the value of a `Cache-Control` header into its directives, with their names
+/// in lowercase, and their values unquoted.
+fn split_directives(value: &str) -> Vec<(String, Option<String>)> {
+    let mut directives = vec![];
+    let mut chars = value.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace() || *c == ',').is_some() {}
+        let mut name = String::new();
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != ',' && *c != '=') {
+            name.push(c);
+        }
+        if name.is_empty() {
+            break;
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+        let mut value = None;
+        if chars.next_if_eq(&'=').is_some() {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            let mut argument = String::new();
+            if chars.next_if_eq(&'"').is_some() {
+                // A quoted string, where a backslash escapes the next character.
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => argument.extend(chars.next()),
+                        c => argument.push(c),
+                    }
+                }
+            } else {
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != ',') {
+                    argument.push(c);
+                }
+            }
+            value = Some(argument);
+        }
+        // Skip anything else up to the next directive.
+        while chars.next_if(|c| *c != ',').is_some() {}
+        directives.push((name.to_ascii_lowercase(), value));
+    }
+    directives
+}
+
+/// Parses the list of field names in the value of `no-cache` or `private`.
+fn field_names(value: Option<&str>) -> Vec<String> {
+    value
+        .into_iter()
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+impl CacheControl {
+    /// Parses the directives of all the `Cache-Control` headers in `headers`.
+    pub fn from_headers(headers: &Headers) -> Self {
+        let values = headers.get("cache-control").map(|values| values.join(","));
+        Self::parse(values.as_deref().unwrap_or_default())
+    }
+
+    /// Parses the value of a `Cache-Control` header. Unknown directives end up in
+    /// `extensions`, and directives with invalid values are ignored.
+    pub fn parse(value: &str) -> Self {
+        let mut cache_control = Self::default();
+        let mut seen = vec![];
+        for (name, value) in split_directives(value) {
+            if seen.contains(&name) {
+                continue;
+            }
+            seen.push(name.clone());
+            let seconds = || value.as_deref()?.parse::<u64>().ok();
+            match name.as_str() {
+                "max-age" => cache_control.max_age = Some(seconds().unwrap_or(0)),
+                "max-stale" => {
+                    cache_control.max_stale = match &value {
+                        Some(_) => seconds(),
+                        None => Some(u64::MAX),
+                    }
+                }
+                "min-fresh" => cache_control.min_fresh = seconds(),
+                "s-maxage" => cache_control.s_maxage = Some(seconds().unwrap_or(0)),
+                "no-cache" => cache_control.no_cache = Some(field_names(value.as_deref())),
+                "no-store" => cache_control.no_store = true,
+                "no-transform" => cache_control.no_transform = true,
+                "only-if-cached" => cache_control.only_if_cached = true,
+                "must-revalidate" => cache_control.must_revalidate = true,
+                "must-understand" => cache_control.must_understand = true,
+                "proxy-revalidate" => cache_control.proxy_revalidate = true,
+                "public" => cache_control.public = true,
+                "private" => cache_control.private = Some(field_names(value.as_deref())),
+                "immutable" => cache_control.immutable = true,
+                "stale-while-revalidate" => cache_control.stale_while_revalidate = seconds(),
+                "stale-if-error" => cache_control.stale_if_error = seconds(),
+                _ => cache_control.extensions.push((name, value)),
+            }
+        }
+        cache_control
+    }
+}
+
+impl Display for CacheControl {
+    /// Writes the directives as the value of a `Cache-Control` header.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let quote = |value: &str| {
+            let is_token = !value.is_empty()
+                && value
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c));
+            if is_token {
+                value.to_string()
+            } else {
+                format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+            }
+        };
+        let field_names = |name: &str, names: &Vec<String>| {
+            if names.is_empty() {
+                name.to_string()
+            } else {
+                format!("{name}=\"{}\"", names.join(", "))
+            }
+        };
+
+        let mut directives = vec![];
+        for (name, seconds) in [
+            ("max-age", self.max_age),
+            ("max-stale", self.max_stale),
+            ("min-fresh", self.min_fresh),
+            ("s-maxage", self.s_maxage),
+            ("stale-while-revalidate", self.stale_while_revalidate),
+            ("stale-if-error", self.stale_if_error),
+        ] {
+            match seconds {
+                Some(u64::MAX) if name == "max-stale" => directives.push(name.to_string()),
+                Some(seconds) => directives.push(format!("{name}={seconds}")),
+                None => {}
+            }
+        }
+        if let Some(names) = &self.no_cache {
+            directives.push(field_names("no-cache", names));
+        }
+        if let Some(names) = &self.private {
+            directives.push(field_names("private", names));
+        }
+        for (flag, name) in [
+            (self.no_store, "no-store"),
+            (self.no_transform, "no-transform"),
+            (self.only_if_cached, "only-if-cached"),
+            (self.must_revalidate, "must-revalidate"),
+            (self.must_understand, "must-understand"),
+            (self.proxy_revalidate, "proxy-revalidate"),
+            (self.public, "public"),
+            (self.immutable, "immutable"),
+        ] {
+            if flag {
+                directives.push(name.to_string());
+            }
+        }
+        for (name, value) in &self.extensions {
+            directives.push(match value {
+                Some(value) => format!("{name}={}", quote(value)),
+                None => name.clone(),
+            });
+        }
+        write!(f, "{}", directives.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Option<Vec<String>> {
+        Some(names.iter().map(|name| name.to_string()).collect())
+    }
+
+    #[test]
+    fn cache_control_directives() {
+        let cases = [
+            ("", CacheControl::default()),
+            (
+                "max-age=600",
+                CacheControl {
+                    max_age: Some(600),
+                    ..CacheControl::default()
+                },
+            ),
+            (
+                "public, max-age=600",
+                CacheControl {
+                    public: true,
+                    max_age: Some(600),
+                    ..CacheControl::default()
+                },
+            ),
+            (
+                "  Max-Age = 60 ,, PRIVATE ",
+                CacheControl {
+                    max_age: Some(60),
+                    private: names(&[]),
+                    ..CacheControl::default()
+                },
+            ),
+            (
+                "max-age=\"60\"",
+                CacheControl {
+                    max_age: Some(60),
+                    ..CacheControl::default()
+                },
+            ),
+            // An invalid max-age makes the response stale.
+            (
+                "max-age=soon",
+                CacheControl {
+                    max_age: Some(0),
+                    ..CacheControl::default()
+                },
+            ),
+            (
+                "max-age=-1, s-maxage",
+                CacheControl {
+                    max_age: Some(0),
+                    s_maxage: Some(0),
+                    ..CacheControl::default()
+                },
+            ),
+            // The first one counts.
+            (
+                "max-age=60, max-age=3600",
+                CacheControl {
+                    max_age: Some(60),
+                    ..CacheControl::default()
+                },
+            ),
+            (
+                "no-cache, no-store, must-revalidate",
+                CacheControl {
+                    no_cache: names(&[]),
+                    no_store: true,
+                    must_revalidate: true,
+                    ..CacheControl::default()
+                },
+            ),
+            (
+                "no-cache=\"Set-Cookie, set-cookie2\", private=\"Authorization\"",
+                CacheControl {
+                    no_cache: names(&["set-cookie", "set-cookie2"]),
+                    private: names(&["authorization"]),
+                    ..CacheControl::default()
+                },
+            ),
+            (
+                "s-maxage=3600, proxy-revalidate, must-understand, no-transform",
+                CacheControl {
+                    s_maxage: Some(3600),
+                    proxy_revalidate: true,
+                    must_understand: true,
+                    no_transform: true,
+                    ..CacheControl::default()
+                },
+            ),
+            (
+                "max-age=31536000, immutable",
+                CacheControl {
+                    max_age: Some(31536000),
+                    immutable: true,
+                    ..CacheControl::default()
+                },
+            ),
+            (
+                "max-age=1, stale-while-revalidate=59, stale-if-error=86400",
+                CacheControl {
+                    max_age: Some(1),
+                    stale_while_revalidate: Some(59),
+                    stale_if_error: Some(86400),
+                    ..CacheControl::default()
+                },
+            ),
+            ("stale-while-revalidate=later", CacheControl::default()),
+            // Request directives.
+            (
+                "max-stale, min-fresh=30, only-if-cached",
+                CacheControl {
+                    max_stale: Some(u64::MAX),
+                    min_fresh: Some(30),
+                    only_if_cached: true,
+                    ..CacheControl::default()
+                },
+            ),
+            (
+                "max-stale=120, max-age=0, no-cache",
+                CacheControl {
+                    max_stale: Some(120),
+                    max_age: Some(0),
+                    no_cache: names(&[]),
+                    ..CacheControl::default()
+                },
+            ),
+            // Extensions, with commas and escapes in quoted values.
+            (
+                "community=\"UCI, \\\"UCLA\\\"\", x-flag, public",
+                CacheControl {
+                    public: true,
+                    extensions: vec![
+                        ("community".to_string(), Some("UCI, \"UCLA\"".to_string())),
+                        ("x-flag".to_string(), None),
+                    ],
+                    ..CacheControl::default()
+                },
+            ),
+            // An unterminated quoted string goes on to the end.
+            (
+                "private=\"x-a, x-b",
+                CacheControl {
+                    private: names(&["x-a", "x-b"]),
+                    ..CacheControl::default()
+                },
+            ),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(CacheControl::parse(value), expected, "{value:?}");
+        }
+    }
+
+    #[test]
+    fn cache_control_headers() {
+        let mut headers = Headers::default();
+        headers.add("Cache-Control", "public");
+        headers.add("Cache-Control", "max-age=600, max-age=1");
+        assert_eq!(
+            CacheControl::from_headers(&headers),
+            CacheControl {
+                public: true,
+                max_age: Some(600),
+                ..CacheControl::default()
+            }
+        );
+        assert_eq!(
+            CacheControl::from_headers(&Headers::default()),
+            CacheControl::default()
+        );
+    }
+
+    #[test]
+    fn cache_control_serialization() {
+        let cases = [
+            ("", ""),
+            ("Public, MAX-AGE=600", "max-age=600, public"),
+            ("max-stale", "max-stale"),
+            ("max-stale=10, min-fresh=5", "max-stale=10, min-fresh=5"),
+            (
+                "private=\"Set-Cookie\", no-cache, immutable",
+                "no-cache, private=\"set-cookie\", immutable",
+            ),
+            (
+                "community=\"UCI, \\\"UCLA\\\"\", token=abc",
+                "community=\"UCI, \\\"UCLA\\\"\", token=abc",
+            ),
+        ];
+        for (value, expected) in cases {
+            let cache_control = CacheControl::parse(value);
+            assert_eq!(cache_control.to_string(), expected, "{value:?}");
+            assert_eq!(CacheControl::parse(expected), cache_control, "{expected:?}");
+        }
+    }
+}
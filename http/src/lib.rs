@@ -1,5 +1,42 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+mod body;
 pub mod cache;
-mod headers;
+pub mod charset;
+pub mod client;
+mod connection;
+pub mod cookies;
+pub mod headers;
+mod pool;
 pub mod request;
+pub mod transport;
 
+pub use connection::tls_root_count;
 pub use request::HttpError;
+
+/// The version of this crate, e.g. for showing on `about:version`.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Writes `contents` to a temporary file next to `path` first, and then renames it,
+/// so that `path` is never left half-written.
+pub(crate) fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temporary_path = path.with_extension("tmp");
+    fs::write(&temporary_path, contents)?;
+    fs::rename(temporary_path, path)
+}
+
+#[cfg(test)]
+mod test_util {
+    use anyhow::{anyhow, Result};
+    use octo_url::{Url, WebUrl};
+
+    /// Parses `url`, which the test expects to be an `http` or `https` one.
+    pub(crate) fn web_url(url: &str) -> Result<WebUrl> {
+        match url.parse::<Url>()? {
+            Url::Web(url) => Ok(url),
+            url => Err(anyhow!("Expected a WebUrl, got {url:?}")),
+        }
+    }
+}
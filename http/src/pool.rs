@@ -0,0 +1,347 @@
+//! A pool of keep-alive connections, shared by all the requests sent by a
+//! [`Client`](crate::client::Client) and its clones.
+
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use octo_url::Origin;
+
+use crate::connection::GenericTcpStream;
+
+/// How many connections can be open to one origin at once, like in most browsers.
+pub(crate) const DEFAULT_MAX_PER_HOST: usize = 6;
+/// How many connections can be open at once, to all origins together.
+pub(crate) const DEFAULT_MAX_TOTAL: usize = 64;
+
+#[derive(Debug)]
+struct IdleConnection {
+    stream: GenericTcpStream,
+    idle_since: Instant,
+    /// When the server said it would close the connection, if it did.
+    expires: Option<Instant>,
+}
+
+impl IdleConnection {
+    fn is_usable(&self, now: Instant) -> bool {
+        self.expires.is_none_or(|expires| now < expires) && !self.stream.is_closed()
+    }
+}
+
+#[derive(Debug, Default)]
+struct PoolState {
+    idle: HashMap<Origin, Vec<IdleConnection>>,
+    /// The number of connections open to each origin, whether they are idle or in use.
+    open: HashMap<Origin, usize>,
+}
+
+impl PoolState {
+    fn open_to(&self, origin: &Origin) -> usize {
+        self.open.get(origin).copied().unwrap_or(0)
+    }
+
+    fn total_open(&self) -> usize {
+        self.open.values().sum()
+    }
+
+    fn forget(&mut self, origin: &Origin) {
+        if let Some(count) = self.open.get_mut(origin) {
+            *count -= 1;
+            if *count == 0 {
+                self.open.remove(origin);
+            }
+        }
+    }
+
+    /// Takes the most recently used idle connection to `origin` that is still usable,
+    /// closing the ones that have expired or that the server has closed.
+    fn take_idle(&mut self, origin: &Origin) -> Option<GenericTcpStream> {
+        let now = Instant::now();
+        let mut stream = None;
+        let mut num_closed = 0;
+        if let Some(idle) = self.idle.get_mut(origin) {
+            while let Some(connection) = idle.pop() {
+                if connection.is_usable(now) {
+                    stream = Some(connection.stream);
+                    break;
+                }
+                num_closed += 1;
+            }
+            if idle.is_empty() {
+                self.idle.remove(origin);
+            }
+        }
+        for _ in 0..num_closed {
+            self.forget(origin);
+        }
+        stream
+    }
+
+    /// Closes the connection that has been idle the longest, to make room for another.
+    /// Returns false if there are no idle connections.
+    fn close_oldest_idle(&mut self) -> bool {
+        let oldest = self
+            .idle
+            .iter()
+            .filter_map(|(origin, idle)| Some((origin, idle.first()?.idle_since)))
+            .min_by_key(|(_, idle_since)| *idle_since)
+            .map(|(origin, _)| origin.clone());
+        let Some(origin) = oldest else {
+            return false;
+        };
+        if let Some(idle) = self.idle.get_mut(&origin) {
+            idle.remove(0);
+            if idle.is_empty() {
+                self.idle.remove(&origin);
+            }
+        }
+        self.forget(&origin);
+        true
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ConnectionPool {
+    state: Mutex<PoolState>,
+    /// Signalled whenever a connection goes back in the pool or gets closed.
+    released: Condvar,
+    max_per_host: usize,
+    max_total: usize,
+}
+
+impl Default for ConnectionPool {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_PER_HOST, DEFAULT_MAX_TOTAL)
+    }
+}
+
+impl ConnectionPool {
+    pub(crate) fn new(max_per_host: usize, max_total: usize) -> Self {
+        Self {
+            state: Mutex::default(),
+            released: Condvar::new(),
+            max_per_host: max_per_host.max(1),
+            max_total: max_total.max(1),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PoolState> {
+        // The state is kept consistent between every statement that could panic,
+        // so it's still fine to use after another thread panicked.
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Waits until there is a connection to `origin` to use: either an idle one
+    /// from the pool, or room to open a new one without going over the limits.
+    /// Returns `None` if that takes until `deadline`.
+    pub(crate) fn checkout(
+        &self,
+        origin: &Origin,
+        deadline: Option<Instant>,
+    ) -> Option<PooledConnection<'_>> {
+        let mut state = self.lock();
+        loop {
+            if let Some(stream) = state.take_idle(origin) {
+                return Some(PooledConnection::new(self, origin, Some(stream)));
+            }
+            if state.open_to(origin) < self.max_per_host
+                && (state.total_open() < self.max_total || state.close_oldest_idle())
+            {
+                *state.open.entry(origin.clone()).or_default() += 1;
+                return Some(PooledConnection::new(self, origin, None));
+            }
+            state = match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.checked_duration_since(Instant::now())?;
+                    self.released
+                        .wait_timeout(state, remaining)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+                None => self
+                    .released
+                    .wait(state)
+                    .unwrap_or_else(PoisonError::into_inner),
+            };
+        }
+    }
+
+    fn release(&self, origin: &Origin, stream: GenericTcpStream, idle_timeout: Option<Duration>) {
+        let now = Instant::now();
+        self.lock()
+            .idle
+            .entry(origin.clone())
+            .or_default()
+            .push(IdleConnection {
+                stream,
+                idle_since: now,
+                expires: idle_timeout.map(|timeout| now + timeout),
+            });
+        self.released.notify_all();
+    }
+
+    fn close(&self, origin: &Origin) {
+        self.lock().forget(origin);
+        self.released.notify_all();
+    }
+
+    #[cfg(test)]
+    pub(crate) fn idle_count(&self) -> usize {
+        self.lock().idle.values().map(Vec::len).sum()
+    }
+}
+
+/// A connection checked out of the pool, which counts towards the limits until
+/// it's either released back into the pool, or dropped (which closes it).
+#[derive(Debug)]
+pub(crate) struct PooledConnection<'a> {
+    pool: &'a ConnectionPool,
+    origin: Origin,
+    /// The idle connection taken from the pool, or `None` if a new one has to be opened.
+    idle_stream: Option<GenericTcpStream>,
+    released: bool,
+}
+
+impl<'a> PooledConnection<'a> {
+    fn new(
+        pool: &'a ConnectionPool,
+        origin: &Origin,
+        idle_stream: Option<GenericTcpStream>,
+    ) -> Self {
+        Self {
+            pool,
+            origin: origin.clone(),
+            idle_stream,
+            released: false,
+        }
+    }
+
+    /// Takes the idle connection out, if there was one to reuse.
+    pub(crate) fn take_idle_stream(&mut self) -> Option<GenericTcpStream> {
+        self.idle_stream.take()
+    }
+
+    /// Puts `stream` back into the pool for the next request to the same origin,
+    /// until `idle_timeout` passes.
+    pub(crate) fn release(mut self, stream: GenericTcpStream, idle_timeout: Option<Duration>) {
+        self.pool.release(&self.origin, stream, idle_timeout);
+        self.released = true;
+    }
+}
+
+impl Drop for PooledConnection<'_> {
+    fn drop(&mut self) {
+        if !self.released {
+            self.pool.close(&self.origin);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::web_url;
+    use crate::transport::TcpTransport;
+    use anyhow::Result;
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::mpsc;
+    use std::thread;
+
+    fn origin(url: &str) -> Result<Origin> {
+        Ok(web_url(url)?.origin())
+    }
+
+    fn checkout<'a>(pool: &'a ConnectionPool, origin: &Origin) -> Result<PooledConnection<'a>> {
+        pool.checkout(origin, None)
+            .ok_or_else(|| anyhow::anyhow!("Expected to get a connection"))
+    }
+
+    /// Whether checking out a connection to `origin` has to wait
+    /// until `connection` is dropped.
+    fn waits_for(pool: &ConnectionPool, origin: &Origin, connection: PooledConnection) -> bool {
+        let (sender, receiver) = mpsc::channel();
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let _connection = pool.checkout(origin, None);
+                let _ = sender.send(());
+            });
+            let waited = receiver.recv_timeout(Duration::from_millis(100)).is_err();
+            drop(connection);
+            let _ = receiver.recv_timeout(Duration::from_secs(5));
+            waited
+        })
+    }
+
+    #[test]
+    fn limits() -> Result<()> {
+        let pool = ConnectionPool::new(1, 2);
+        let (a, b, c) = (
+            origin("http://a.test/")?,
+            origin("http://b.test/")?,
+            origin("http://c.test/")?,
+        );
+
+        let connection_a = checkout(&pool, &a)?;
+        assert!(waits_for(&pool, &a, connection_a));
+
+        let _connection_a = checkout(&pool, &a)?;
+        let connection_b = checkout(&pool, &b)?;
+        assert!(waits_for(&pool, &c, connection_b));
+
+        // Waiting gives up at the deadline.
+        let _connection_b = checkout(&pool, &b)?;
+        let deadline = Instant::now() + Duration::from_millis(50);
+        assert!(pool.checkout(&c, Some(deadline)).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn idle_connections() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = web_url(&format!(
+            "http://127.0.0.1:{}/",
+            listener.local_addr()?.port()
+        ))?;
+        // Returns the client's end of a new connection, and the server's.
+        let connect = || -> Result<(GenericTcpStream, TcpStream)> {
+            let stream = GenericTcpStream::connect(&TcpTransport, &url, None, false)?;
+            Ok((stream, listener.accept()?.0))
+        };
+        let origin = url.origin();
+        let pool = ConnectionPool::new(1, 1);
+
+        let mut connection = checkout(&pool, &origin)?;
+        assert!(connection.take_idle_stream().is_none());
+        let (stream, _server_stream) = connect()?;
+        connection.release(stream, None);
+        assert_eq!(pool.idle_count(), 1);
+
+        let mut connection = checkout(&pool, &origin)?;
+        let stream = connection.take_idle_stream();
+        assert!(stream.is_some());
+        assert_eq!(pool.idle_count(), 0);
+
+        // Expired connections are closed instead of being reused.
+        #[allow(clippy::unwrap_used)]
+        connection.release(stream.unwrap(), Some(Duration::ZERO));
+        let mut connection = checkout(&pool, &origin)?;
+        assert!(connection.take_idle_stream().is_none());
+        assert_eq!(pool.idle_count(), 0);
+
+        // So are the ones the server has closed.
+        let (stream, server_stream) = connect()?;
+        connection.release(stream, None);
+        drop(server_stream);
+        thread::sleep(Duration::from_millis(50));
+        let mut connection = checkout(&pool, &origin)?;
+        assert!(connection.take_idle_stream().is_none());
+
+        // Idle connections to other origins make room when the pool is full.
+        let (stream, _server_stream) = connect()?;
+        connection.release(stream, None);
+        let mut connection = checkout(&pool, &self::origin("http://other.test/")?)?;
+        assert!(connection.take_idle_stream().is_none());
+        assert_eq!(pool.idle_count(), 0);
+        Ok(())
+    }
+}
@@ -1,38 +1,52 @@
-use std::cell::OnceCell;
 use std::fmt::{Display, Formatter};
 use std::io;
-use std::io::{BufRead, BufReader, Read, Write};
-use std::net::TcpStream;
+use std::io::{BufRead, BufReader, Read};
 use std::num::ParseIntError;
 use std::str::FromStr;
-use std::sync::{Arc, LazyLock};
+use std::sync::OnceLock;
+use std::time::Duration;
 
-use flate2::read::GzDecoder;
 use thiserror::Error;
 
+use crate::body::{self, DecodedReader, FramedReader};
+use crate::charset;
 use crate::headers::{Headers, HeadersError, USER_AGENT};
-use octo_url::{Scheme, WebUrl};
-
-static ROOT_STORE: LazyLock<Arc<rustls::RootCertStore>> = LazyLock::new(|| {
-    Arc::new(rustls::RootCertStore::from_iter(
-        webpki_roots::TLS_SERVER_ROOTS.iter().cloned(),
-    ))
-});
-
-static CONFIG: LazyLock<Arc<rustls::ClientConfig>> = LazyLock::new(|| {
-    Arc::new(
-        rustls::ClientConfig::builder()
-            .with_root_certificates(ROOT_STORE.clone())
-            .with_no_client_auth(),
-    )
-});
+use octo_url::{MimeType, Scheme, Url, WebUrl};
 
 #[derive(Error, Debug)]
-#[error(transparent)]
-pub struct HttpError(#[from] NetworkError);
+pub enum HttpError {
+    /// The server took too long, e.g. to accept the connection or to send the response.
+    #[error("timed out: {0}")]
+    Timeout(Timeout),
+
+    #[error(transparent)]
+    Network(NetworkError),
+}
+
+impl From<NetworkError> for HttpError {
+    fn from(error: NetworkError) -> Self {
+        match error.timeout() {
+            Some(timeout) => Self::Timeout(timeout),
+            None => Self::Network(error),
+        }
+    }
+}
+
+/// Which of the time limits of a [`Request`] ran out.
+#[derive(Error, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Timeout {
+    #[error("the server didn't accept the connection in time")]
+    Connect,
+
+    #[error("the server stopped sending or receiving data")]
+    Read,
+
+    #[error("the request took too long overall")]
+    Total,
+}
 
 #[derive(Error, Debug)]
-pub(crate) enum NetworkError {
+pub enum NetworkError {
     #[error(transparent)]
     Request(#[from] RequestError),
 
@@ -40,28 +54,56 @@ pub(crate) enum NetworkError {
     Response(#[from] ResponseError),
 }
 
-#[derive(Debug, Copy, Clone)]
+impl NetworkError {
+    /// The timeout that caused this error, if it was one. The client reports them
+    /// as I/O errors of kind `TimedOut`, with the [`Timeout`] inside.
+    fn timeout(&self) -> Option<Timeout> {
+        let (Self::Request(RequestError::ConnectionFailed(error))
+        | Self::Response(ResponseError::Stream(error))) = self
+        else {
+            return None;
+        };
+        error.get_ref()?.downcast_ref::<Timeout>().copied()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum RequestMethod {
     Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Options,
+    Patch,
 }
 
-impl Display for RequestMethod {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Get => write!(f, "GET"),
-        }
+impl RequestMethod {
+    /// Whether requests with this method are expected to have a body,
+    /// so we send a `Content-Length: 0` even if they don't
+    /// (https://www.rfc-editor.org/rfc/rfc9110#section-8.6).
+    fn expects_body(self) -> bool {
+        matches!(self, Self::Post | Self::Put | Self::Patch)
     }
 }
 
-/// Abstraction over both `std::net::TcpStream` and `rustls::StreamOwned`
-#[derive(Debug)]
-enum GenericTcpStream {
-    Insecure(TcpStream),
-    Secure(Box<rustls::StreamOwned<rustls::ClientConnection, TcpStream>>),
+impl Display for RequestMethod {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let method = match self {
+            Self::Get => "GET",
+            Self::Head => "HEAD",
+            Self::Post => "POST",
+            Self::Put => "PUT",
+            Self::Delete => "DELETE",
+            Self::Options => "OPTIONS",
+            Self::Patch => "PATCH",
+        };
+        write!(f, "{method}")
+    }
 }
 
 #[derive(Error, Debug)]
-pub(crate) enum RequestError {
+pub enum RequestError {
     #[error("invalid scheme for a web URL: {0}")]
     InvalidScheme(Scheme),
 
@@ -73,146 +115,324 @@ pub(crate) enum RequestError {
 
     #[error("DNS error: {0}")]
     DnsName(#[from] rustls::pki_types::InvalidDnsNameError),
+
+    #[error("can't follow a redirect to {0}")]
+    InvalidRedirect(String),
+
+    #[error("more than {0} redirects in a row")]
+    TooManyRedirects(u8),
 }
 
-impl GenericTcpStream {
-    fn connect_insecure(url: &WebUrl) -> Result<Self, RequestError> {
-        let stream = TcpStream::connect(format!("{}:{}", url.host, url.port))?;
-        Ok(Self::Insecure(stream))
-    }
+/// How a [`Client`](crate::client::Client) handles redirect responses.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RedirectPolicy {
+    /// Return redirect responses as they are.
+    None,
+    /// Follow up to this many redirects in a row, and fail if there are more.
+    Limit(u8),
+}
 
-    fn connect_secure(url: &WebUrl) -> Result<Self, RequestError> {
-        let stream = TcpStream::connect(format!("{}:{}", url.host, url.port))?;
-        let client = rustls::ClientConnection::new(CONFIG.clone(), url.host.clone().try_into()?)?;
-        let tls = rustls::StreamOwned::new(client, stream);
-        Ok(Self::Secure(Box::new(tls)))
+impl RedirectPolicy {
+    /// How many redirects in a row are followed by default, the same as
+    /// in fetch (https://fetch.spec.whatwg.org/#http-redirect-fetch).
+    pub const DEFAULT_LIMIT: u8 = 20;
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> Self {
+        Self::Limit(Self::DEFAULT_LIMIT)
     }
 }
 
-impl Read for GenericTcpStream {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        match self {
-            Self::Insecure(stream) => stream.read(buf),
-            Self::Secure(stream) => stream.read(buf),
+/// The headers that make a request conditional on the state of the resource at its URL,
+/// which say nothing about the resource a redirect leads to.
+const CONDITIONAL_HEADERS: [&str; 5] = [
+    "if-match",
+    "if-none-match",
+    "if-modified-since",
+    "if-unmodified-since",
+    "if-range",
+];
+
+/// The headers with credentials for an origin, which don't go along a redirect
+/// to another one. Cookies for the new origin are added by the cookie jar, if any.
+const CREDENTIAL_HEADERS: [&str; 3] = ["authorization", "proxy-authorization", "cookie"];
+
+/// The headers that describe the body, which have to go if a redirect drops the body
+/// (https://fetch.spec.whatwg.org/#request-body-header-name).
+const REQUEST_BODY_HEADERS: [&str; 4] = [
+    "content-encoding",
+    "content-language",
+    "content-location",
+    "content-type",
+];
+
+/// An HTTP request, made with a [`RequestBuilder`] and sent with a
+/// [`Client`](crate::client::Client). It can't be changed once it's built,
+/// so the same request can be logged, sent again or inspected in tests.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    method: RequestMethod,
+    url: WebUrl,
+    headers: Headers,
+    body: Option<Vec<u8>>,
+    timeouts: Timeouts,
+    redirect_policy: RedirectPolicy,
+}
+
+/// The time limits of a request. `None` means there is no limit.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+struct Timeouts {
+    connect: Option<Duration>,
+    read: Option<Duration>,
+    total: Option<Duration>,
+}
+
+impl Request {
+    pub fn builder(method: RequestMethod, url: &WebUrl) -> RequestBuilder {
+        RequestBuilder::new(method, url)
+    }
+
+    pub fn method(&self) -> RequestMethod {
+        self.method
+    }
+
+    pub fn url(&self) -> &WebUrl {
+        &self.url
+    }
+
+    /// The headers set on the request. `Host` and `Content-Length` aren't in here,
+    /// since they are worked out from the URL and the body when the request is sent.
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    pub fn body(&self) -> Option<&[u8]> {
+        self.body.as_deref()
+    }
+
+    /// How long connecting to the server can take.
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        self.timeouts.connect
+    }
+
+    /// How long the server can go without sending (or accepting) any data.
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.timeouts.read
+    }
+
+    /// How long the whole request can take, including any redirects.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeouts.total
+    }
+
+    pub fn redirect_policy(&self) -> RedirectPolicy {
+        self.redirect_policy
+    }
+
+    /// A copy of this request with another header.
+    pub(crate) fn with_header(&self, key: &str, value: &str) -> Self {
+        let mut request = self.clone();
+        request.headers.add(key, value);
+        request
+    }
+
+    /// Whether the connection can be reused for another request after this one.
+    pub(crate) fn keep_alive(&self) -> bool {
+        !has_token(&self.headers, "connection", "close")
+    }
+
+    /// The value of the `Host` header: the host, and the port if it isn't the default one.
+    fn host_header(&self) -> String {
+        let host = self.url.host.to_string();
+        if Some(self.url.port) == self.url.scheme.default_port() {
+            host
+        } else {
+            format!("{host}:{}", self.url.port)
         }
     }
-}
 
-impl Write for GenericTcpStream {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        match self {
-            Self::Insecure(stream) => stream.write(buf),
-            Self::Secure(stream) => stream.write(buf),
+    /// The request line and headers (including the `Content-Length` of the body),
+    /// which the body is sent after.
+    fn make_string(&self) -> String {
+        let mut string = format!("{} {} HTTP/1.1\r\n", self.method, self.url.request_target());
+        string.push_str(&format!("host: {}\r\n", self.host_header()));
+        string.push_str(self.headers.to_string().as_str());
+        let content_length = self
+            .body
+            .as_ref()
+            .map(Vec::len)
+            .or(self.method.expects_body().then_some(0));
+        if let Some(content_length) = content_length {
+            string.push_str(&format!("content-length: {content_length}\r\n"));
         }
+        string.push_str("\r\n");
+        string
+    }
+
+    /// The whole request, as it's sent to the server.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.make_string().into_bytes();
+        bytes.extend_from_slice(self.body().unwrap_or_default());
+        bytes
     }
 
-    fn flush(&mut self) -> io::Result<()> {
-        match self {
-            Self::Insecure(stream) => stream.flush(),
-            Self::Secure(stream) => stream.flush(),
+    /// The request to make after a redirect response with the given status code
+    /// and `Location`, following https://fetch.spec.whatwg.org/#http-redirect-fetch.
+    pub(crate) fn redirect(&self, status_code: u16, location: &str) -> Result<Self, RequestError> {
+        // The Location may be relative to the URL we were redirected from.
+        let url = match self.url.join(location) {
+            Ok(Url::Web(url)) => url,
+            Ok(url) => return Err(RequestError::InvalidRedirect(url.to_string())),
+            Err(error) => {
+                return Err(RequestError::InvalidRedirect(format!(
+                    "{location}: {error}"
+                )))
+            }
+        };
+
+        let mut request = Self {
+            url,
+            ..self.clone()
+        };
+        // POSTs redirected with 301 or 302, and anything but HEAD redirected with 303,
+        // turn into GETs without a body.
+        if (matches!(status_code, 301 | 302) && self.method == RequestMethod::Post)
+            || (status_code == 303 && self.method != RequestMethod::Head)
+        {
+            request.method = RequestMethod::Get;
+            request.body = None;
+            for header in REQUEST_BODY_HEADERS {
+                request.headers.remove(header);
+            }
+        }
+        // The validators are for the resource we were redirected from
+        // (e.g. a cached response being revalidated), not for this one.
+        for header in CONDITIONAL_HEADERS {
+            request.headers.remove(header);
+        }
+        // Don't send credentials meant for one origin to another.
+        if !self.url.origin().is_same_origin(&request.url.origin()) {
+            for header in CREDENTIAL_HEADERS {
+                request.headers.remove(header);
+            }
         }
+        Ok(request)
     }
 }
 
-// Have to make a newtype because OnceCell::get_mut_or_init
-// isn't available on stable, and we need to put the TcpStream in a OnceCell
-// so that it's not dropped (and therefore closed) after every call to Request.make
-#[derive(Debug)]
-struct ReusableTcpStream(OnceCell<GenericTcpStream>);
-
-impl ReusableTcpStream {
-    fn new() -> Self {
-        Self(OnceCell::new())
-    }
-
-    #[allow(clippy::unwrap_used)]
-    fn get_mut_or_try_init<F>(&mut self, f: F) -> Result<&mut GenericTcpStream, RequestError>
-    where
-        F: FnOnce() -> Result<GenericTcpStream, RequestError>,
-    {
-        // There might be a more elegant way of doing this,
-        // but this satisfies the borrow checker, and is good enough for now.
-        if self.0.get().is_none() {
-            let stream = f()?;
-            self.0.set(stream).unwrap();
-        }
-        Ok(self.0.get_mut().unwrap())
+impl Display for Request {
+    /// Writes the request line and headers, e.g. for logging. The body is left out.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.make_string())
     }
 }
 
-#[derive(Debug)]
-pub struct Request {
+/// Builds a [`Request`]. By default, requests ask for keep-alive connections
+/// and compressed responses, identify themselves with our `User-Agent`,
+/// follow up to 20 redirects and never time out.
+#[derive(Debug, Clone)]
+pub struct RequestBuilder {
     method: RequestMethod,
+    url: WebUrl,
     headers: Headers,
-    stream: ReusableTcpStream,
+    body: Option<Vec<u8>>,
+    timeouts: Timeouts,
+    redirect_policy: RedirectPolicy,
+    keep_alive: bool,
+    compression: bool,
 }
 
-impl Request {
-    pub fn new(method: RequestMethod, host: &str, keep_alive: bool, gzip: bool) -> Self {
-        let connection_value = if keep_alive { "keep-alive" } else { "close" };
-        let mut headers = Headers::from(&[("Host", &[host]), ("Connection", &[connection_value])]);
-
-        if gzip {
-            headers.add("Accept-Encoding", "gzip")
-        }
-
+impl RequestBuilder {
+    pub fn new(method: RequestMethod, url: &WebUrl) -> Self {
         Self {
             method,
-            headers,
-            stream: ReusableTcpStream::new(),
+            url: url.clone(),
+            headers: Headers::default(),
+            body: None,
+            timeouts: Timeouts::default(),
+            redirect_policy: RedirectPolicy::default(),
+            keep_alive: true,
+            compression: true,
         }
     }
 
-    /// Adds given Header key/values to the Request.
-    /// The same Header key can be specified multiple times.
-    /// Note that this does not overwrite any existing headers!
-    /// If a given Header already exists in this Request,
-    /// the new value(s) will simply be appended to that Header.
-    pub(crate) fn with_extra_headers(mut self, headers: &[(&str, &[&str])]) -> Self {
-        self.headers.add_many(headers);
+    /// Adds a header. Adding the same header again appends another value, instead of
+    /// replacing the first one. Setting `Connection`, `Accept-Encoding` or `User-Agent`
+    /// here overrides the defaults.
+    pub fn header(mut self, key: &str, value: &str) -> Self {
+        self.headers.add(key, value);
         self
     }
 
-    fn make_string(&self, url: &WebUrl, _body: Option<&str>) -> String {
-        let mut string = format!("{} {} HTTP/1.1\r\n", self.method, url.path);
-        string.push_str(self.headers.to_string().as_str());
-        // TODO: add body
-        string.push_str("\r\n");
-        string
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = Some(body.into());
+        self
     }
 
-    pub fn make(&mut self, url: &WebUrl, body: Option<&str>) -> Result<Response, HttpError> {
-        if !matches!(url.scheme, Scheme::Http) && !matches!(url.scheme, Scheme::Https) {
-            return Err(NetworkError::from(RequestError::InvalidScheme(url.scheme)).into());
+    /// Limits how long connecting to the server can take.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.connect = Some(timeout);
+        self
+    }
+
+    /// Limits how long the server can go without sending (or accepting) any data,
+    /// so a server that stops responding is given up on even if the request
+    /// has no overall timeout.
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.read = Some(timeout);
+        self
+    }
+
+    /// Limits how long the whole request can take, including connecting
+    /// and following redirects.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.total = Some(timeout);
+        self
+    }
+
+    pub fn redirect_policy(mut self, redirect_policy: RedirectPolicy) -> Self {
+        self.redirect_policy = redirect_policy;
+        self
+    }
+
+    pub fn keep_alive(mut self, keep_alive: bool) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Whether to ask for compressed responses, in any of the content codings
+    /// this crate was built to decode.
+    pub fn compression(mut self, compression: bool) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn build(self) -> Request {
+        let mut headers = self.headers;
+        if headers.get("connection").is_none() {
+            let connection_value = if self.keep_alive {
+                "keep-alive"
+            } else {
+                "close"
+            };
+            headers.add("Connection", connection_value);
+        }
+        if self.compression && headers.get("accept-encoding").is_none() {
+            headers.add("Accept-Encoding", &body::accept_encoding());
+        }
+        if headers.get("user-agent").is_none() {
+            headers.add("User-Agent", USER_AGENT);
         }
 
-        let self_string = self.make_string(url, body);
-
-        let stream = self
-            .stream
-            .get_mut_or_try_init(|| {
-                if matches!(url.scheme, Scheme::Http) {
-                    GenericTcpStream::connect_insecure(url)
-                } else {
-                    // HTTPS
-                    GenericTcpStream::connect_secure(url)
-                }
-            })
-            .map_err(NetworkError::from)?;
-
-        stream
-            .write_all(self_string.as_bytes())
-            .map_err(|e| NetworkError::from(RequestError::from(e)))?;
-        Ok(Response::from_stream(stream).map_err(NetworkError::from)?)
-    }
-
-    /// Convenience method to make a GET request
-    /// to the given URL with the default `User-Agent`,
-    /// and return the resulting `Response` or error.
-    pub fn get(url: &WebUrl) -> Result<Response, HttpError> {
-        let mut request = Self::new(RequestMethod::Get, &url.host, false, true)
-            .with_extra_headers(&[("User-Agent", &[USER_AGENT])]);
-        request.make(url, None)
+        Request {
+            method: self.method,
+            url: self.url,
+            headers,
+            body: self.body,
+            timeouts: self.timeouts,
+            redirect_policy: self.redirect_policy,
+        }
     }
 }
 
@@ -243,14 +463,6 @@ impl FromStr for StatusLine {
     }
 }
 
-#[inline]
-fn decompress_gzip(bytes: impl Read) -> Result<String, ResponseError> {
-    let mut gz = GzDecoder::new(bytes);
-    let mut string = String::new();
-    gz.read_to_string(&mut string)?;
-    Ok(string)
-}
-
 #[derive(Error, Debug)]
 pub enum ResponseError {
     #[error("missing status line: {0}")]
@@ -270,86 +482,84 @@ pub enum ResponseError {
 
     #[error("error reading the response stream: {0}")]
     Stream(#[from] io::Error),
+
+    #[error("the connection was closed before the response started")]
+    ConnectionClosed,
+
+    #[error("unsupported content encoding: {0}")]
+    UnsupportedContentEncoding(String),
 }
 
-#[inline]
-fn read_chunked(reader: &mut BufReader<&mut impl Read>) -> Result<Vec<u8>, ResponseError> {
-    let mut current_chunk_len_line = String::new();
-    let mut response_body = vec![];
-    loop {
-        // Read the chunk length.
-        reader.read_line(&mut current_chunk_len_line)?;
-        let chunk_len = usize::from_str_radix(current_chunk_len_line.trim(), 16)?;
-
-        if chunk_len > 0 {
-            let mut chunk_buf = vec![0; chunk_len];
-            reader.read_exact(&mut chunk_buf)?;
-            response_body.append(&mut chunk_buf);
-            // Skip the newline at the end
-            reader.read_line(&mut current_chunk_len_line)?;
-        } else {
-            break;
-        }
-        current_chunk_len_line.clear();
-    }
-    Ok(response_body)
+/// Whether a response to a request with `method` can have a body at all.
+/// Responses to HEAD requests, and some status codes, never have one,
+/// even if their headers say how long it would have been
+/// (https://www.rfc-editor.org/rfc/rfc9112#section-6.3).
+pub(crate) fn can_have_body(method: RequestMethod, status_code: u16) -> bool {
+    method != RequestMethod::Head
+        && !(100..200).contains(&status_code)
+        && status_code != 204
+        && status_code != 304
 }
 
-#[inline]
-fn read_body(
-    reader: &mut BufReader<&mut impl Read>,
-    headers: &Headers,
-) -> Result<Option<String>, ResponseError> {
-    let buf = if headers.has_given_value("transfer-encoding", "chunked") == Some(true) {
-        read_chunked(reader)?
-    } else {
-        // The two calls to transpose here are a bit awkward, but they help us deal
-        // with the whole Option<Result> thing and make sure
-        // we handle the errors from both not having a content-length header at all,
-        // and not having a valid value for the content-length.
-        let content_length = headers
-            .get_single_value("content-length")
-            .transpose()?
-            .map(|s| s.parse::<usize>())
-            .transpose()?
-            .unwrap_or(0);
-
-        let mut buf = vec![0u8; content_length];
-        reader.read_exact(&mut buf)?;
-        buf
-    };
-
-    if !buf.is_empty() {
-        let body = if headers.has_given_value("content-encoding", "gzip") == Some(true) {
-            decompress_gzip(buf.as_slice())?
-        } else {
-            String::from_utf8_lossy(&buf).to_string()
-        };
-        Ok(Some(body))
-    } else {
-        Ok(None)
-    }
+/// Whether the comma-separated values of the `key` header include `token`, ignoring case.
+fn has_token(headers: &Headers, key: &str, token: &str) -> bool {
+    headers.get(key).is_some_and(|values| {
+        values
+            .iter()
+            .flat_map(|value| value.split(','))
+            .any(|value| value.trim().eq_ignore_ascii_case(token))
+    })
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct Response {
     status_line: StatusLine,
     pub headers: Headers,
-    pub body: Option<String>,
+    body: Option<Vec<u8>>,
+    /// The body decoded into text, the first time it's asked for.
+    text: OnceLock<String>,
+}
+
+impl PartialEq for Response {
+    // Whether the text has been decoded yet doesn't matter.
+    fn eq(&self, other: &Self) -> bool {
+        self.status_line == other.status_line
+            && self.headers == other.headers
+            && self.body == other.body
+    }
 }
 
 impl Response {
-    fn from_stream(stream: &mut impl Read) -> Result<Self, ResponseError> {
-        let mut reader = BufReader::new(stream);
+    /// Reads the status line and headers of a response from `reader`,
+    /// leaving the body to be read after them. The response has no body (yet).
+    pub(crate) fn read_head(reader: &mut impl BufRead) -> Result<Self, ResponseError> {
         let mut status_line = String::new();
-        reader.read_line(&mut status_line)?;
+        match reader.read_line(&mut status_line) {
+            Ok(0) => return Err(ResponseError::ConnectionClosed),
+            Ok(_) => {}
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    io::ErrorKind::UnexpectedEof
+                        | io::ErrorKind::ConnectionReset
+                        | io::ErrorKind::ConnectionAborted
+                ) =>
+            {
+                return Err(ResponseError::ConnectionClosed)
+            }
+            Err(error) => return Err(error.into()),
+        }
         let status_line = status_line.parse::<StatusLine>()?;
 
         let mut current_line = String::new();
         let mut headers = Headers::default();
 
         loop {
-            reader.read_line(&mut current_line)?;
+            if reader.read_line(&mut current_line)? == 0 {
+                return Err(ResponseError::ParseHeaders(
+                    "the connection was closed in the middle of the headers".to_string(),
+                ));
+            }
             if current_line.as_str() == "\r\n" {
                 break;
             }
@@ -361,92 +571,382 @@ impl Response {
             current_line.clear();
         }
 
-        let body = read_body(&mut reader, &headers)?;
-
         Ok(Self {
             status_line,
             headers,
-            body,
+            body: None,
+            text: OnceLock::new(),
         })
     }
 
+    /// The status line and headers, as [`Response::read_head`] reads them,
+    /// with each header value on its own line.
+    pub(crate) fn head(&self) -> String {
+        let StatusLine {
+            version,
+            status_code,
+            explanation,
+        } = &self.status_line;
+        let mut head = format!("{version} {status_code} {explanation}\r\n");
+        for (key, values) in self.headers.iter() {
+            for value in values {
+                head.push_str(&format!("{key}: {value}\r\n"));
+            }
+        }
+        head.push_str("\r\n");
+        head
+    }
+
+    /// This response (read with [`Response::read_head`]) with `body`.
+    pub(crate) fn with_body(self, body: Vec<u8>) -> Self {
+        Self {
+            body: (!body.is_empty()).then_some(body),
+            text: OnceLock::new(),
+            ..self
+        }
+    }
+
+    /// This response with its headers replaced by `headers`.
+    pub(crate) fn with_headers(self, headers: Headers) -> Self {
+        Self {
+            headers,
+            text: OnceLock::new(),
+            ..self
+        }
+    }
+
+    /// Reads a whole response to a request with the given method from `stream`.
+    pub(crate) fn from_stream(
+        stream: &mut impl Read,
+        method: RequestMethod,
+    ) -> Result<Self, ResponseError> {
+        let mut reader = BufReader::new(stream);
+        let head = Self::read_head(&mut reader)?;
+        let has_body = can_have_body(method, head.status_code());
+        let framed = FramedReader::new(reader, &head.headers, has_body)?;
+        let mut body = vec![];
+        DecodedReader::new(framed, body::decoders(&head.headers)?).read_to_end(&mut body)?;
+        Ok(head.with_body(body))
+    }
+
     pub fn status_code(&self) -> u16 {
         self.status_line.status_code
     }
+
+    /// The body, as it was sent (but no longer compressed).
+    pub fn body(&self) -> Option<&[u8]> {
+        self.body.as_deref()
+    }
+
+    /// The MIME type from the `Content-Type` header, if there's a valid one.
+    pub fn content_type(&self) -> Option<MimeType> {
+        self.headers
+            .get_single_value("content-type")?
+            .ok()?
+            .parse::<MimeType>()
+            .ok()
+    }
+
+    /// The body decoded into text, using its `charset` (see [`charset::decode`]).
+    /// It's only decoded once, the first time this is called.
+    pub fn text(&self) -> &str {
+        self.text.get_or_init(|| {
+            charset::decode(
+                self.body().unwrap_or_default(),
+                self.content_type().as_ref(),
+            )
+        })
+    }
+
+    /// Whether the connection this response (to a request with `method`) came in on
+    /// can be used for another request (https://www.rfc-editor.org/rfc/rfc9112#section-9.3).
+    pub(crate) fn can_reuse_connection(&self, method: RequestMethod) -> bool {
+        if has_token(&self.headers, "connection", "close") {
+            return false;
+        }
+        // HTTP/1.0 connections are closed unless the server says otherwise.
+        if self.status_line.version == "HTTP/1.0"
+            && !has_token(&self.headers, "connection", "keep-alive")
+        {
+            return false;
+        }
+        // A body without a length ends when the connection does.
+        !can_have_body(method, self.status_code())
+            || has_token(&self.headers, "transfer-encoding", "chunked")
+            || self.headers.get("content-length").is_some()
+    }
+
+    /// How long the server keeps the connection open while it's idle,
+    /// from a header like `Keep-Alive: timeout=5, max=100`.
+    pub(crate) fn keep_alive_timeout(&self) -> Option<Duration> {
+        self.headers
+            .get("keep-alive")?
+            .iter()
+            .flat_map(|value| value.split(','))
+            .filter_map(|parameter| parameter.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("timeout"))
+            .and_then(|(_, seconds)| seconds.trim().parse::<u64>().ok())
+            .map(Duration::from_secs)
+    }
+
+    /// The `Location` to follow, if this is a redirect.
+    pub(crate) fn redirect_location(&self) -> Option<&str> {
+        if !matches!(self.status_code(), 301 | 302 | 303 | 307 | 308) {
+            return None;
+        }
+        self.headers
+            .get_single_value("location")
+            .and_then(Result::ok)
+            .map(String::as_str)
+    }
 }
 
 impl FromStr for Response {
     type Err = ResponseError;
 
     fn from_str(s: &str) -> Result<Self, ResponseError> {
-        Self::from_stream(&mut s.as_bytes())
+        Self::from_stream(&mut s.as_bytes(), RequestMethod::Get)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::web_url;
     use anyhow::Result;
-    use octo_url::Url;
 
     #[test]
-    fn close() -> Result<()> {
-        let url = "http://example.com".parse::<Url>()?;
-        #[allow(clippy::unwrap_used)]
-        let response = Request::get(url.as_web_url().unwrap())?;
-        assert!(response.body.is_some());
-
-        let url = "https://browser.engineering/http.html".parse::<Url>()?;
-        #[allow(clippy::unwrap_used)]
-        let response = Request::get(url.as_web_url().unwrap())?;
-        assert!(response.body.is_some());
+    fn request_line_has_no_fragment() -> Result<()> {
+        let request =
+            Request::builder(RequestMethod::Get, &web_url("https://a.org/x?y=1#top")?).build();
+        assert!(request.to_string().starts_with("GET /x?y=1 HTTP/1.1\r\n"));
         Ok(())
     }
 
-    fn test_url_keepalive(url: &str) -> Result<()> {
-        let url = url.parse::<Url>()?;
-        #[allow(clippy::unwrap_used)]
-        let url = url.as_web_url().unwrap();
+    #[test]
+    fn methods() {
+        let methods = [
+            RequestMethod::Get,
+            RequestMethod::Head,
+            RequestMethod::Post,
+            RequestMethod::Put,
+            RequestMethod::Delete,
+            RequestMethod::Options,
+            RequestMethod::Patch,
+        ];
+        let methods = methods.map(|method| method.to_string());
+        assert_eq!(
+            methods,
+            ["GET", "HEAD", "POST", "PUT", "DELETE", "OPTIONS", "PATCH"]
+        );
+    }
 
-        let mut request = Request::new(RequestMethod::Get, &url.host, true, true);
-        let first_response = request.make(url, None)?;
-        assert!(first_response.body.is_some());
-        let second_response = request.make(url, None)?;
-        assert_eq!(first_response.body, second_response.body);
+    #[test]
+    fn content_length() -> Result<()> {
+        let url = web_url("http://127.0.0.1/")?;
+        let request = |method| Request::builder(method, &url);
+        assert!(request(RequestMethod::Post)
+            .build()
+            .to_string()
+            .contains("content-length: 0\r\n"));
+        assert!(request(RequestMethod::Put)
+            .body("🐙")
+            .build()
+            .to_string()
+            .contains("content-length: 4\r\n"));
+        assert!(!request(RequestMethod::Get)
+            .build()
+            .to_string()
+            .contains("content-length"));
+        Ok(())
+    }
 
-        let one_off_response = Request::get(url)?;
-        assert_eq!(first_response.body, one_off_response.body);
-        assert_eq!(second_response.body, one_off_response.body);
+    #[test]
+    fn host_header() -> Result<()> {
+        let host = |url: &str| -> Result<String> {
+            Ok(Request::builder(RequestMethod::Get, &web_url(url)?)
+                .build()
+                .host_header())
+        };
+        assert_eq!(host("https://Example.com:443/")?, "example.com");
+        assert_eq!(host("http://example.com:8080/")?, "example.com:8080");
+        assert_eq!(host("http://[::1]:80/")?, "[::1]");
+
+        let request = Request::builder(RequestMethod::Get, &web_url("http://a.org/")?).build();
+        let request_string = request.to_string();
+        // The Host header comes right after the request line.
+        assert!(request_string.starts_with("GET / HTTP/1.1\r\nhost: a.org\r\n"));
+        assert!(request_string.ends_with("\r\n\r\n"));
         Ok(())
     }
 
     #[test]
-    fn keep_alive() -> Result<()> {
-        test_url_keepalive("http://example.com")?;
-        test_url_keepalive("http://browser.engineering/http.html")
+    fn builder() -> Result<()> {
+        let url = web_url("https://a.org/upload")?;
+        let request = Request::builder(RequestMethod::Put, &url)
+            .header("Content-Type", "text/plain")
+            .header("User-Agent", "Test")
+            .body("hello")
+            .connect_timeout(Duration::from_secs(1))
+            .read_timeout(Duration::from_secs(2))
+            .timeout(Duration::from_secs(3))
+            .redirect_policy(RedirectPolicy::None)
+            .keep_alive(false)
+            .compression(false)
+            .build();
+        assert_eq!(request.method(), RequestMethod::Put);
+        assert_eq!(request.url(), &url);
+        assert_eq!(request.body(), Some(&b"hello"[..]));
+        assert_eq!(request.connect_timeout(), Some(Duration::from_secs(1)));
+        assert_eq!(request.read_timeout(), Some(Duration::from_secs(2)));
+        assert_eq!(request.timeout(), Some(Duration::from_secs(3)));
+        assert_eq!(request.redirect_policy(), RedirectPolicy::None);
+        assert!(!request.keep_alive());
+        assert_eq!(
+            request.headers().get("user-agent"),
+            Some(&vec!["Test".to_string()])
+        );
+        assert_eq!(request.headers().get("accept-encoding"), None);
+        assert!(request.to_bytes().ends_with(b"\r\n\r\nhello"));
+
+        let request = Request::builder(RequestMethod::Get, &url).build();
+        assert!(request.keep_alive());
+        assert_eq!(request.connect_timeout(), None);
+        assert_eq!(request.read_timeout(), None);
+        assert_eq!(request.timeout(), None);
+        assert_eq!(request.redirect_policy(), RedirectPolicy::Limit(20));
+        assert_eq!(
+            request.headers().get("user-agent"),
+            Some(&vec![USER_AGENT.to_string()])
+        );
+        assert_eq!(
+            request.headers().get("accept-encoding"),
+            Some(&vec![body::accept_encoding()])
+        );
+        Ok(())
     }
 
     #[test]
-    fn keep_alive_https() -> Result<()> {
-        test_url_keepalive("https://example.com")?;
-        test_url_keepalive("https://browser.engineering/http.html")
+    fn redirects() -> Result<()> {
+        let request = Request::builder(RequestMethod::Post, &web_url("https://a.org/form")?)
+            .header("Content-Type", "text/plain")
+            .header("Authorization", "Basic b2N0bzpvY3Rv")
+            .header("Proxy-Authorization", "Basic b2N0bzpvY3Rv")
+            .header("Cookie", "session=1")
+            .body("hello")
+            .build();
+
+        // 307 and 308 keep the method and body.
+        let redirected = request.redirect(307, "/other")?;
+        assert_eq!(redirected.url(), &web_url("https://a.org/other")?);
+        assert_eq!(redirected.method(), RequestMethod::Post);
+        assert_eq!(redirected.body(), request.body());
+        assert_eq!(redirected.headers(), request.headers());
+
+        // Validators don't carry over to another resource, even on the same origin.
+        let conditional = request
+            .with_header("If-None-Match", "\"v1\"")
+            .with_header("If-Modified-Since", "Wed, 21 Oct 2015 07:28:00 GMT");
+        let redirected = conditional.redirect(307, "/other")?;
+        assert_eq!(redirected.headers().get("if-none-match"), None);
+        assert_eq!(redirected.headers().get("if-modified-since"), None);
+        assert_eq!(redirected.headers(), request.headers());
+
+        let redirected = request.redirect(302, "https://b.org/")?;
+        assert_eq!(redirected.method(), RequestMethod::Get);
+        assert_eq!(redirected.body(), None);
+        assert_eq!(redirected.headers().get("content-type"), None);
+        assert_eq!(redirected.headers().get("authorization"), None);
+        assert_eq!(redirected.headers().get("proxy-authorization"), None);
+        assert_eq!(redirected.headers().get("cookie"), None);
+
+        let head = Request::builder(RequestMethod::Head, &web_url("https://a.org/")?).build();
+        assert_eq!(head.redirect(303, "/")?.method(), RequestMethod::Head);
+        assert!(request.redirect(301, "data:,hi").is_err());
+        Ok(())
     }
 
     #[test]
-    fn gzipped_matches_uncompressed() -> Result<()> {
-        let url = "https://browser.engineering/http.html".parse::<Url>()?;
-        #[allow(clippy::unwrap_used)]
-        let url = url.as_web_url().unwrap();
+    fn not_modified_has_no_body() -> Result<()> {
+        let response =
+            "HTTP/1.1 304 Not Modified\r\ncontent-length: 5\r\n\r\n".parse::<Response>()?;
+        assert_eq!(response.body(), None);
+        Ok(())
+    }
 
-        let mut request_uncompressed =
-            Request::new(RequestMethod::Get, url.host.as_str(), true, false);
-        let response_uncompressed = request_uncompressed.make(url, None)?;
+    #[test]
+    fn binary_bodies() -> Result<()> {
+        let mut gzipped = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        std::io::Write::write_all(&mut gzipped, b"\x89PNG\r\n\x1a\n\xff")?;
+        let gzipped = gzipped.finish()?;
+        let mut raw = format!(
+            "HTTP/1.1 200 OK\r\ncontent-encoding: gzip\r\ncontent-length: {}\r\n\r\n",
+            gzipped.len()
+        )
+        .into_bytes();
+        raw.extend(gzipped);
+        let response = Response::from_stream(&mut raw.as_slice(), RequestMethod::Get)?;
+        assert_eq!(response.body(), Some(&b"\x89PNG\r\n\x1a\n\xff"[..]));
+
+        let raw = b"HTTP/1.1 200 OK\r\ncontent-type: text/html; charset=iso-8859-1\r\ncontent-length: 4\r\n\r\ncaf\xe9";
+        let response = Response::from_stream(&mut &raw[..], RequestMethod::Get)?;
+        assert_eq!(response.text(), "café");
+        assert_eq!(response.clone(), response);
+        Ok(())
+    }
 
-        let mut request_compressed =
-            Request::new(RequestMethod::Get, url.host.as_str(), true, true);
-        let response_compressed = request_compressed.make(url, None)?;
+    #[test]
+    fn body_without_length_ends_with_connection() -> Result<()> {
+        let response = "HTTP/1.1 200 OK\r\n\r\nall of it".parse::<Response>()?;
+        assert_eq!(response.body(), Some(&b"all of it"[..]));
+        assert!(!response.can_reuse_connection(RequestMethod::Get));
+
+        assert!(matches!(
+            "".parse::<Response>(),
+            Err(ResponseError::ConnectionClosed)
+        ));
+        assert!(matches!(
+            "HTTP/1.1 200 OK\r\ncontent-length: 1\r\n".parse::<Response>(),
+            Err(ResponseError::ParseHeaders(_))
+        ));
+        Ok(())
+    }
 
-        assert_eq!(response_compressed.body, response_uncompressed.body);
+    #[test]
+    fn connection_reuse() -> Result<()> {
+        let reusable = |response: &str, method| -> Result<bool> {
+            Ok(response.parse::<Response>()?.can_reuse_connection(method))
+        };
+        let get = RequestMethod::Get;
+        assert!(reusable(
+            "HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n",
+            get
+        )?);
+        assert!(reusable(
+            "HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n0\r\n\r\n",
+            get
+        )?);
+        assert!(reusable("HTTP/1.1 204 No Content\r\n\r\n", get)?);
+        assert!(reusable("HTTP/1.1 200 OK\r\n\r\n", RequestMethod::Head)?);
+        assert!(!reusable(
+            "HTTP/1.1 200 OK\r\nConnection: Close\r\ncontent-length: 0\r\n\r\n",
+            get
+        )?);
+        assert!(!reusable(
+            "HTTP/1.0 200 OK\r\ncontent-length: 0\r\n\r\n",
+            get
+        )?);
+        assert!(reusable(
+            "HTTP/1.0 200 OK\r\nconnection: keep-alive\r\ncontent-length: 0\r\n\r\n",
+            get
+        )?);
+
+        let response =
+            "HTTP/1.1 200 OK\r\nkeep-alive: timeout=5, max=100\r\ncontent-length: 0\r\n\r\n"
+                .parse::<Response>()?;
+        assert_eq!(response.keep_alive_timeout(), Some(Duration::from_secs(5)));
+        let response = "HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n".parse::<Response>()?;
+        assert_eq!(response.keep_alive_timeout(), None);
         Ok(())
     }
 }
@@ -0,0 +1,350 @@
+//! The byte streams that requests are sent over: TCP connections to servers,
+//! or in-memory connections that answer with scripted responses, for tests
+//! and for running without a network.
+
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::io;
+use std::io::{Cursor, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use octo_url::Host;
+
+/// Opens connections to servers, for a [`Client`](crate::client::Client) to send
+/// its requests over. TLS is done on top of them, for `https` URLs.
+pub trait Transport: Debug + Send + Sync {
+    /// Opens a connection to `host` on `port`. `timeout` applies to connecting,
+    /// and to every read and write after that.
+    fn connect(
+        &self,
+        host: &Host,
+        port: u16,
+        timeout: Option<Duration>,
+    ) -> io::Result<Box<dyn Stream>>;
+}
+
+/// A connection opened by a [`Transport`].
+pub trait Stream: Read + Write + Debug + Send {
+    /// Whether the server has closed the connection (or sent something we didn't ask for,
+    /// which makes it just as unusable), checked without blocking.
+    fn is_closed(&self) -> bool;
+
+    /// Changes the timeout of reads and writes, e.g. when reusing the connection
+    /// for a request with a different timeout.
+    fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+/// Connects to servers over TCP.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TcpTransport;
+
+impl Transport for TcpTransport {
+    /// Connects directly to the address if the host is an IP address,
+    /// or looks up the domain otherwise (trying each address it resolves to).
+    /// The lookup counts towards `timeout`, along with connecting.
+    fn connect(
+        &self,
+        host: &Host,
+        port: u16,
+        timeout: Option<Duration>,
+    ) -> io::Result<Box<dyn Stream>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let addresses = match host {
+            Host::Ipv4(address) => vec![SocketAddr::from((*address, port))],
+            Host::Ipv6(address) => vec![SocketAddr::from((*address, port))],
+            Host::Domain(domain) | Host::Opaque(domain) => resolve(domain, port, timeout)?,
+        };
+
+        let mut last_error = io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no addresses found for {host}"),
+        );
+        for address in addresses {
+            let stream = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(remaining) if !remaining.is_zero() => {
+                        TcpStream::connect_timeout(&address, remaining)
+                    }
+                    _ => return Err(timed_out(host)),
+                },
+                None => TcpStream::connect(address),
+            };
+            match stream {
+                Ok(stream) => {
+                    stream.set_timeout(timeout)?;
+                    return Ok(Box::new(stream));
+                }
+                Err(error) => last_error = error,
+            }
+        }
+        Err(last_error)
+    }
+}
+
+fn timed_out(host: &Host) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("connecting to {host} took too long"),
+    )
+}
+
+/// Looks up the addresses of `domain`, giving up after `timeout`. The system's resolver
+/// can't be given a timeout, so the lookup is done on another thread, which is left
+/// to finish on its own if it takes too long.
+fn resolve(domain: &str, port: u16, timeout: Option<Duration>) -> io::Result<Vec<SocketAddr>> {
+    let Some(timeout) = timeout else {
+        return Ok((domain, port).to_socket_addrs()?.collect());
+    };
+    let (sender, receiver) = mpsc::channel();
+    let lookup_domain = domain.to_string();
+    thread::spawn(move || {
+        let addresses = (lookup_domain.as_str(), port)
+            .to_socket_addrs()
+            .map(Iterator::collect);
+        // Nobody is waiting for the addresses any more if it took too long.
+        let _ = sender.send(addresses);
+    });
+    receiver.recv_timeout(timeout).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("looking up {domain} took too long"),
+        )
+    })?
+}
+
+impl Stream for TcpStream {
+    fn is_closed(&self) -> bool {
+        if self.set_nonblocking(true).is_err() {
+            return true;
+        }
+        let closed = match self.peek(&mut [0]) {
+            Err(error) => error.kind() != io::ErrorKind::WouldBlock,
+            Ok(_) => true,
+        };
+        closed || self.set_nonblocking(false).is_err()
+    }
+
+    fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.set_read_timeout(timeout)?;
+        self.set_write_timeout(timeout)
+    }
+}
+
+#[derive(Debug)]
+struct ScriptedConnection {
+    host: String,
+    port: u16,
+    responses: VecDeque<Vec<u8>>,
+}
+
+#[derive(Debug, Default)]
+struct Script {
+    /// The connections that haven't been opened yet, in the order they were added.
+    connections: Vec<ScriptedConnection>,
+    /// The requests sent so far, on all connections.
+    requests: Vec<String>,
+}
+
+/// A transport that never touches the network. Each connection it opens answers
+/// the requests sent on it with canned responses, given beforehand, and records
+/// the requests. Connections for `https` URLs are in plain text, when it's given
+/// to a client with [`Client::with_scripted_transport`](crate::client::Client::with_scripted_transport).
+#[derive(Debug, Default, Clone)]
+pub struct ScriptedTransport {
+    script: Arc<Mutex<Script>>,
+}
+
+impl ScriptedTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Script> {
+        self.script.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Adds a connection to `host` on `port`, which answers a request with each
+    /// of `responses` in turn, and is closed after the last one. Connections to
+    /// the same host and port are opened in the order they're added.
+    pub fn add_connection(&self, host: &str, port: u16, responses: Vec<Vec<u8>>) {
+        self.lock().connections.push(ScriptedConnection {
+            host: host.to_string(),
+            port,
+            responses: responses.into(),
+        });
+    }
+
+    /// Adds a connection to `host` on `port` that answers one request with `response`.
+    pub fn add_response(&self, host: &str, port: u16, response: impl Into<Vec<u8>>) {
+        self.add_connection(host, port, vec![response.into()]);
+    }
+
+    /// The requests sent so far, on all connections, in the order they were answered.
+    pub fn requests(&self) -> Vec<String> {
+        self.lock().requests.clone()
+    }
+}
+
+impl Transport for ScriptedTransport {
+    fn connect(
+        &self,
+        host: &Host,
+        port: u16,
+        _timeout: Option<Duration>,
+    ) -> io::Result<Box<dyn Stream>> {
+        let host = host.to_string();
+        let mut script = self.lock();
+        let index = script
+            .connections
+            .iter()
+            .position(|connection| connection.host == host && connection.port == port)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::ConnectionRefused,
+                    format!("no connection to {host}:{port} in the script"),
+                )
+            })?;
+        let connection = script.connections.remove(index);
+        Ok(Box::new(ScriptedStream {
+            responses: connection.responses,
+            received: vec![],
+            response: Cursor::default(),
+            script: self.script.clone(),
+        }))
+    }
+}
+
+/// A connection opened by a [`ScriptedTransport`].
+#[derive(Debug)]
+struct ScriptedStream {
+    /// The responses to the requests still to come.
+    responses: VecDeque<Vec<u8>>,
+    /// What's been written since the last whole request.
+    received: Vec<u8>,
+    /// The response being read.
+    response: Cursor<Vec<u8>>,
+    script: Arc<Mutex<Script>>,
+}
+
+impl ScriptedStream {
+    fn has_unread_response(&self) -> bool {
+        self.response.position() < self.response.get_ref().len() as u64
+    }
+}
+
+/// The length of the request at the start of `bytes`, if all of it is there.
+fn request_len(bytes: &[u8]) -> Option<usize> {
+    let head_len = bytes.windows(4).position(|window| window == b"\r\n\r\n")? + 4;
+    let head = String::from_utf8_lossy(&bytes[..head_len]).to_lowercase();
+    let content_length = head
+        .lines()
+        .find_map(|line| line.strip_prefix("content-length:"))
+        .and_then(|value| value.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    let len = head_len + content_length;
+    (bytes.len() >= len).then_some(len)
+}
+
+impl Read for ScriptedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.has_unread_response() {
+            // The next response is only sent once there's a request for it.
+            // Without one, or without any more responses, the connection is closed.
+            let Some(len) = request_len(&self.received) else {
+                return Ok(0);
+            };
+            let Some(response) = self.responses.pop_front() else {
+                return Ok(0);
+            };
+            let request = self.received.drain(..len).collect::<Vec<_>>();
+            self.script
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .requests
+                .push(String::from_utf8_lossy(&request).into_owned());
+            self.response = Cursor::new(response);
+        }
+        self.response.read(buf)
+    }
+}
+
+impl Write for ScriptedStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.received.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Stream for ScriptedStream {
+    fn is_closed(&self) -> bool {
+        self.responses.is_empty() || self.has_unread_response()
+    }
+
+    fn set_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn tcp_connections() -> Result<()> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        let host = Host::Domain("localhost".to_string());
+        // Looked up on another thread, as there's a timeout.
+        let stream = TcpTransport.connect(&host, port, Some(Duration::from_secs(5)))?;
+        assert!(!stream.is_closed());
+        let error = TcpTransport.connect(&host, port, Some(Duration::ZERO));
+        assert!(error.is_err_and(|error| error.kind() == io::ErrorKind::TimedOut));
+        Ok(())
+    }
+
+    #[test]
+    fn scripted_connections() -> Result<()> {
+        let transport = ScriptedTransport::new();
+        transport.add_connection(
+            "example.org",
+            80,
+            vec![b"first".to_vec(), b"second".to_vec()],
+        );
+        let host = Host::Domain("example.org".to_string());
+        assert!(transport.connect(&host, 443, None).is_err());
+
+        let mut stream = transport.connect(&host, 80, None)?;
+        assert!(!stream.is_closed());
+        let mut response = String::new();
+        // Nothing is sent before the request is.
+        stream.read_to_string(&mut response)?;
+        assert_eq!(response, "");
+        stream.write_all(b"POST / HTTP/1.1\r\ncontent-length: 3\r\n\r\na=1")?;
+        stream.write_all(b"GET / HTTP/1.1\r\n")?;
+        stream.read_to_string(&mut response)?;
+        assert_eq!(response, "first");
+        stream.write_all(b"\r\n")?;
+        stream.read_to_string(&mut response)?;
+        assert_eq!(response, "firstsecond");
+        assert!(stream.is_closed());
+
+        assert_eq!(
+            transport.requests(),
+            [
+                "POST / HTTP/1.1\r\ncontent-length: 3\r\n\r\na=1",
+                "GET / HTTP/1.1\r\n\r\n"
+            ]
+        );
+        // Each connection is only opened once.
+        assert!(transport.connect(&host, 80, None).is_err());
+        Ok(())
+    }
+}
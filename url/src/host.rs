@@ -0,0 +1,452 @@
+use std::fmt::{Display, Formatter};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use idna::AsciiDenyList;
+
+use crate::percent_encoding;
+use crate::psl;
+use crate::UrlError;
+
+/// The host of a URL (https://url.spec.whatwg.org/#concept-host).
+/// Domains are always stored in their ASCII (Punycode) form.
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub enum Host {
+    Domain(String),
+    Ipv4(Ipv4Addr),
+    Ipv6(Ipv6Addr),
+    /// The host of a non-special URL, which is only percent-encoded, not parsed any further.
+    Opaque(String),
+}
+
+impl Host {
+    /// The IP address, if the host is an IPv4 or IPv6 address rather than a name.
+    pub fn ip_addr(&self) -> Option<IpAddr> {
+        match self {
+            Self::Ipv4(address) => Some(IpAddr::V4(*address)),
+            Self::Ipv6(address) => Some(IpAddr::V6(*address)),
+            Self::Domain(_) | Self::Opaque(_) => None,
+        }
+    }
+
+    /// Like `to_string`, but with any Punycode labels of a domain converted back to Unicode,
+    /// e.g. `bücher.example` instead of `xn--bcher-kva.example`.
+    pub fn to_display_string(&self) -> String {
+        match self {
+            Self::Domain(domain) => domain_to_unicode(domain),
+            _ => self.to_string(),
+        }
+    }
+
+    /// The public suffix of a domain according to the Public Suffix List,
+    /// e.g. `co.uk` for `www.example.co.uk` (https://url.spec.whatwg.org/#host-public-suffix).
+    /// IP addresses and opaque hosts don't have one.
+    pub fn public_suffix(&self) -> Option<String> {
+        match self {
+            Self::Domain(domain) => Some(psl::public_suffix(domain)),
+            _ => None,
+        }
+    }
+
+    /// The public suffix of a domain plus the label before it, e.g. `example.co.uk`
+    /// for `www.example.co.uk` (https://url.spec.whatwg.org/#host-registrable-domain).
+    /// `None` for IP addresses, opaque hosts and domains that are public suffixes themselves.
+    pub fn registrable_domain(&self) -> Option<String> {
+        match self {
+            Self::Domain(domain) => psl::registrable_domain(domain),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Host {
+    type Err = UrlError;
+
+    /// Parses the host of a special URL (https://url.spec.whatwg.org/#host-parsing).
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        if let Some(address) = input.strip_prefix('[') {
+            let address = address
+                .strip_suffix(']')
+                .ok_or_else(|| UrlError::InvalidHost(input.to_string()))?;
+            let pieces =
+                parse_ipv6(address).ok_or_else(|| UrlError::InvalidHost(input.to_string()))?;
+            return Ok(Self::Ipv6(Ipv6Addr::from(pieces)));
+        }
+
+        let domain = percent_encoding::decode(input.as_bytes());
+        let domain = String::from_utf8_lossy(&domain);
+        let ascii_domain = domain_to_ascii(&domain)?;
+
+        if ends_in_a_number(&ascii_domain) {
+            let address = parse_ipv4(&ascii_domain)
+                .ok_or_else(|| UrlError::InvalidHost(input.to_string()))?;
+            return Ok(Self::Ipv4(Ipv4Addr::from(address)));
+        }
+
+        Ok(Self::Domain(ascii_domain))
+    }
+}
+
+impl Display for Host {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Domain(domain) | Self::Opaque(domain) => write!(f, "{domain}"),
+            // Ipv4Addr's Display is already the dotted decimal form the spec wants.
+            Self::Ipv4(address) => write!(f, "{address}"),
+            // Ipv6Addr's Display writes some addresses (e.g. IPv4-mapped ones) differently
+            // from the spec, so we serialize them ourselves.
+            Self::Ipv6(address) => write!(f, "[{}]", serialize_ipv6(&address.segments())),
+        }
+    }
+}
+
+/// https://url.spec.whatwg.org/#concept-domain-to-ascii
+/// Maps `domain` with UTS #46 and converts any non-ASCII labels to Punycode,
+/// so `Bücher.example` becomes `xn--bcher-kva.example`.
+fn domain_to_ascii(domain: &str) -> Result<String, UrlError> {
+    let ascii_domain = idna::domain_to_ascii_cow(domain.as_bytes(), AsciiDenyList::URL)
+        .map_err(|_| UrlError::InvalidHost(domain.to_string()))?;
+    if ascii_domain.is_empty() {
+        return Err(UrlError::InvalidHost(domain.to_string()));
+    }
+    Ok(ascii_domain.into_owned())
+}
+
+/// Converts the Punycode labels of an ASCII domain back to Unicode, for displaying it.
+/// Labels that aren't valid Punycode are left as they are.
+fn domain_to_unicode(domain: &str) -> String {
+    idna::domain_to_unicode(domain).0
+}
+
+/// https://url.spec.whatwg.org/#ends-in-a-number-checker
+fn ends_in_a_number(domain: &str) -> bool {
+    let mut parts = domain.split('.').collect::<Vec<_>>();
+    if parts.last() == Some(&"") {
+        if parts.len() == 1 {
+            return false;
+        }
+        parts.pop();
+    }
+
+    match parts.last() {
+        Some(last) if !last.is_empty() && last.bytes().all(|b| b.is_ascii_digit()) => true,
+        Some(last) => parse_ipv4_number(last).is_some(),
+        None => false,
+    }
+}
+
+/// Parses one dot-separated part of an IPv4 address, which may be written in
+/// decimal, octal (leading `0`) or hexadecimal (leading `0x`).
+/// Numbers too large to fit in a `u64` saturate, since they are invalid either way.
+fn parse_ipv4_number(input: &str) -> Option<u64> {
+    if input.is_empty() {
+        return None;
+    }
+
+    let (digits, radix) = if let Some(hex) = input
+        .strip_prefix("0x")
+        .or_else(|| input.strip_prefix("0X"))
+    {
+        (hex, 16)
+    } else if input.len() > 1 && input.starts_with('0') {
+        (&input[1..], 8)
+    } else {
+        (input, 10)
+    };
+
+    if digits.is_empty() {
+        return Some(0);
+    }
+
+    digits.chars().try_fold(0u64, |number, c| {
+        c.to_digit(radix).map(|digit| {
+            number
+                .saturating_mul(radix as u64)
+                .saturating_add(digit as u64)
+        })
+    })
+}
+
+/// https://url.spec.whatwg.org/#concept-ipv4-parser
+fn parse_ipv4(input: &str) -> Option<u32> {
+    let mut parts = input.split('.').collect::<Vec<_>>();
+    if parts.last() == Some(&"") && parts.len() > 1 {
+        parts.pop();
+    }
+
+    if parts.len() > 4 {
+        return None;
+    }
+
+    let numbers = parts
+        .into_iter()
+        .map(parse_ipv4_number)
+        .collect::<Option<Vec<_>>>()?;
+
+    let (last, rest) = numbers.split_last()?;
+    if rest.iter().any(|&n| n > 255) || *last >= 256u64.pow(5 - numbers.len() as u32) {
+        return None;
+    }
+
+    let address = rest.iter().enumerate().fold(*last, |address, (i, n)| {
+        address + n * 256u64.pow(3 - i as u32)
+    });
+    u32::try_from(address).ok()
+}
+
+/// https://url.spec.whatwg.org/#concept-ipv6-parser
+fn parse_ipv6(input: &str) -> Option<[u16; 8]> {
+    let input = input.chars().collect::<Vec<_>>();
+    let mut address = [0u16; 8];
+    let mut piece_index = 0;
+    let mut compress = None;
+    let mut pointer = 0;
+
+    if input.first() == Some(&':') {
+        if input.get(1) != Some(&':') {
+            return None;
+        }
+        pointer += 2;
+        piece_index += 1;
+        compress = Some(piece_index);
+    }
+
+    while let Some(&c) = input.get(pointer) {
+        if piece_index == 8 {
+            return None;
+        }
+
+        if c == ':' {
+            if compress.is_some() {
+                return None;
+            }
+            pointer += 1;
+            piece_index += 1;
+            compress = Some(piece_index);
+            continue;
+        }
+
+        let mut value = 0u16;
+        let mut length = 0;
+        while length < 4 {
+            match input.get(pointer).and_then(|c| c.to_digit(16)) {
+                Some(digit) => {
+                    value = value * 0x10 + digit as u16;
+                    pointer += 1;
+                    length += 1;
+                }
+                None => break,
+            }
+        }
+
+        match input.get(pointer) {
+            Some('.') => {
+                if length == 0 || piece_index > 6 {
+                    return None;
+                }
+                pointer -= length;
+                parse_embedded_ipv4(&input[pointer..], &mut address, piece_index)?;
+                piece_index += 2;
+                break;
+            }
+            Some(':') => {
+                pointer += 1;
+                if pointer == input.len() {
+                    return None;
+                }
+            }
+            Some(_) => return None,
+            None => {}
+        }
+
+        address[piece_index] = value;
+        piece_index += 1;
+    }
+
+    if let Some(compress) = compress {
+        let mut swaps = piece_index - compress;
+        piece_index = 7;
+        while piece_index != 0 && swaps > 0 {
+            address.swap(piece_index, compress + swaps - 1);
+            piece_index -= 1;
+            swaps -= 1;
+        }
+    } else if piece_index != 8 {
+        return None;
+    }
+
+    Some(address)
+}
+
+/// Parses the dotted IPv4 suffix of an IPv6 address (as in `::ffff:127.0.0.1`)
+/// into the two pieces starting at `piece_index`.
+fn parse_embedded_ipv4(input: &[char], address: &mut [u16; 8], piece_index: usize) -> Option<()> {
+    let mut pointer = 0;
+    let mut numbers_seen = 0;
+    let mut piece_index = piece_index;
+
+    while pointer < input.len() {
+        if numbers_seen > 0 {
+            if input[pointer] == '.' && numbers_seen < 4 {
+                pointer += 1;
+            } else {
+                return None;
+            }
+        }
+
+        let mut ipv4_piece: Option<u16> = None;
+        while let Some(number) = input.get(pointer).and_then(|c| c.to_digit(10)) {
+            ipv4_piece = match ipv4_piece {
+                None => Some(number as u16),
+                Some(0) => return None,
+                Some(piece) => Some(piece * 10 + number as u16),
+            };
+            if ipv4_piece > Some(255) {
+                return None;
+            }
+            pointer += 1;
+        }
+
+        address[piece_index] = address[piece_index] * 0x100 + ipv4_piece?;
+        numbers_seen += 1;
+        if numbers_seen == 2 || numbers_seen == 4 {
+            piece_index += 1;
+        }
+    }
+
+    (numbers_seen == 4).then_some(())
+}
+
+/// https://url.spec.whatwg.org/#concept-ipv6-serializer
+fn serialize_ipv6(address: &[u16; 8]) -> String {
+    // Find the first longest run of (at least two) zero pieces, which gets compressed to `::`.
+    let mut compress: Option<(usize, usize)> = None;
+    let mut i = 0;
+    while i < address.len() {
+        let run = address[i..].iter().take_while(|&&piece| piece == 0).count();
+        if run > 1 && compress.is_none_or(|(_, longest)| run > longest) {
+            compress = Some((i, run));
+        }
+        i += run.max(1);
+    }
+
+    let mut output = String::new();
+    let mut i = 0;
+    while i < address.len() {
+        if let Some((start, len)) = compress.filter(|&(start, _)| start == i) {
+            output.push_str(if start == 0 { "::" } else { ":" });
+            i += len;
+            continue;
+        }
+        output.push_str(&format!("{:x}", address[i]));
+        if i != address.len() - 1 {
+            output.push(':');
+        }
+        i += 1;
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn parse_host(input: &str) -> Result<Host, UrlError> {
+        input.parse::<Host>()
+    }
+
+    #[test]
+    fn ipv4_shorthand() -> Result<()> {
+        assert_eq!(parse_host("0x7f.1")?.to_string(), "127.0.0.1");
+        assert_eq!(parse_host("192.0x00A80001")?.to_string(), "192.168.0.1");
+        assert_eq!(parse_host("0300.0250.0.1")?.to_string(), "192.168.0.1");
+        Ok(())
+    }
+
+    #[test]
+    fn host_variants() -> Result<()> {
+        assert_eq!(
+            parse_host("0x7f.1")?,
+            Host::Ipv4(Ipv4Addr::new(127, 0, 0, 1))
+        );
+        assert_eq!(parse_host("[::1]")?, Host::Ipv6(Ipv6Addr::LOCALHOST));
+        assert_eq!(
+            parse_host("example.org")?,
+            Host::Domain("example.org".to_string())
+        );
+        assert_eq!(
+            parse_host("[::1]")?.ip_addr(),
+            Some(IpAddr::V6(Ipv6Addr::LOCALHOST))
+        );
+        assert_eq!(parse_host("example.org")?.ip_addr(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn ipv4_out_of_range() {
+        assert!(parse_host("256.0.0.1").is_err());
+        assert!(parse_host("4294967296").is_err());
+        assert!(parse_host("1.2.3.4.5").is_err());
+    }
+
+    #[test]
+    fn ipv6() -> Result<()> {
+        assert_eq!(parse_host("[::1]")?.to_string(), "[::1]");
+        assert_eq!(parse_host("[0:0:0:0:0:0:0:1]")?.to_string(), "[::1]");
+        assert_eq!(
+            parse_host("[2001:db8:0:0:1:0:0:1]")?.to_string(),
+            "[2001:db8::1:0:0:1]"
+        );
+        assert_eq!(parse_host("[::127.0.0.1]")?.to_string(), "[::7f00:1]");
+        assert_eq!(
+            parse_host("[::ffff:1.2.3.4]")?.to_string(),
+            "[::ffff:102:304]"
+        );
+        assert!(parse_host("[::1").is_err());
+        assert!(parse_host("[1:2:3:4:5:6:7:8:9]").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn domain_is_lowercased() -> Result<()> {
+        assert_eq!(parse_host("ExAmPlE.org")?.to_string(), "example.org");
+        assert!(parse_host("exa mple.org").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn idna() -> Result<()> {
+        assert_eq!(
+            parse_host("Bücher.example")?.to_string(),
+            "xn--bcher-kva.example"
+        );
+        assert_eq!(parse_host("%E2%98%83.net")?.to_string(), "xn--n3h.net");
+        // Full-width characters and ideographic full stops get mapped to ASCII.
+        assert_eq!(parse_host("ｇｏ。com")?.to_string(), "go.com");
+        assert!(parse_host("xn--").is_err());
+        assert_eq!(parse_host("a%C2%ADb")?.to_string(), "ab");
+        assert_eq!(
+            parse_host("xn--bcher-kva.example")?.to_display_string(),
+            "bücher.example"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn registrable_domain() -> Result<()> {
+        let host = parse_host("www.example.co.uk")?;
+        assert_eq!(host.public_suffix().as_deref(), Some("co.uk"));
+        assert_eq!(host.registrable_domain().as_deref(), Some("example.co.uk"));
+        assert_eq!(parse_host("co.uk")?.registrable_domain(), None);
+        assert_eq!(
+            parse_host("www.Bücher.example")?
+                .registrable_domain()
+                .as_deref(),
+            Some("xn--bcher-kva.example")
+        );
+        assert_eq!(parse_host("127.0.0.1")?.registrable_domain(), None);
+        assert_eq!(parse_host("127.0.0.1")?.public_suffix(), None);
+        Ok(())
+    }
+}
@@ -1,3 +1,18 @@
+mod host;
+mod mime;
+mod origin;
+mod parser;
+mod percent_encoding;
+mod psl;
+mod search_params;
 pub mod url;
 
+pub use crate::host::Host;
+pub use crate::mime::MimeType;
+pub use crate::origin::{OpaqueOrigin, Origin, Site};
+pub use crate::search_params::SearchParams;
+
 pub use crate::url::{DataUrl, FileUrl, Scheme, Url, UrlError, WebUrl};
+
+/// The version of this crate, e.g. for showing on `about:version`.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
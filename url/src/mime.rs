@@ -0,0 +1,211 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use crate::UrlError;
+
+/// A parsed MIME type, like `text/html;charset=utf-8`
+/// (https://mimesniff.spec.whatwg.org/#understanding-mime-types).
+/// The type, subtype and parameter names are always lowercase.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MimeType {
+    pub r#type: String,
+    pub subtype: String,
+    pub parameters: Vec<(String, String)>,
+}
+
+/// https://mimesniff.spec.whatwg.org/#http-token-code-point
+fn is_token_code_point(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)
+}
+
+fn is_token(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(is_token_code_point)
+}
+
+/// https://mimesniff.spec.whatwg.org/#http-quoted-string-token-code-point
+fn is_quoted_string_token_code_point(c: char) -> bool {
+    c == '\t' || (' '..='~').contains(&c) || ('\u{80}'..='\u{ff}').contains(&c)
+}
+
+/// https://fetch.spec.whatwg.org/#http-whitespace
+fn is_http_whitespace(c: char) -> bool {
+    matches!(c, '\n' | '\r' | '\t' | ' ')
+}
+
+/// Collects an HTTP quoted string (https://fetch.spec.whatwg.org/#collect-an-http-quoted-string)
+/// from the start of `input`, which must start with `"`, and returns its value
+/// along with the rest of `input`.
+fn collect_quoted_string(input: &str) -> (String, &str) {
+    let mut value = String::new();
+    let mut chars = input.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some((_, escaped)) => value.push(escaped),
+                None => {
+                    value.push('\\');
+                    return (value, "");
+                }
+            },
+            '"' => return (value, &input[i + 1..]),
+            _ => value.push(c),
+        }
+    }
+    (value, "")
+}
+
+impl MimeType {
+    /// The type and subtype, e.g. `text/html`.
+    pub fn essence(&self) -> String {
+        format!("{}/{}", self.r#type, self.subtype)
+    }
+
+    /// Returns the value of the parameter with the given (lowercase) name.
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn charset(&self) -> Option<&str> {
+        self.parameter("charset")
+    }
+
+    pub fn is_html(&self) -> bool {
+        self.essence() == "text/html"
+    }
+
+    pub fn is_text(&self) -> bool {
+        self.r#type == "text"
+    }
+
+    pub fn is_image(&self) -> bool {
+        self.r#type == "image"
+    }
+
+    /// The MIME type that data URLs fall back to if they don't have a valid one.
+    pub(crate) fn default_for_data_url() -> Self {
+        Self {
+            r#type: "text".to_string(),
+            subtype: "plain".to_string(),
+            parameters: vec![("charset".to_string(), "US-ASCII".to_string())],
+        }
+    }
+
+    fn parse_parameters(&mut self, mut input: &str) {
+        while let Some(rest) = input.strip_prefix(';') {
+            let rest = rest.trim_start_matches(is_http_whitespace);
+            let name_end = rest.find([';', '=']).unwrap_or(rest.len());
+            let name = rest[..name_end].to_ascii_lowercase();
+            input = &rest[name_end..];
+
+            let Some(rest) = input.strip_prefix('=') else {
+                // Either a parameter without a value, or the end of the input.
+                continue;
+            };
+
+            let value = if rest.starts_with('"') {
+                let (value, rest) = collect_quoted_string(rest);
+                // Ignore anything between the closing quote and the next `;`.
+                input = &rest[rest.find(';').unwrap_or(rest.len())..];
+                value
+            } else {
+                let value_end = rest.find(';').unwrap_or(rest.len());
+                input = &rest[value_end..];
+                let value = rest[..value_end].trim_end_matches(is_http_whitespace);
+                if value.is_empty() {
+                    continue;
+                }
+                value.to_string()
+            };
+
+            if is_token(&name)
+                && value.chars().all(is_quoted_string_token_code_point)
+                && self.parameter(&name).is_none()
+            {
+                self.parameters.push((name, value));
+            }
+        }
+    }
+}
+
+impl FromStr for MimeType {
+    type Err = UrlError;
+
+    /// https://mimesniff.spec.whatwg.org/#parse-a-mime-type
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || UrlError::InvalidMimeType(s.to_string());
+        let input = s.trim_matches(is_http_whitespace);
+
+        let (r#type, rest) = input.split_once('/').ok_or_else(error)?;
+        let subtype_end = rest.find(';').unwrap_or(rest.len());
+        let subtype = rest[..subtype_end].trim_end_matches(is_http_whitespace);
+        if !is_token(r#type) || !is_token(subtype) {
+            return Err(error());
+        }
+
+        let mut mime_type = Self {
+            r#type: r#type.to_ascii_lowercase(),
+            subtype: subtype.to_ascii_lowercase(),
+            parameters: vec![],
+        };
+        mime_type.parse_parameters(&rest[subtype_end..]);
+        Ok(mime_type)
+    }
+}
+
+impl Display for MimeType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.essence())?;
+        for (name, value) in &self.parameters {
+            if is_token(value) {
+                write!(f, ";{name}={value}")?;
+            } else {
+                let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+                write!(f, ";{name}=\"{value}\"")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn parse_mime_type() -> Result<()> {
+        let mime_type = " Text/HTML ; Charset=\"utf-8\" ; foo ; bar=baz ".parse::<MimeType>()?;
+        assert_eq!(mime_type.essence(), "text/html");
+        assert_eq!(mime_type.charset(), Some("utf-8"));
+        assert_eq!(mime_type.parameter("bar"), Some("baz"));
+        assert_eq!(mime_type.parameter("foo"), None);
+        assert_eq!(mime_type.to_string(), "text/html;charset=utf-8;bar=baz");
+        Ok(())
+    }
+
+    #[test]
+    fn first_parameter_wins() -> Result<()> {
+        let mime_type = "text/plain;charset=gbk;charset=windows-1252".parse::<MimeType>()?;
+        assert_eq!(mime_type.charset(), Some("gbk"));
+        Ok(())
+    }
+
+    #[test]
+    fn quoted_parameter() -> Result<()> {
+        let mime_type = r#"text/plain;name="a \"b\";c";x=y"#.parse::<MimeType>()?;
+        assert_eq!(mime_type.parameter("name"), Some(r#"a "b";c"#));
+        assert_eq!(mime_type.parameter("x"), Some("y"));
+        assert_eq!(mime_type.to_string(), r#"text/plain;name="a \"b\";c";x=y"#);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_mime_types() {
+        for input in ["", "text", "text/", "/html", "te xt/html", "text/ht@ml"] {
+            assert!(input.parse::<MimeType>().is_err(), "{input:?}");
+        }
+    }
+}
@@ -0,0 +1,575 @@
+//! A state machine parser for special (http, https, file) URLs,
+//! following the basic URL parser from https://url.spec.whatwg.org/#concept-basic-url-parser.
+//! Since every scheme that reaches this parser is special,
+//! the states that only apply to non-special URLs (opaque paths etc.) are left out.
+
+use crate::host::Host;
+use crate::percent_encoding::{self, EncodeSet};
+use crate::{Scheme, UrlError};
+
+/// The intermediate representation of a URL, as the spec describes it.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct UrlRecord {
+    pub(crate) scheme: Scheme,
+    pub(crate) username: String,
+    pub(crate) password: String,
+    /// `None` for the empty host of a file URL.
+    pub(crate) host: Option<Host>,
+    pub(crate) port: Option<u16>,
+    pub(crate) path: Vec<String>,
+    pub(crate) query: Option<String>,
+    pub(crate) fragment: Option<String>,
+}
+
+impl UrlRecord {
+    fn new(scheme: Scheme) -> Self {
+        Self {
+            scheme,
+            username: String::new(),
+            password: String::new(),
+            host: None,
+            port: None,
+            path: vec![],
+            query: None,
+            fragment: None,
+        }
+    }
+
+    /// https://url.spec.whatwg.org/#shorten-a-urls-path
+    fn shorten_path(&mut self) {
+        if matches!(self.scheme, Scheme::File)
+            && self.path.len() == 1
+            && is_normalized_windows_drive_letter(&self.path[0])
+        {
+            return;
+        }
+        self.path.pop();
+    }
+
+    /// Returns the serialized path, e.g. `/a/b/c`.
+    pub(crate) fn serialize_path(&self) -> String {
+        self.path.iter().fold(String::new(), |mut path, segment| {
+            path.push('/');
+            path.push_str(segment);
+            path
+        })
+    }
+}
+
+/// Removes leading and trailing C0 controls and spaces,
+/// and all ASCII tabs and newlines, as the first step of parsing any URL.
+pub(crate) fn preprocess(input: &str) -> String {
+    input
+        .trim_matches(|c: char| c <= ' ')
+        .chars()
+        .filter(|c| !matches!(c, '\t' | '\n' | '\r'))
+        .collect()
+}
+
+/// Returns the (lowercased) scheme at the start of `input`, if there is one,
+/// along with the rest of `input` after the `:`.
+pub(crate) fn split_scheme(input: &str) -> Option<(String, &str)> {
+    let (scheme, rest) = input.split_once(':')?;
+    let mut chars = scheme.chars();
+    let starts_with_alpha = chars.next().is_some_and(|c| c.is_ascii_alphabetic());
+    if starts_with_alpha && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+    {
+        Some((scheme.to_ascii_lowercase(), rest))
+    } else {
+        None
+    }
+}
+
+/// https://url.spec.whatwg.org/#windows-drive-letter
+fn is_windows_drive_letter(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(
+        (chars.next(), chars.next(), chars.next()),
+        (Some(letter), Some(':' | '|'), None) if letter.is_ascii_alphabetic()
+    )
+}
+
+/// https://url.spec.whatwg.org/#normalized-windows-drive-letter
+fn is_normalized_windows_drive_letter(s: &str) -> bool {
+    is_windows_drive_letter(s) && s.ends_with(':')
+}
+
+/// https://url.spec.whatwg.org/#start-with-a-windows-drive-letter
+fn starts_with_windows_drive_letter(input: &[char]) -> bool {
+    input.len() >= 2
+        && input[0].is_ascii_alphabetic()
+        && matches!(input[1], ':' | '|')
+        && (input.len() == 2 || matches!(input[2], '/' | '\\' | '?' | '#'))
+}
+
+/// https://url.spec.whatwg.org/#single-dot-path-segment
+fn is_single_dot_segment(s: &str) -> bool {
+    s == "." || s.eq_ignore_ascii_case("%2e")
+}
+
+/// https://url.spec.whatwg.org/#double-dot-path-segment
+fn is_double_dot_segment(s: &str) -> bool {
+    matches!(
+        s.to_ascii_lowercase().as_str(),
+        ".." | ".%2e" | "%2e." | "%2e%2e"
+    )
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    SpecialRelativeOrAuthority,
+    Relative,
+    RelativeSlash,
+    SpecialAuthoritySlashes,
+    SpecialAuthorityIgnoreSlashes,
+    Authority,
+    Host,
+    Port,
+    File,
+    FileSlash,
+    FileHost,
+    PathStart,
+    Path,
+    Query,
+    Fragment,
+}
+
+struct Parser<'a> {
+    original: &'a str,
+    input: Vec<char>,
+    // Signed, because the spec sometimes "decreases the pointer by 1" at the start of the input.
+    pointer: isize,
+    state: State,
+    buffer: String,
+    at_sign_seen: bool,
+    inside_brackets: bool,
+    password_token_seen: bool,
+    base: Option<&'a UrlRecord>,
+    url: UrlRecord,
+}
+
+/// Parses `input` (which must already be preprocessed) into a URL record,
+/// resolving it against `base` if it is relative.
+pub(crate) fn parse(input: &str, base: Option<&UrlRecord>) -> Result<UrlRecord, UrlError> {
+    let (url, state, rest) = match split_scheme(input) {
+        Some((scheme, rest)) => {
+            let scheme = scheme.parse::<Scheme>()?;
+            if !matches!(scheme, Scheme::Http | Scheme::Https | Scheme::File) {
+                return Err(UrlError::UnknownScheme(scheme.to_string()));
+            }
+            let state = match base {
+                _ if matches!(scheme, Scheme::File) => State::File,
+                Some(base) if base.scheme == scheme => State::SpecialRelativeOrAuthority,
+                _ => State::SpecialAuthoritySlashes,
+            };
+            (UrlRecord::new(scheme), state, rest)
+        }
+        None => {
+            let base = base.ok_or_else(|| UrlError::RelativeUrlWithoutBase(input.to_string()))?;
+            let state = if matches!(base.scheme, Scheme::File) {
+                State::File
+            } else {
+                State::Relative
+            };
+            (UrlRecord::new(base.scheme), state, input)
+        }
+    };
+
+    let mut parser = Parser {
+        original: input,
+        input: rest.chars().collect(),
+        pointer: 0,
+        state,
+        buffer: String::new(),
+        at_sign_seen: false,
+        inside_brackets: false,
+        password_token_seen: false,
+        base,
+        url,
+    };
+    parser.run()?;
+    Ok(parser.url)
+}
+
+impl<'a> Parser<'a> {
+    fn run(&mut self) -> Result<(), UrlError> {
+        loop {
+            let c = self.c();
+            match self.state {
+                State::SpecialRelativeOrAuthority => self.special_relative_or_authority(c),
+                State::Relative => self.relative(c)?,
+                State::RelativeSlash => self.relative_slash(c)?,
+                State::SpecialAuthoritySlashes => self.special_authority_slashes(c),
+                State::SpecialAuthorityIgnoreSlashes => self.special_authority_ignore_slashes(c),
+                State::Authority => self.authority(c)?,
+                State::Host => self.host(c)?,
+                State::Port => self.port(c)?,
+                State::File => self.file(c),
+                State::FileSlash => self.file_slash(c),
+                State::FileHost => self.file_host(c)?,
+                State::PathStart => self.path_start(c),
+                State::Path => self.path(c),
+                State::Query => self.query(c),
+                State::Fragment => self.fragment(c),
+            }
+
+            if self.pointer >= self.input.len() as isize {
+                return Ok(());
+            }
+            self.pointer += 1;
+        }
+    }
+
+    /// The current code point, or `None` for the EOF code point.
+    fn c(&self) -> Option<char> {
+        usize::try_from(self.pointer)
+            .ok()
+            .and_then(|pointer| self.input.get(pointer))
+            .copied()
+    }
+
+    /// The code points after the current one.
+    fn remaining(&self) -> &[char] {
+        let start = (self.pointer + 1).clamp(0, self.input.len() as isize) as usize;
+        &self.input[start..]
+    }
+
+    /// The code points from the current one to the end of the input.
+    fn rest(&self) -> &[char] {
+        let start = self.pointer.clamp(0, self.input.len() as isize) as usize;
+        &self.input[start..]
+    }
+
+    fn invalid_url(&self) -> UrlError {
+        UrlError::InvalidUrl(self.original.to_string())
+    }
+
+    /// The base URL, which the states that call this are only reachable with.
+    /// Without one, the input can't be parsed rather than panicking.
+    fn base(&self) -> Result<&'a UrlRecord, UrlError> {
+        self.base.ok_or_else(|| self.invalid_url())
+    }
+
+    fn special_relative_or_authority(&mut self, c: Option<char>) {
+        if c == Some('/') && self.remaining().first() == Some(&'/') {
+            self.state = State::SpecialAuthorityIgnoreSlashes;
+            self.pointer += 1;
+        } else {
+            self.state = State::Relative;
+            self.pointer -= 1;
+        }
+    }
+
+    fn relative(&mut self, c: Option<char>) -> Result<(), UrlError> {
+        let base = self.base()?;
+        self.url.scheme = base.scheme;
+        if matches!(c, Some('/' | '\\')) {
+            self.state = State::RelativeSlash;
+            return Ok(());
+        }
+
+        self.url.username.clone_from(&base.username);
+        self.url.password.clone_from(&base.password);
+        self.url.host.clone_from(&base.host);
+        self.url.port = base.port;
+        self.url.path.clone_from(&base.path);
+        self.url.query.clone_from(&base.query);
+
+        match c {
+            Some('?') => {
+                self.url.query = Some(String::new());
+                self.state = State::Query;
+            }
+            Some('#') => {
+                self.url.fragment = Some(String::new());
+                self.state = State::Fragment;
+            }
+            Some(_) => {
+                self.url.query = None;
+                self.url.shorten_path();
+                self.state = State::Path;
+                self.pointer -= 1;
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    fn relative_slash(&mut self, c: Option<char>) -> Result<(), UrlError> {
+        if matches!(c, Some('/' | '\\')) {
+            self.state = State::SpecialAuthorityIgnoreSlashes;
+        } else {
+            let base = self.base()?;
+            self.url.username.clone_from(&base.username);
+            self.url.password.clone_from(&base.password);
+            self.url.host.clone_from(&base.host);
+            self.url.port = base.port;
+            self.state = State::Path;
+            self.pointer -= 1;
+        }
+        Ok(())
+    }
+
+    fn special_authority_slashes(&mut self, c: Option<char>) {
+        self.state = State::SpecialAuthorityIgnoreSlashes;
+        if c == Some('/') && self.remaining().first() == Some(&'/') {
+            self.pointer += 1;
+        } else {
+            self.pointer -= 1;
+        }
+    }
+
+    fn special_authority_ignore_slashes(&mut self, c: Option<char>) {
+        if !matches!(c, Some('/' | '\\')) {
+            self.state = State::Authority;
+            self.pointer -= 1;
+        }
+    }
+
+    fn authority(&mut self, c: Option<char>) -> Result<(), UrlError> {
+        match c {
+            Some('@') => {
+                if self.at_sign_seen {
+                    self.buffer.insert_str(0, "%40");
+                }
+                self.at_sign_seen = true;
+
+                for code_point in self.buffer.chars() {
+                    if code_point == ':' && !self.password_token_seen {
+                        self.password_token_seen = true;
+                        continue;
+                    }
+                    let target = if self.password_token_seen {
+                        &mut self.url.password
+                    } else {
+                        &mut self.url.username
+                    };
+                    percent_encoding::encode_char(code_point, EncodeSet::Userinfo, target);
+                }
+                self.buffer.clear();
+            }
+            None | Some('/' | '?' | '#' | '\\') => {
+                if self.at_sign_seen && self.buffer.is_empty() {
+                    return Err(self.invalid_url());
+                }
+                self.pointer -= self.buffer.chars().count() as isize + 1;
+                self.buffer.clear();
+                self.state = State::Host;
+            }
+            Some(c) => self.buffer.push(c),
+        }
+        Ok(())
+    }
+
+    fn host(&mut self, c: Option<char>) -> Result<(), UrlError> {
+        match c {
+            Some(':') if !self.inside_brackets => {
+                if self.buffer.is_empty() {
+                    return Err(self.invalid_url());
+                }
+                self.url.host = Some(self.buffer.parse::<Host>()?);
+                self.buffer.clear();
+                self.state = State::Port;
+            }
+            None | Some('/' | '?' | '#' | '\\') => {
+                self.pointer -= 1;
+                if self.buffer.is_empty() {
+                    return Err(self.invalid_url());
+                }
+                self.url.host = Some(self.buffer.parse::<Host>()?);
+                self.buffer.clear();
+                self.state = State::PathStart;
+            }
+            Some(c) => {
+                if c == '[' {
+                    self.inside_brackets = true;
+                } else if c == ']' {
+                    self.inside_brackets = false;
+                }
+                self.buffer.push(c);
+            }
+        }
+        Ok(())
+    }
+
+    fn port(&mut self, c: Option<char>) -> Result<(), UrlError> {
+        match c {
+            Some(digit) if digit.is_ascii_digit() => self.buffer.push(digit),
+            None | Some('/' | '?' | '#' | '\\') => {
+                if !self.buffer.is_empty() {
+                    let port = self.buffer.parse::<u16>()?;
+                    self.url.port = (Some(port) != self.url.scheme.default_port()).then_some(port);
+                    self.buffer.clear();
+                }
+                self.state = State::PathStart;
+                self.pointer -= 1;
+            }
+            Some(_) => return Err(self.invalid_url()),
+        }
+        Ok(())
+    }
+
+    fn file(&mut self, c: Option<char>) {
+        self.url.scheme = Scheme::File;
+        self.url.host = None;
+
+        if matches!(c, Some('/' | '\\')) {
+            self.state = State::FileSlash;
+            return;
+        }
+
+        match self.base.filter(|base| matches!(base.scheme, Scheme::File)) {
+            Some(base) => {
+                self.url.host.clone_from(&base.host);
+                self.url.path.clone_from(&base.path);
+                self.url.query.clone_from(&base.query);
+                match c {
+                    Some('?') => {
+                        self.url.query = Some(String::new());
+                        self.state = State::Query;
+                    }
+                    Some('#') => {
+                        self.url.fragment = Some(String::new());
+                        self.state = State::Fragment;
+                    }
+                    Some(_) => {
+                        self.url.query = None;
+                        if starts_with_windows_drive_letter(self.rest()) {
+                            self.url.path.clear();
+                        } else {
+                            self.url.shorten_path();
+                        }
+                        self.state = State::Path;
+                        self.pointer -= 1;
+                    }
+                    None => {}
+                }
+            }
+            None => {
+                self.state = State::Path;
+                self.pointer -= 1;
+            }
+        }
+    }
+
+    fn file_slash(&mut self, c: Option<char>) {
+        if matches!(c, Some('/' | '\\')) {
+            self.state = State::FileHost;
+            return;
+        }
+
+        if let Some(base) = self.base.filter(|base| matches!(base.scheme, Scheme::File)) {
+            self.url.host.clone_from(&base.host);
+            if !starts_with_windows_drive_letter(self.rest()) {
+                if let Some(drive_letter) = base
+                    .path
+                    .first()
+                    .filter(|segment| is_normalized_windows_drive_letter(segment))
+                {
+                    self.url.path.push(drive_letter.clone());
+                }
+            }
+        }
+        self.state = State::Path;
+        self.pointer -= 1;
+    }
+
+    fn file_host(&mut self, c: Option<char>) -> Result<(), UrlError> {
+        match c {
+            None | Some('/' | '\\' | '?' | '#') => {
+                self.pointer -= 1;
+                if is_windows_drive_letter(&self.buffer) {
+                    // The buffer is kept, and becomes the first segment of the path.
+                    self.state = State::Path;
+                } else if self.buffer.is_empty() {
+                    self.url.host = None;
+                    self.state = State::PathStart;
+                } else {
+                    self.url.host = match self.buffer.parse::<Host>()? {
+                        Host::Domain(domain) if domain == "localhost" => None,
+                        host => Some(host),
+                    };
+                    self.buffer.clear();
+                    self.state = State::PathStart;
+                }
+            }
+            Some(c) => self.buffer.push(c),
+        }
+        Ok(())
+    }
+
+    fn path_start(&mut self, c: Option<char>) {
+        self.state = State::Path;
+        if !matches!(c, Some('/' | '\\')) {
+            self.pointer -= 1;
+        }
+    }
+
+    fn path(&mut self, c: Option<char>) {
+        match c {
+            None | Some('/' | '\\' | '?' | '#') => self.end_path_segment(c),
+            Some(c) => percent_encoding::encode_char(c, EncodeSet::Path, &mut self.buffer),
+        }
+    }
+
+    fn end_path_segment(&mut self, c: Option<char>) {
+        let slash = matches!(c, Some('/' | '\\'));
+
+        if is_double_dot_segment(&self.buffer) {
+            self.url.shorten_path();
+            if !slash {
+                self.url.path.push(String::new());
+            }
+        } else if is_single_dot_segment(&self.buffer) {
+            if !slash {
+                self.url.path.push(String::new());
+            }
+        } else {
+            if matches!(self.url.scheme, Scheme::File)
+                && self.url.path.is_empty()
+                && is_windows_drive_letter(&self.buffer)
+            {
+                self.buffer.replace_range(1..2, ":");
+            }
+            self.url.path.push(self.buffer.clone());
+        }
+        self.buffer.clear();
+
+        match c {
+            Some('?') => {
+                self.url.query = Some(String::new());
+                self.state = State::Query;
+            }
+            Some('#') => {
+                self.url.fragment = Some(String::new());
+                self.state = State::Fragment;
+            }
+            _ => {}
+        }
+    }
+
+    fn query(&mut self, c: Option<char>) {
+        match c {
+            None | Some('#') => {
+                let encoded = percent_encoding::encode(&self.buffer, EncodeSet::SpecialQuery);
+                self.url
+                    .query
+                    .get_or_insert_with(String::new)
+                    .push_str(&encoded);
+                self.buffer.clear();
+                if c == Some('#') {
+                    self.url.fragment = Some(String::new());
+                    self.state = State::Fragment;
+                }
+            }
+            Some(c) => self.buffer.push(c),
+        }
+    }
+
+    fn fragment(&mut self, c: Option<char>) {
+        if let Some(c) = c {
+            let fragment = self.url.fragment.get_or_insert_with(String::new);
+            percent_encoding::encode_char(c, EncodeSet::Fragment, fragment);
+        }
+    }
+}
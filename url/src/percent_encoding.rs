@@ -0,0 +1,83 @@
+/// The percent-encode sets from https://url.spec.whatwg.org/#percent-encoded-bytes.
+/// All of them include the C0 control percent-encode set.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum EncodeSet {
+    C0Control,
+    Fragment,
+    Query,
+    SpecialQuery,
+    Path,
+    Userinfo,
+    Component,
+    FormUrlencoded,
+}
+
+impl EncodeSet {
+    fn contains(self, byte: u8) -> bool {
+        let c0_control = !(0x20..=0x7e).contains(&byte);
+        match self {
+            Self::C0Control => c0_control,
+            Self::Fragment => c0_control || matches!(byte, b' ' | b'"' | b'<' | b'>' | b'`'),
+            Self::Query => c0_control || matches!(byte, b' ' | b'"' | b'#' | b'<' | b'>'),
+            Self::SpecialQuery => Self::Query.contains(byte) || byte == b'\'',
+            Self::Path => Self::Query.contains(byte) || matches!(byte, b'?' | b'`' | b'{' | b'}'),
+            Self::Userinfo => {
+                Self::Path.contains(byte)
+                    || matches!(byte, b'/' | b':' | b';' | b'=' | b'@' | b'['..=b'^' | b'|')
+            }
+            Self::Component => {
+                Self::Userinfo.contains(byte) || matches!(byte, b'$'..=b'&' | b'+' | b',')
+            }
+            Self::FormUrlencoded => {
+                Self::Component.contains(byte) || matches!(byte, b'!' | b'\''..=b')' | b'~')
+            }
+        }
+    }
+}
+
+/// Appends `c` to `out`, percent-encoding each of its UTF-8 bytes that is in `set`.
+pub(crate) fn encode_char(c: char, set: EncodeSet, out: &mut String) {
+    let mut buf = [0u8; 4];
+    for &byte in c.encode_utf8(&mut buf).as_bytes() {
+        if set.contains(byte) {
+            out.push_str(&format!("%{byte:02X}"));
+        } else {
+            out.push(byte as char);
+        }
+    }
+}
+
+/// Percent-encodes every character of `s` that is in `set`.
+pub(crate) fn encode(s: &str, set: EncodeSet) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        encode_char(c, set, &mut out);
+    }
+    out
+}
+
+/// Decodes `%XX` sequences into the bytes they stand for.
+/// Malformed sequences (e.g. `%zz` or a trailing `%`) are left as they are, like the spec says.
+pub(crate) fn decode(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        let byte = input[i];
+        if byte == b'%' && i + 2 < input.len() {
+            let hex = (hex_value(input[i + 1]), hex_value(input[i + 2]));
+            if let (Some(high), Some(low)) = hex {
+                out.push(high << 4 | low);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(byte);
+        i += 1;
+    }
+    out
+}
+
+#[inline]
+fn hex_value(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|d| d as u8)
+}
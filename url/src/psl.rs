@@ -0,0 +1,155 @@
+//! Public suffix lookups (https://publicsuffix.org/list/), using a snapshot of the list
+//! bundled with the crate. To update it, replace `public_suffix_list.dat`
+//! with https://publicsuffix.org/list/public_suffix_list.dat.
+
+use std::collections::HashSet;
+use std::sync::LazyLock;
+
+static LIST: LazyLock<PublicSuffixList> =
+    LazyLock::new(|| PublicSuffixList::parse(include_str!("public_suffix_list.dat")));
+
+#[derive(Debug, Default)]
+struct PublicSuffixList {
+    rules: HashSet<String>,
+    /// Wildcard rules, stored without their leading `*.`.
+    wildcards: HashSet<String>,
+    /// Exception rules, stored without their leading `!`.
+    exceptions: HashSet<String>,
+}
+
+impl PublicSuffixList {
+    fn parse(list: &str) -> Self {
+        let mut psl = Self::default();
+        let rules = list
+            .lines()
+            .filter_map(|line| line.split_whitespace().next())
+            .filter(|rule| !rule.starts_with("//"));
+
+        for rule in rules {
+            // Hosts are always in their ASCII form by the time we look them up,
+            // so the rules have to be too.
+            let (set, rule) = if let Some(rule) = rule.strip_prefix("*.") {
+                (&mut psl.wildcards, rule)
+            } else if let Some(rule) = rule.strip_prefix('!') {
+                (&mut psl.exceptions, rule)
+            } else {
+                (&mut psl.rules, rule)
+            };
+            if let Ok(rule) = idna::domain_to_ascii(rule) {
+                set.insert(rule);
+            }
+        }
+        psl
+    }
+
+    /// Returns how many labels (counting from the right) of `labels` make up the public suffix.
+    fn suffix_len(&self, labels: &[&str]) -> usize {
+        for i in 0..labels.len() {
+            let candidate = labels[i..].join(".");
+            if self.exceptions.contains(&candidate) {
+                // An exception rule means the public suffix is one label shorter than the rule.
+                return labels.len() - i - 1;
+            }
+            let wildcard_parent = labels.get(i + 1..).map(|parent| parent.join("."));
+            if self.rules.contains(&candidate)
+                || wildcard_parent.is_some_and(|parent| self.wildcards.contains(&parent))
+            {
+                return labels.len() - i;
+            }
+        }
+        // The implicit `*` rule: any TLD is a public suffix.
+        1
+    }
+}
+
+/// Returns the public suffix of an ASCII, lowercase `domain`, e.g. `co.uk` for `www.example.co.uk`.
+pub(crate) fn public_suffix(domain: &str) -> String {
+    let (domain, trailing_dot) = match domain.strip_suffix('.') {
+        Some(domain) => (domain, "."),
+        None => (domain, ""),
+    };
+    let labels = domain.split('.').collect::<Vec<_>>();
+    let suffix_len = LIST.suffix_len(&labels);
+    format!(
+        "{}{trailing_dot}",
+        labels[labels.len() - suffix_len..].join(".")
+    )
+}
+
+/// Returns the registrable domain of an ASCII, lowercase `domain` (its public suffix
+/// plus one more label, e.g. `example.co.uk` for `www.example.co.uk`),
+/// or `None` if `domain` is a public suffix itself.
+pub(crate) fn registrable_domain(domain: &str) -> Option<String> {
+    let (domain, trailing_dot) = match domain.strip_suffix('.') {
+        Some(domain) => (domain, "."),
+        None => (domain, ""),
+    };
+    let labels = domain.split('.').collect::<Vec<_>>();
+    if labels.iter().any(|label| label.is_empty()) {
+        return None;
+    }
+
+    let suffix_len = LIST.suffix_len(&labels);
+    (labels.len() > suffix_len).then(|| {
+        format!(
+            "{}{trailing_dot}",
+            labels[labels.len() - suffix_len - 1..].join(".")
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registrable_domains() {
+        // Some of the cases from https://raw.githubusercontent.com/publicsuffix/list/master/tests/tests.txt
+        let cases = [
+            ("com", None),
+            ("example.com", Some("example.com")),
+            ("www.example.com", Some("example.com")),
+            (".example.com", None),
+            ("example", None),
+            ("example.example", Some("example.example")),
+            ("b.example.example", Some("example.example")),
+            ("uk.com", None),
+            ("b.example.uk.com", Some("example.uk.com")),
+            ("mm", None),
+            ("c.mm", None),
+            ("a.b.c.mm", Some("b.c.mm")),
+            ("ide.kyoto.jp", None),
+            ("a.b.ide.kyoto.jp", Some("b.ide.kyoto.jp")),
+            ("c.kobe.jp", None),
+            ("a.b.c.kobe.jp", Some("b.c.kobe.jp")),
+            ("city.kobe.jp", Some("city.kobe.jp")),
+            ("www.city.kobe.jp", Some("city.kobe.jp")),
+            ("k12.ak.us", None),
+            ("www.test.k12.ak.us", Some("test.k12.ak.us")),
+            (
+                "xn--85x722f.xn--55qx5d.cn",
+                Some("xn--85x722f.xn--55qx5d.cn"),
+            ),
+            ("www.xn--85x722f.xn--fiqs8s", Some("xn--85x722f.xn--fiqs8s")),
+            ("shishi.xn--fiqs8s", Some("shishi.xn--fiqs8s")),
+            ("xn--fiqs8s", None),
+            ("example.com.", Some("example.com.")),
+        ];
+
+        for (domain, expected) in cases {
+            assert_eq!(
+                registrable_domain(domain).as_deref(),
+                expected,
+                "registrable domain of {domain:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn public_suffixes() {
+        assert_eq!(public_suffix("www.example.co.uk"), "co.uk");
+        assert_eq!(public_suffix("example.github.io"), "github.io");
+        assert_eq!(public_suffix("example.unlisted"), "unlisted");
+        assert_eq!(public_suffix("example.com."), "com.");
+    }
+}
@@ -0,0 +1,220 @@
+use std::convert::Infallible;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use crate::percent_encoding::{self, EncodeSet};
+
+/// The name/value pairs of a URL query (or a form submission),
+/// like `URLSearchParams` in JavaScript (https://url.spec.whatwg.org/#interface-urlsearchparams).
+/// Names and values are stored decoded, and get `application/x-www-form-urlencoded`-encoded
+/// when the params are displayed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchParams {
+    pairs: Vec<(String, String)>,
+}
+
+/// Decodes one name or value of an `application/x-www-form-urlencoded` string.
+fn decode(s: &str) -> String {
+    let s = s.replace('+', " ");
+    String::from_utf8_lossy(&percent_encoding::decode(s.as_bytes())).to_string()
+}
+
+/// Encodes one name or value as `application/x-www-form-urlencoded`.
+fn encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c == ' ' {
+            encoded.push('+');
+        } else {
+            percent_encoding::encode_char(c, EncodeSet::FormUrlencoded, &mut encoded);
+        }
+    }
+    encoded
+}
+
+impl SearchParams {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a new name/value pair, even if `name` is already present.
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.pairs.push((name.to_string(), value.to_string()));
+    }
+
+    /// Sets the value of the first pair with the given `name` and removes any others,
+    /// or appends a new pair if there is none.
+    pub fn set(&mut self, name: &str, value: &str) {
+        let mut found = false;
+        self.pairs.retain_mut(|(n, v)| {
+            if n != name {
+                return true;
+            }
+            if found {
+                return false;
+            }
+            found = true;
+            *v = value.to_string();
+            true
+        });
+
+        if !found {
+            self.append(name, value);
+        }
+    }
+
+    /// Removes all pairs with the given `name`.
+    pub fn remove(&mut self, name: &str) {
+        self.pairs.retain(|(n, _)| n != name);
+    }
+
+    /// Returns the value of the first pair with the given `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the values of all pairs with the given `name`, in order.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.pairs
+            .iter()
+            .filter(move |(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.pairs.iter().any(|(n, _)| n == name)
+    }
+
+    /// Sorts the pairs by name (comparing UTF-16 code units, as the spec says),
+    /// keeping the relative order of pairs with the same name.
+    pub fn sort(&mut self) {
+        self.pairs
+            .sort_by(|(a, _), (b, _)| a.encode_utf16().cmp(b.encode_utf16()));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+impl FromStr for SearchParams {
+    type Err = Infallible;
+
+    /// Parses an `application/x-www-form-urlencoded` string (with or without a leading `?`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.strip_prefix('?').unwrap_or(s);
+        let pairs = s
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (decode(name), decode(value))
+            })
+            .collect();
+        Ok(Self { pairs })
+    }
+}
+
+impl Display for SearchParams {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let serialized = self
+            .pairs
+            .iter()
+            .map(|(name, value)| format!("{}={}", encode(name), encode(value)))
+            .collect::<Vec<_>>()
+            .join("&");
+        write!(f, "{serialized}")
+    }
+}
+
+impl<'a> IntoIterator for &'a SearchParams {
+    type Item = (&'a str, &'a str);
+    type IntoIter = Box<dyn Iterator<Item = Self::Item> + 'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Box::new(self.iter())
+    }
+}
+
+impl<'a> FromIterator<(&'a str, &'a str)> for SearchParams {
+    fn from_iter<T: IntoIterator<Item = (&'a str, &'a str)>>(iter: T) -> Self {
+        let pairs = iter
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        Self { pairs }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn parse() -> Result<()> {
+        let params = "?a=1&b=two+words&&c=%F0%9F%90%99&a=3&d".parse::<SearchParams>()?;
+        assert_eq!(
+            params.iter().collect::<Vec<_>>(),
+            vec![
+                ("a", "1"),
+                ("b", "two words"),
+                ("c", "🐙"),
+                ("a", "3"),
+                ("d", "")
+            ]
+        );
+        assert_eq!(params.get("a"), Some("1"));
+        assert_eq!(params.get_all("a").collect::<Vec<_>>(), vec!["1", "3"]);
+        assert_eq!(params.get("e"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn modify() -> Result<()> {
+        let mut params = "a=1&b=2&a=3&c=4&a=5".parse::<SearchParams>()?;
+        params.set("a", "x");
+        assert_eq!(params.to_string(), "a=x&b=2&c=4");
+        params.set("d", "y");
+        params.append("b", "z");
+        params.remove("c");
+        assert_eq!(params.to_string(), "a=x&b=2&d=y&b=z");
+        params.sort();
+        assert_eq!(params.to_string(), "a=x&b=2&b=z&d=y");
+        Ok(())
+    }
+
+    #[test]
+    fn serialize() {
+        let params = SearchParams::from_iter([("q", "a b&c=d"), ("emoji", "🐙"), ("safe", "*-._")]);
+        assert_eq!(
+            params.to_string(),
+            "q=a+b%26c%3Dd&emoji=%F0%9F%90%99&safe=*-._"
+        );
+    }
+
+    #[test]
+    fn sort_by_utf16_code_units() -> Result<()> {
+        // U+FFFD sorts after U+1F419 when comparing UTF-16 code units, but not code points.
+        let mut params = "\u{fffd}=a&\u{1f419}=b".parse::<SearchParams>()?;
+        params.sort();
+        assert_eq!(
+            params.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+            vec!["\u{1f419}", "\u{fffd}"]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,182 @@
+//! Runs the URL parsing cases from the web-platform-tests suite
+//! (https://github.com/web-platform-tests/wpt/blob/master/url/resources/urltestdata.json).
+//! Octo only parses a handful of schemes, so the cases for other schemes are skipped
+//! (including the ones expected to fail, which would fail for the scheme alone).
+
+use octo_url::Url;
+use serde_json::Value;
+
+const FIXTURE: &str = include_str!("urltestdata.json");
+const SUPPORTED_PROTOCOLS: &[&str] = &["http:", "https:", "file:"];
+/// How many cases have to run, so that skipping too many of them doesn't go unnoticed.
+const MIN_RUN: usize = 540;
+
+/// The components of a parsed URL, in the shape WPT expects them.
+#[derive(Debug, PartialEq)]
+struct Components {
+    username: String,
+    password: String,
+    hostname: String,
+    port: String,
+    pathname: String,
+    search: String,
+    hash: String,
+}
+
+fn prefixed(prefix: char, component: &Option<String>) -> String {
+    component
+        .as_deref()
+        .filter(|s| !s.is_empty())
+        .map(|s| format!("{prefix}{s}"))
+        .unwrap_or_default()
+}
+
+fn components(url: &Url) -> Option<Components> {
+    match url {
+        Url::Web(url) => Some(Components {
+            username: url.username.clone(),
+            password: url.password.clone(),
+            hostname: url.host.to_string(),
+            port: if Some(url.port) == default_port(&url.scheme.to_string()) {
+                String::new()
+            } else {
+                url.port.to_string()
+            },
+            pathname: url.path.clone(),
+            search: prefixed('?', &url.query),
+            hash: prefixed('#', &url.fragment),
+        }),
+        Url::File(url) => Some(Components {
+            username: String::new(),
+            password: String::new(),
+            hostname: url
+                .host
+                .as_ref()
+                .map(ToString::to_string)
+                .unwrap_or_default(),
+            port: String::new(),
+            pathname: url.path.clone(),
+            search: prefixed('?', &url.query),
+            hash: prefixed('#', &url.fragment),
+        }),
+        _ => None,
+    }
+}
+
+fn default_port(scheme: &str) -> Option<u16> {
+    match scheme {
+        "http" => Some(80),
+        "https" => Some(443),
+        _ => None,
+    }
+}
+
+/// The scheme (with its colon) that `input` starts with, lowercased, going by
+/// how the URL parser reads it: surrounding spaces and controls are trimmed,
+/// and tabs and newlines are ignored.
+fn input_protocol(input: &str) -> Option<String> {
+    let input = input
+        .trim_matches(|c: char| c <= ' ')
+        .replace(['\t', '\n', '\r'], "");
+    let (scheme, _) = input.split_once(':')?;
+    let mut chars = scheme.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c));
+    valid.then(|| format!("{}:", scheme.to_ascii_lowercase()))
+}
+
+fn expected_components(case: &Value) -> Components {
+    let get = |key: &str| case[key].as_str().unwrap_or_default().to_string();
+    Components {
+        username: get("username"),
+        password: get("password"),
+        hostname: get("hostname"),
+        port: get("port"),
+        pathname: get("pathname"),
+        search: get("search"),
+        hash: get("hash"),
+    }
+}
+
+#[test]
+fn urltestdata() -> anyhow::Result<()> {
+    let cases = serde_json::from_str::<Vec<Value>>(FIXTURE)?;
+    let mut failures = vec![];
+    let mut num_run = 0;
+    let mut num_skipped = 0;
+
+    // Strings in the fixture are comments.
+    for case in cases.iter().filter(|case| case.is_object()) {
+        let input = case["input"].as_str().unwrap_or_default();
+
+        let base = case["base"].as_str().map(str::parse::<Url>);
+        let parsed = match &base {
+            None => input.parse::<Url>(),
+            Some(Ok(Url::Web(base))) => base.join(input),
+            Some(Ok(Url::File(base))) => base.join(input),
+            // The base has a scheme we don't support, or is one we can't resolve against.
+            Some(_) => {
+                num_skipped += 1;
+                continue;
+            }
+        };
+        if case["failure"].as_bool() == Some(true) {
+            // Only failures for schemes we support count: any other scheme fails
+            // for not being supported, whatever the rest of the input is.
+            let protocol = input_protocol(input).or_else(|| match &base {
+                Some(Ok(Url::Web(base))) => Some(format!("{}:", base.scheme)),
+                Some(Ok(Url::File(base))) => Some(format!("{}:", base.scheme)),
+                _ => None,
+            });
+            if !protocol.is_some_and(|protocol| SUPPORTED_PROTOCOLS.contains(&protocol.as_str())) {
+                num_skipped += 1;
+                continue;
+            }
+            num_run += 1;
+            if let Ok(url) = parsed {
+                failures.push(format!("{input:?}: expected failure, got {url:?}"));
+            }
+            continue;
+        }
+
+        let protocol = case["protocol"].as_str().unwrap_or_default();
+        if !SUPPORTED_PROTOCOLS.contains(&protocol) {
+            num_skipped += 1;
+            continue;
+        }
+
+        num_run += 1;
+        let expected = expected_components(case);
+        match parsed {
+            Ok(url) => {
+                let components = components(&url);
+                if components.as_ref() != Some(&expected) {
+                    failures.push(format!(
+                        "{input:?}: expected {expected:?}, got {components:?}"
+                    ));
+                }
+                let href = url.to_string();
+                if href != case["href"].as_str().unwrap_or_default() {
+                    failures.push(format!("{input:?}: expected {}, got {href}", case["href"]));
+                }
+                if href.parse::<Url>().ok().as_ref() != Some(&url) {
+                    failures.push(format!("{input:?}: {href} doesn't parse back into {url:?}"));
+                }
+            }
+            Err(error) => failures.push(format!("{input:?}: expected {expected:?}, got {error}")),
+        }
+    }
+
+    println!("Ran {num_run} cases, and skipped {num_skipped}.");
+    assert!(
+        num_run >= MIN_RUN,
+        "Only {num_run} cases ran (and {num_skipped} were skipped), instead of at least {MIN_RUN}"
+    );
+    assert!(
+        failures.is_empty(),
+        "{} of {num_run} cases failed:\n{}",
+        failures.len(),
+        failures.join("\n")
+    );
+    Ok(())
+}